@@ -0,0 +1,21 @@
+#![no_main]
+
+use harkive::midi::BinBuild;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // first byte picks the mode, as the DataStream kind does on the device
+    let Some((mode, bytes)) = data.split_first() else {
+        return;
+    };
+    let mut build = BinBuild::new();
+    build.start(mode & 1 != 0);
+    for byte in bytes {
+        if *byte == 0xFF {
+            let _ = build.set_encoded(mode & 2 != 0);
+        } else {
+            build.add(*byte & 0x7F);
+        }
+    }
+    build.flush();
+});
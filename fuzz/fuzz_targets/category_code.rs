@@ -0,0 +1,10 @@
+#![no_main]
+
+use harkive::continuum_preset::{category_list, is_empty_preset_name, HCCategoryCode};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|text: &str| {
+    let _ = category_list(text);
+    let _ = HCCategoryCode::new().decode(text);
+    let _ = is_empty_preset_name(text);
+});
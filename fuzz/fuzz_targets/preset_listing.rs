@@ -0,0 +1,11 @@
+#![no_main]
+
+use harkive::preset_listing::{parse_preset_listing, ListingMode};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|text: &str| {
+    for mode in [ListingMode::Strict, ListingMode::Lenient] {
+        let mut presets = Vec::new();
+        let _ = parse_preset_listing(text, "fuzz", mode, &mut presets);
+    }
+});
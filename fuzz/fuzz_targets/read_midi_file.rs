@@ -0,0 +1,9 @@
+#![no_main]
+
+use harkive::read_midi_file::ReadMidiFile;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = ReadMidiFile::new(data);
+    while let Ok(Some(_)) = reader.next() {}
+});
@@ -0,0 +1,24 @@
+#![no_main]
+
+use harkive::midi::VariableLengthValue;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // decode values back to back, as a track parser does
+    let mut decoder = VariableLengthValue::new();
+    decoder.start();
+    for byte in data {
+        if !decoder.add_byte(*byte) {
+            let value = decoder.finish().unwrap();
+            // anything in range encodes back to what decodes to the same value
+            if let Ok(bytes) = VariableLengthValue::encode(value) {
+                let mut check = VariableLengthValue::new();
+                for byte in &bytes {
+                    check.add_byte(*byte);
+                }
+                assert_eq!(check.finish().unwrap(), value);
+            }
+        }
+    }
+    let _ = decoder.finish();
+});
@@ -0,0 +1,254 @@
+use std::{
+    cell::RefCell,
+    ffi::CString,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::{Error, Result},
+    midi_message::{MidiMessage, TimedMessage},
+    transport::{Direction, MidiInput, MidiOutput, MidiTransport, PortInfo},
+};
+use alsa::seq::{
+    Addr, ClientIter, EventType, MidiEvent, PortCap, PortIter, PortSubscribe, PortType,
+};
+use alsa::Seq;
+
+const CLIENT_NAME: &str = "harkive";
+// Largest message we expect to decode in one piece (sysex is rare on Haken devices).
+const DECODE_BUFFER_SIZE: usize = 1024;
+
+fn open_seq(direction: alsa::Direction, nonblock: bool) -> Result<Seq> {
+    let seq = Seq::open(None, Some(direction), nonblock)?;
+    if let Ok(name) = CString::new(CLIENT_NAME) {
+        seq.set_client_name(&name)?;
+    }
+    Ok(seq)
+}
+
+fn parse_addr(id: &str) -> Option<Addr> {
+    id.parse::<Addr>().ok()
+}
+
+/// ALSA sequencer transport.
+///
+/// Port ids are sequencer addresses in "client:port" form, as shown by `aconnect -l`.
+pub struct AlsaTransport {
+    seq: Seq,
+}
+
+impl AlsaTransport {
+    pub fn new() -> Result<Self> {
+        match Seq::open(None, None, false) {
+            Ok(seq) => Ok(Self { seq }),
+            Err(error) => Err(Error::Midi(format!(
+                "Unable to open the ALSA sequencer: {error}"
+            ))),
+        }
+    }
+}
+
+impl MidiTransport for AlsaTransport {
+    fn ports(&self, direction: Direction) -> Vec<PortInfo> {
+        // A device "in" port is one we can read from, and so on.
+        let caps = match direction {
+            Direction::In => PortCap::READ | PortCap::SUBS_READ,
+            Direction::Out => PortCap::WRITE | PortCap::SUBS_WRITE,
+        };
+        let mut result = Vec::new();
+        for client in ClientIter::new(&self.seq) {
+            for port in PortIter::new(&self.seq, client.get_client()) {
+                if !port.get_capability().contains(caps)
+                    || port.get_capability().contains(PortCap::NO_EXPORT)
+                    || !port.get_type().contains(PortType::MIDI_GENERIC)
+                {
+                    continue;
+                }
+                if let Ok(name) = port.get_name() {
+                    let addr = port.addr();
+                    result.push(PortInfo {
+                        direction,
+                        name: name.to_string(),
+                        id: format!("{}:{}", addr.client, addr.port),
+                    });
+                }
+            }
+        }
+        result
+    }
+
+    fn open_input(&self, info: &PortInfo) -> Option<Box<dyn MidiInput>> {
+        debug_assert!(info.direction == Direction::In);
+        match AlsaInput::open(info) {
+            Ok(input) => Some(Box::new(input)),
+            Err(error) => {
+                println!("Error opening Midi in port '{}': {}", info.name, error);
+                None
+            }
+        }
+    }
+
+    fn open_output(&self, info: &PortInfo) -> Option<Box<dyn MidiOutput>> {
+        debug_assert!(info.direction == Direction::Out);
+        match AlsaOutput::open(info) {
+            Ok(output) => Some(Box::new(output)),
+            Err(error) => {
+                println!("Error opening Midi out port '{}': {}", info.name, error);
+                None
+            }
+        }
+    }
+}
+
+pub struct AlsaInput {
+    seq: Option<Seq>,
+    stop: Arc<AtomicBool>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl AlsaInput {
+    fn open(info: &PortInfo) -> Result<Self> {
+        let device = parse_addr(&info.id).ok_or_else(|| Error::Midi("Bad ALSA port address".to_string()))?;
+        let seq = open_seq(alsa::Direction::Capture, true)?;
+        let name = CString::new("harkive in").unwrap_or_default();
+        let port = seq.create_simple_port(
+            &name,
+            PortCap::WRITE | PortCap::SUBS_WRITE,
+            PortType::MIDI_GENERIC | PortType::APPLICATION,
+        )?;
+        let subscription = PortSubscribe::empty()?;
+        subscription.set_sender(device);
+        subscription.set_dest(Addr {
+            client: seq.client_id()?,
+            port,
+        });
+        seq.subscribe_port(&subscription)?;
+        Ok(Self {
+            seq: Some(seq),
+            stop: Arc::new(AtomicBool::new(false)),
+            reader: None,
+        })
+    }
+
+    fn read_events(seq: Seq, stop: Arc<AtomicBool>, tx: Sender<TimedMessage>) {
+        let decoder = match MidiEvent::new(0) {
+            Ok(decoder) => decoder,
+            Err(error) => {
+                println!("Unable to create ALSA MIDI decoder: {error}");
+                return;
+            }
+        };
+        decoder.enable_running_status(false);
+        let start = Instant::now();
+        let mut buffer = vec![0u8; DECODE_BUFFER_SIZE];
+        let mut input = seq.input();
+        while !stop.load(Ordering::Relaxed) {
+            match input.event_input() {
+                Ok(mut event) => {
+                    if matches!(
+                        event.get_type(),
+                        EventType::PortSubscribed | EventType::PortUnsubscribed
+                    ) {
+                        continue;
+                    }
+                    if let Ok(length) = decoder.decode(&mut buffer, &mut event) {
+                        if let Some(message) = MidiMessage::from_bytes(&buffer[..length]) {
+                            let timed = TimedMessage {
+                                ticks: (start.elapsed().as_nanos() / 100) as i64,
+                                message,
+                            };
+                            if tx.send(timed).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                }
+                // nonblocking: EAGAIN when nothing is pending
+                Err(_) => std::thread::sleep(Duration::from_millis(1)),
+            }
+        }
+    }
+}
+
+impl MidiInput for AlsaInput {
+    fn listen(&mut self, tx: Sender<TimedMessage>) -> Result<()> {
+        if let Some(seq) = self.seq.take() {
+            let stop = self.stop.clone();
+            self.reader = Some(std::thread::spawn(move || {
+                Self::read_events(seq, stop, tx)
+            }));
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(reader) = self.reader.take() {
+            _ = reader.join();
+        }
+        self.seq = None;
+    }
+}
+
+pub struct AlsaOutput {
+    seq: Seq,
+    port: i32,
+    encoder: RefCell<MidiEvent>,
+}
+
+impl AlsaOutput {
+    fn open(info: &PortInfo) -> Result<Self> {
+        let device = parse_addr(&info.id).ok_or_else(|| Error::Midi("Bad ALSA port address".to_string()))?;
+        let seq = open_seq(alsa::Direction::Playback, false)?;
+        let name = CString::new("harkive out").unwrap_or_default();
+        let port = seq.create_simple_port(
+            &name,
+            PortCap::READ | PortCap::SUBS_READ,
+            PortType::MIDI_GENERIC | PortType::APPLICATION,
+        )?;
+        let subscription = PortSubscribe::empty()?;
+        subscription.set_sender(Addr {
+            client: seq.client_id()?,
+            port,
+        });
+        subscription.set_dest(device);
+        seq.subscribe_port(&subscription)?;
+        Ok(Self {
+            seq,
+            port,
+            encoder: RefCell::new(MidiEvent::new(DECODE_BUFFER_SIZE as u32)?),
+        })
+    }
+}
+
+impl MidiOutput for AlsaOutput {
+    fn send(&self, message: &MidiMessage) -> Result<()> {
+        let data = message.to_bytes();
+        let mut encoder = self.encoder.borrow_mut();
+        let mut rest = &data[..];
+        while !rest.is_empty() {
+            let (used, event) = encoder.encode(rest)?;
+            if let Some(mut event) = event {
+                event.set_source(self.port);
+                event.set_subs();
+                event.set_direct();
+                self.seq.event_output_direct(&mut event)?;
+            }
+            if used == 0 {
+                break;
+            }
+            rest = &rest[used..];
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        _ = self.seq.drain_output();
+    }
+}
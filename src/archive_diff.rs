@@ -0,0 +1,231 @@
+use crate::{
+    continuum_preset::HCCategoryCode,
+    data_kind::DataKind,
+    error::{Error, Result},
+    midi_message::MidiMessage,
+    midi_traits::Named,
+    options::Options,
+    preset_archive::PresetArchive,
+    stream_decode::{DataStream, StreamData},
+};
+use std::path::Path;
+
+// differing values listed per stream or message list before summarizing
+const MAX_LISTED: usize = 8;
+
+/// Exit code when the archives differ, after the error codes of `Error::exit_code`.
+pub const DIFFERENT_EXIT_CODE: u8 = 10;
+
+fn describe_message(message: &MidiMessage) -> String {
+    match message {
+        MidiMessage::ControlChange { channel, cc, value } => {
+            format!("ch{} cc{cc}={value}", channel + 1)
+        }
+        MidiMessage::ProgramChange { channel, program } => {
+            format!("ch{} program {program}", channel + 1)
+        }
+        MidiMessage::ChannelPressure { channel, pressure } => {
+            format!("ch{} pressure {pressure}", channel + 1)
+        }
+        _ => format!("{message:?}"),
+    }
+}
+
+fn compare_text(label: &str, a: &str, b: &str, changes: &mut Vec<String>) {
+    if a != b {
+        changes.push(format!("{label}: \"{a}\" -> \"{b}\""));
+    }
+}
+
+/// As `compare_text`, for values that are already quoted where they need it.
+fn compare_values(label: &str, a: &str, b: &str, changes: &mut Vec<String>) {
+    let show = |value: &str| match value {
+        "" => "(none)".to_string(),
+        value => value.to_string(),
+    };
+    if a != b {
+        changes.push(format!("{label}: {} -> {}", show(a), show(b)));
+    }
+}
+
+/// List up to MAX_LISTED differing positions of two sequences.
+fn compare_items<T: PartialEq>(
+    label: &str,
+    a: &[T],
+    b: &[T],
+    show: impl Fn(&T) -> String,
+    changes: &mut Vec<String>,
+) {
+    if a == b {
+        return;
+    }
+    if a.len() != b.len() {
+        changes.push(format!("{label}: {} -> {} items", a.len(), b.len()));
+    }
+    let differing: Vec<usize> = (0..a.len().max(b.len()))
+        .filter(|index| a.get(*index) != b.get(*index))
+        .collect();
+    for index in differing.iter().take(MAX_LISTED) {
+        let a = a.get(*index).map_or("(none)".to_string(), &show);
+        let b = b.get(*index).map_or("(none)".to_string(), &show);
+        changes.push(format!("{label} [{index}]: {a} -> {b}"));
+    }
+    if differing.len() > MAX_LISTED {
+        changes.push(format!(
+            "{label}: {} more differences",
+            differing.len() - MAX_LISTED
+        ));
+    }
+}
+
+fn compare_stream(label: &str, a: &DataStream, b: &DataStream, changes: &mut Vec<String>) {
+    let (a, b) = (&a.data, &b.data);
+    if let (Some(a), Some(b)) = (a.text(), b.text()) {
+        return compare_text(label, a, b, changes);
+    }
+    match (a, b) {
+        (StreamData::Bytes(a), StreamData::Bytes(b)) => {
+            compare_items(label, a, b, |byte| format!("{byte:02X}"), changes)
+        }
+        _ => changes.push(format!("{label}: decoded differently")),
+    }
+}
+
+fn streams_of(archive: &PresetArchive, kind: DataKind) -> Vec<&DataStream> {
+    archive
+        .streams
+        .iter()
+        .filter(|stream| stream.kind == kind)
+        .collect()
+}
+
+fn compare_streams(a: &PresetArchive, b: &PresetArchive, changes: &mut Vec<String>) {
+    let mut kinds: Vec<DataKind> = Vec::new();
+    for stream in a.streams.iter().chain(b.streams.iter()) {
+        // name, text and category are compared on their own
+        if !matches!(
+            stream.kind,
+            DataKind::Name | DataKind::ControlText | DataKind::Category
+        ) && !kinds.contains(&stream.kind)
+        {
+            kinds.push(stream.kind);
+        }
+    }
+    for kind in kinds {
+        let a = streams_of(a, kind);
+        let b = streams_of(b, kind);
+        for index in 0..a.len().max(b.len()) {
+            let label = if a.len().max(b.len()) > 1 {
+                format!("{} stream {}", kind.name(), index + 1)
+            } else {
+                format!("{} stream", kind.name())
+            };
+            match (a.get(index), b.get(index)) {
+                (Some(a), Some(b)) => compare_stream(&label, a, b, changes),
+                (Some(a), None) => changes.push(format!("{label}: removed ({} bytes)", a.size)),
+                (None, Some(b)) => changes.push(format!("{label}: added ({} bytes)", b.size)),
+                (None, None) => {}
+            }
+        }
+    }
+}
+
+/// What changed from archive `a` to archive `b`, ignoring timing and file encoding.
+pub fn diff_archives(a: &PresetArchive, b: &PresetArchive, catcode: &HCCategoryCode) -> Vec<String> {
+    let mut changes = Vec::new();
+    compare_text("Name", &a.name(), &b.name(), &mut changes);
+    compare_text("Text", &a.text(), &b.text(), &mut changes);
+    let categories = |archive: &PresetArchive| catcode.decode(&archive.text()).unwrap_or_default();
+    compare_values("Categories", &categories(a), &categories(b), &mut changes);
+    compare_text("Category", &a.category(), &b.category(), &mut changes);
+    compare_streams(a, b, &mut changes);
+    compare_items(
+        "Matrix",
+        &a.control_messages(),
+        &b.control_messages(),
+        describe_message,
+        &mut changes,
+    );
+    changes
+}
+
+fn archive_path(path: Option<&Path>) -> Result<&Path> {
+    path.ok_or_else(|| Error::Usage("--diff needs two preset files".to_string()))
+}
+
+/// Compare two preset archives and print what differs. Ok(true) when they differ.
+pub fn diff(options: &Options, catcode: &HCCategoryCode) -> Result<bool> {
+    let path_a = options.get_path();
+    let path_a = archive_path(path_a.as_deref())?;
+    let path_b = archive_path(options.other_path.as_deref())?;
+    let a = PresetArchive::read(path_a)?;
+    let b = PresetArchive::read(path_b)?;
+    let changes = diff_archives(&a, &b, catcode);
+    if changes.is_empty() {
+        println!("No differences");
+        return Ok(false);
+    }
+    println!("--- {}", path_a.display());
+    println!("+++ {}", path_b.display());
+    for change in changes.iter() {
+        println!("{change}");
+    }
+    println!(
+        "{} difference{}",
+        changes.len(),
+        if changes.len() == 1 { "" } else { "s" }
+    );
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        haken_midi::cc16,
+        midi::CHANNEL15,
+        stream_decode::stream_messages,
+    };
+
+    fn archive(name: &str, graph: &[u8], matrix: &[u8]) -> PresetArchive {
+        let mut messages = stream_messages(cc16::DataStream_Name, name.as_bytes());
+        messages.extend(stream_messages(cc16::DataStream_Text, b"C=ST_BA"));
+        messages.extend(stream_messages(cc16::DataStream_Graph, graph));
+        for (cc, value) in matrix.iter().enumerate() {
+            messages.push(MidiMessage::cc(CHANNEL15, cc as u8 + 1, *value));
+        }
+        PresetArchive::from_messages(messages)
+    }
+
+    #[test]
+    fn same_content_has_no_differences() {
+        let a = archive("Bass", &[1, 2, 3], &[10, 20]);
+        let b = archive("Bass", &[1, 2, 3], &[10, 20]);
+        assert!(diff_archives(&a, &b, &HCCategoryCode::new()).is_empty());
+    }
+
+    #[test]
+    fn reports_what_changed() {
+        let a = archive("Bass", &[1, 2, 3, 4], &[10, 20]);
+        let b = archive("Bass 2", &[1, 5, 3, 4], &[10, 21]);
+        assert_eq!(
+            diff_archives(&a, &b, &HCCategoryCode::new()),
+            vec![
+                "Name: \"Bass\" -> \"Bass 2\"".to_string(),
+                "Graph stream [1]: 02 -> 05".to_string(),
+                "Matrix [1]: ch15 cc2=20 -> ch15 cc2=21".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn categories_are_shown_as_decoded() {
+        let with_text = |text: &[u8]| {
+            let mut messages = stream_messages(cc16::DataStream_Name, b"Bass");
+            messages.extend(stream_messages(cc16::DataStream_Text, text));
+            PresetArchive::from_messages(messages)
+        };
+        let changes = diff_archives(&with_text(b"C=ST_BA"), &with_text(b"C=WI"), &HCCategoryCode::new());
+        assert_eq!(changes[1], "Categories: {category:\"Strings\", type:[\"Bass\"]} -> {category:\"Winds\"}");
+    }
+}
@@ -138,7 +138,7 @@ impl CcText {
     pub fn get(&self, cc:u8, key:u8) -> Option<String> {
         match cc {
             109 => {
-                return match key {
+                match key {
                     80..=87 => Some(format!("[Download tuning grid {}]", 1 + key - 80)),
                     107..=114 => Some(format!("[Demo assortment to group {}]", 1 + key - 107)),
                     115..=122 =>  Some(format!("[Erase group {}]", 1 + key - 115)),
@@ -146,11 +146,11 @@ impl CcText {
                 }
             }
             110 => {
-                return match key {
+                match key {
                     1..=99 => Some(format!("{key}%")),
                     101..=116 => Some(format!("[Save preset {}]", 1 + key - 101)),
                     _=> self.data_110.get(&key).cloned(),
-                };
+                }
             }
             _=> None,
         }
@@ -1,6 +1,7 @@
+use crate::error::{Error, Result};
 use crate::midi_traits::*;
 use crate::util::short_hash;
-use std::collections::HashMap;
+use std::{collections::HashMap, path::Path};
 
 #[repr(u8)]
 #[derive(Clone, Copy, Default, PartialEq)]
@@ -12,12 +13,7 @@ pub enum NofN {
 }
 
 pub fn is_empty_preset_name(name: &str) -> bool {
-    match name.len() {
-        0..=4 => false,
-        5 => name == "Empty",
-        6.. => &(name[0..6]) == "Empty.",
-        _ => false,
-    }
+    name == "Empty" || name.starts_with("Empty.")
 }
 
 pub fn make_preset_filename(preset: &str, data: &[u8]) -> String {
@@ -98,11 +94,84 @@ impl Named for PresetGroup {
     }
 }
 
+#[derive(Clone)]
 pub struct PresetMeta {
-    pub code: &'static str,
+    pub code: String,
     pub group: PresetGroup,
     pub index: u8,
-    pub name: &'static str,
+    pub name: String,
+}
+
+fn table_group(line: &str) -> Option<PresetGroup> {
+    let name = line
+        .trim_end_matches(':')
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_lowercase();
+    match name.as_str() {
+        "category" | "categories" => Some(PresetGroup::Category),
+        "type" | "types" => Some(PresetGroup::Type),
+        "character" | "characters" => Some(PresetGroup::Character),
+        "matrix" | "matrices" => Some(PresetGroup::Matrix),
+        "setting" | "settings" => Some(PresetGroup::Setting),
+        _ => None,
+    }
+}
+
+/// Parse a category table: a line for each code, "ST Strings", after the name of its group
+/// on a line of its own. Categories are numbered from 1, as their banks are, and the other
+/// groups from 0. A code `known` has is read in its own group and place when no group name
+/// comes before it.
+fn parse_category_table(text: &str, known: &HCCategoryCode) -> Result<Vec<PresetMeta>> {
+    let mut table: Vec<PresetMeta> = Vec::new();
+    let mut group = PresetGroup::Unknown;
+    let mut index = 0;
+    for (number, line) in text.lines().enumerate() {
+        let error = |message: &str| Error::FileFormat(format!("line {}: {message}", number + 1));
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+            continue;
+        }
+        if let Some(next) = table_group(line) {
+            group = next;
+            index = if group == PresetGroup::Category { 1 } else { 0 };
+            continue;
+        }
+        let Some((code, name)) = line.split_once(|ch: char| ch.is_whitespace() || ch == '=' || ch == ',') else {
+            return Err(error(&format!("expected a code and a name, found '{line}'")));
+        };
+        let name = name.trim_start_matches(|ch: char| ch.is_whitespace() || ch == '=' || ch == ',');
+        if code.len() != 2 || !code.chars().all(|ch| ch.is_ascii_uppercase() || ch.is_ascii_digit()) {
+            return Err(error(&format!("'{code}' is not a two letter code")));
+        }
+        if name.is_empty() {
+            return Err(error(&format!("'{code}' has no name")));
+        }
+        if table.iter().any(|meta| meta.code == code) {
+            return Err(error(&format!("'{code}' is listed twice")));
+        }
+        if group == PresetGroup::Unknown {
+            let Some(meta) = known.get_by_code(code) else {
+                return Err(error(&format!("'{code}' is new, so needs a group name before it")));
+            };
+            table.push(PresetMeta {
+                name: name.to_string(),
+                ..meta.clone()
+            });
+            continue;
+        }
+        table.push(PresetMeta {
+            code: code.to_string(),
+            group,
+            index,
+            name: name.to_string(),
+        });
+        index = index.saturating_add(1);
+    }
+    if table.is_empty() {
+        return Err(Error::FileFormat("No codes found".to_string()));
+    }
+    Ok(table)
 }
 
 pub fn category_list(text: &str) -> Vec<String> {
@@ -117,6 +186,7 @@ pub fn category_list(text: &str) -> Vec<String> {
     result
 }
 
+#[derive(Clone)]
 pub struct HCCategoryCode {
     data: HashMap<String, PresetMeta>,
 }
@@ -138,44 +208,72 @@ impl HCCategoryCode {
 
         let mut group = PresetGroup::Unknown;
         let mut first = true;
+        let mut unknown = Vec::new();
         for code in category_list(text) {
-            if let Some(meta) = self.data.get(&code.to_string()) {
-                if group != meta.group {
-                    match group {
-                        PresetGroup::Category => {
-                            result += ", ";
-                        }
-                        PresetGroup::Unknown => {}
-                        _ => {
-                            result += "], ";
-                        }
-                    };
-                    first = true;
-                    result += meta.group.name();
-                    if meta.group == PresetGroup::Category {
-                        result.push(':')
-                    } else {
-                        result += ":[";
-                    }
-                    group = meta.group;
+            let Some(meta) = self.data.get(&code) else {
+                if !code.is_empty() {
+                    unknown.push(format!("\"{code}\""));
                 }
-                if first {
-                    first = false;
+                continue;
+            };
+            if group != meta.group {
+                match group {
+                    PresetGroup::Category => {
+                        result += ", ";
+                    }
+                    PresetGroup::Unknown => {}
+                    _ => {
+                        result += "], ";
+                    }
+                };
+                first = true;
+                result += meta.group.name();
+                if meta.group == PresetGroup::Category {
+                    result.push(':')
                 } else {
-                    result += ", ";
+                    result += ":[";
                 }
-                result.push('"');
-                result += meta.name;
-                result.push('"');
+                group = meta.group;
+            }
+            if first {
+                first = false;
+            } else {
+                result += ", ";
             }
+            result.push('"');
+            result += &meta.name;
+            result.push('"');
         }
 
-        result += "]}";
+        if !matches!(group, PresetGroup::Unknown | PresetGroup::Category) {
+            result.push(']');
+        }
+        // codes this table doesn't know, likely from newer firmware
+        if !unknown.is_empty() {
+            if group != PresetGroup::Unknown {
+                result += ", ";
+            }
+            result += &format!("unknown:[{}]", unknown.join(", "));
+        }
+        result.push('}');
         Some(result)
     }
 
     fn add(&mut self, item: PresetMeta) {
-        self.data.insert(item.code.to_string(), item);
+        self.data.insert(item.code.clone(), item);
+    }
+
+    /// Add `item` in place of what the table has for its code. A category also takes over
+    /// its bank number: the category that had it keeps its name, but no bank (index 0).
+    fn merge(&mut self, item: PresetMeta) {
+        if item.group == PresetGroup::Category {
+            for meta in self.data.values_mut() {
+                if meta.group == PresetGroup::Category && meta.index == item.index {
+                    meta.index = 0;
+                }
+            }
+        }
+        self.add(item);
     }
 
     pub fn get_by_category_name(&self, name: &str) -> Option<&PresetMeta> {
@@ -188,562 +286,597 @@ impl HCCategoryCode {
             .values()
             .find(|v| v.group == PresetGroup::Category && v.code == code)
     }
+    /// Any group's entry for `code`: "ST" the category, "BR" the character, and so on.
+    pub fn get_by_code(&self, code: &str) -> Option<&PresetMeta> {
+        self.data.get(code)
+    }
+    pub fn get_by_category_index(&self, index: u8) -> Option<&PresetMeta> {
+        self.data
+            .values()
+            .find(|v| v.group == PresetGroup::Category && v.index == index)
+    }
+
+    /// Name of a system preset bank: cat-code banks are numbered by category.
+    pub fn bank_name(&self, bank_hi: u8) -> String {
+        match bank_hi {
+            127 => "System".to_string(),
+            _ => match self.get_by_category_index(bank_hi) {
+                Some(meta) => meta.name.to_string(),
+                None => format!("Bank {bank_hi}"),
+            },
+        }
+    }
+
+    /// The built-in table with the codes in a file (see `parse_category_table`) over it,
+    /// so codes added by newer firmware are known and codes the file leaves out still are.
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| Error::Io(format!("{}: {error}", path.display())))?;
+        let mut result = Self::new();
+        let table = parse_category_table(&text, &result)
+            .map_err(|error| error.context(&path.display().to_string()))?;
+        for meta in table {
+            result.merge(meta);
+        }
+        Ok(result)
+    }
 
+    /// The built-in table, as the Haken Editor knows the codes.
     pub fn new() -> Self {
         let mut result = HCCategoryCode {
             data: HashMap::new(),
         };
         result.add(PresetMeta {
-            code: "ST",
+            code: "ST".into(),
             group: PresetGroup::Category,
             index: 1,
-            name: "Strings",
+            name: "Strings".into(),
         });
         result.add(PresetMeta {
-            code: "WI",
+            code: "WI".into(),
             group: PresetGroup::Category,
             index: 2,
-            name: "Winds",
+            name: "Winds".into(),
         });
         result.add(PresetMeta {
-            code: "VO",
+            code: "VO".into(),
             group: PresetGroup::Category,
             index: 3,
-            name: "Vocal",
+            name: "Vocal".into(),
         });
         result.add(PresetMeta {
-            code: "KY",
+            code: "KY".into(),
             group: PresetGroup::Category,
             index: 4,
-            name: "Keyboard",
+            name: "Keyboard".into(),
         });
         result.add(PresetMeta {
-            code: "CL",
+            code: "CL".into(),
             group: PresetGroup::Category,
             index: 5,
-            name: "Classic",
+            name: "Classic".into(),
         });
         result.add(PresetMeta {
-            code: "OT",
+            code: "OT".into(),
             group: PresetGroup::Category,
             index: 6,
-            name: "Other",
+            name: "Other".into(),
         });
         result.add(PresetMeta {
-            code: "PE",
+            code: "PE".into(),
             group: PresetGroup::Category,
             index: 7,
-            name: "Percussion",
+            name: "Percussion".into(),
         });
         result.add(PresetMeta {
-            code: "PT",
+            code: "PT".into(),
             group: PresetGroup::Category,
             index: 8,
-            name: "Tuned Perc",
+            name: "Tuned Perc".into(),
         });
         result.add(PresetMeta {
-            code: "PR",
+            code: "PR".into(),
             group: PresetGroup::Category,
             index: 9,
-            name: "Processor",
+            name: "Processor".into(),
         });
         result.add(PresetMeta {
-            code: "DO",
+            code: "DO".into(),
             group: PresetGroup::Category,
             index: 10,
-            name: "Drone",
+            name: "Drone".into(),
         });
         result.add(PresetMeta {
-            code: "MD",
+            code: "MD".into(),
             group: PresetGroup::Category,
             index: 11,
-            name: "Midi",
+            name: "Midi".into(),
         });
         result.add(PresetMeta {
-            code: "CV",
+            code: "CV".into(),
             group: PresetGroup::Category,
             index: 12,
-            name: "Control Voltage",
+            name: "Control Voltage".into(),
         });
         result.add(PresetMeta {
-            code: "UT",
+            code: "UT".into(),
             group: PresetGroup::Category,
             index: 13,
-            name: "Utility",
+            name: "Utility".into(),
         });
         result.add(PresetMeta {
-            code: "AT",
+            code: "AT".into(),
             group: PresetGroup::Type,
             index: 0,
-            name: "Atonal",
+            name: "Atonal".into(),
         });
         result.add(PresetMeta {
-            code: "BA",
+            code: "BA".into(),
             group: PresetGroup::Type,
             index: 1,
-            name: "Bass",
+            name: "Bass".into(),
         });
         result.add(PresetMeta {
-            code: "BO",
+            code: "BO".into(),
             group: PresetGroup::Type,
             index: 2,
-            name: "Bowed",
+            name: "Bowed".into(),
         });
         result.add(PresetMeta {
-            code: "BR",
+            code: "BR".into(),
             group: PresetGroup::Type,
             index: 3,
-            name: "Brass",
+            name: "Brass".into(),
         });
         result.add(PresetMeta {
-            code: "DP",
+            code: "DP".into(),
             group: PresetGroup::Type,
             index: 4,
-            name: "Demo Preset",
+            name: "Demo Preset".into(),
         });
         result.add(PresetMeta {
-            code: "EP",
+            code: "EP".into(),
             group: PresetGroup::Type,
             index: 5,
-            name: "Elec Piano",
+            name: "Elec Piano".into(),
         });
         result.add(PresetMeta {
-            code: "FL",
+            code: "FL".into(),
             group: PresetGroup::Type,
             index: 6,
-            name: "Flute",
+            name: "Flute".into(),
         });
         result.add(PresetMeta {
-            code: "LE",
+            code: "LE".into(),
             group: PresetGroup::Type,
             index: 7,
-            name: "Lead",
+            name: "Lead".into(),
         });
         result.add(PresetMeta {
-            code: "OR",
+            code: "OR".into(),
             group: PresetGroup::Type,
             index: 8,
-            name: "Organ",
+            name: "Organ".into(),
         });
         result.add(PresetMeta {
-            code: "PA",
+            code: "PA".into(),
             group: PresetGroup::Type,
             index: 9,
-            name: "Pad",
+            name: "Pad".into(),
         });
         result.add(PresetMeta {
-            code: "PL",
+            code: "PL".into(),
             group: PresetGroup::Type,
             index: 10,
-            name: "Plucked",
+            name: "Plucked".into(),
         });
         result.add(PresetMeta {
-            code: "RD",
+            code: "RD".into(),
             group: PresetGroup::Type,
             index: 11,
-            name: "Double Reed",
+            name: "Double Reed".into(),
         });
         result.add(PresetMeta {
-            code: "RS",
+            code: "RS".into(),
             group: PresetGroup::Type,
             index: 12,
-            name: "Single Reed",
+            name: "Single Reed".into(),
         });
         result.add(PresetMeta {
-            code: "SU",
+            code: "SU".into(),
             group: PresetGroup::Type,
             index: 13,
-            name: "Struck",
+            name: "Struck".into(),
         });
         result.add(PresetMeta {
-            code: "AC",
+            code: "AC".into(),
             group: PresetGroup::Character,
             index: 0,
-            name: "Acoustic",
+            name: "Acoustic".into(),
         });
         result.add(PresetMeta {
-            code: "AG",
+            code: "AG".into(),
             group: PresetGroup::Character,
             index: 1,
-            name: "Aggressive",
+            name: "Aggressive".into(),
         });
         result.add(PresetMeta {
-            code: "AI",
+            code: "AI".into(),
             group: PresetGroup::Character,
             index: 2,
-            name: "Airy",
+            name: "Airy".into(),
         });
         result.add(PresetMeta {
-            code: "AN",
+            code: "AN".into(),
             group: PresetGroup::Character,
             index: 3,
-            name: "Analog",
+            name: "Analog".into(),
         });
         result.add(PresetMeta {
-            code: "AR",
+            code: "AR".into(),
             group: PresetGroup::Character,
             index: 4,
-            name: "Arpeggio",
+            name: "Arpeggio".into(),
         });
         result.add(PresetMeta {
-            code: "BG",
+            code: "BG".into(),
             group: PresetGroup::Character,
             index: 5,
-            name: "Big",
+            name: "Big".into(),
         });
         result.add(PresetMeta {
-            code: "BI",
+            code: "BI".into(),
             group: PresetGroup::Character,
             index: 6,
-            name: "Bright",
+            name: "Bright".into(),
         });
         result.add(PresetMeta {
-            code: "CH",
+            code: "CH".into(),
             group: PresetGroup::Character,
             index: 7,
-            name: "Chords",
+            name: "Chords".into(),
         });
         result.add(PresetMeta {
-            code: "CN",
+            code: "CN".into(),
             group: PresetGroup::Character,
             index: 8,
-            name: "Clean",
+            name: "Clean".into(),
         });
         result.add(PresetMeta {
-            code: "DA",
+            code: "DA".into(),
             group: PresetGroup::Character,
             index: 9,
-            name: "Dark",
+            name: "Dark".into(),
         });
         result.add(PresetMeta {
-            code: "DI",
+            code: "DI".into(),
             group: PresetGroup::Character,
             index: 10,
-            name: "Digital",
+            name: "Digital".into(),
         });
         result.add(PresetMeta {
-            code: "DT",
+            code: "DT".into(),
             group: PresetGroup::Character,
             index: 11,
-            name: "Distorted",
+            name: "Distorted".into(),
         });
         result.add(PresetMeta {
-            code: "DY",
+            code: "DY".into(),
             group: PresetGroup::Character,
             index: 12,
-            name: "Dry",
+            name: "Dry".into(),
         });
         result.add(PresetMeta {
-            code: "EC",
+            code: "EC".into(),
             group: PresetGroup::Character,
             index: 13,
-            name: "Echo",
+            name: "Echo".into(),
         });
         result.add(PresetMeta {
-            code: "EL",
+            code: "EL".into(),
             group: PresetGroup::Character,
             index: 14,
-            name: "Electric",
+            name: "Electric".into(),
         });
         result.add(PresetMeta {
-            code: "EN",
+            code: "EN".into(),
             group: PresetGroup::Character,
             index: 15,
-            name: "Ensemble",
+            name: "Ensemble".into(),
         });
         result.add(PresetMeta {
-            code: "EV",
+            code: "EV".into(),
             group: PresetGroup::Character,
             index: 16,
-            name: "Evolving",
+            name: "Evolving".into(),
         });
         result.add(PresetMeta {
-            code: "FM",
+            code: "FM".into(),
             group: PresetGroup::Character,
             index: 17,
-            name: "FM",
+            name: "FM".into(),
         });
         result.add(PresetMeta {
-            code: "HY",
+            code: "HY".into(),
             group: PresetGroup::Character,
             index: 18,
-            name: "Hybrid",
+            name: "Hybrid".into(),
         });
         result.add(PresetMeta {
-            code: "IC",
+            code: "IC".into(),
             group: PresetGroup::Character,
             index: 19,
-            name: "Icy",
+            name: "Icy".into(),
         });
         result.add(PresetMeta {
-            code: "IN",
+            code: "IN".into(),
             group: PresetGroup::Character,
             index: 20,
-            name: "Intimate",
+            name: "Intimate".into(),
         });
         result.add(PresetMeta {
-            code: "LF",
+            code: "LF".into(),
             group: PresetGroup::Character,
             index: 21,
-            name: "Lo-fi",
+            name: "Lo-fi".into(),
         });
         result.add(PresetMeta {
-            code: "LP",
+            code: "LP".into(),
             group: PresetGroup::Character,
             index: 22,
-            name: "Looping",
+            name: "Looping".into(),
         });
         result.add(PresetMeta {
-            code: "LY",
+            code: "LY".into(),
             group: PresetGroup::Character,
             index: 23,
-            name: "Layered",
+            name: "Layered".into(),
         });
         result.add(PresetMeta {
-            code: "MO",
+            code: "MO".into(),
             group: PresetGroup::Character,
             index: 24,
-            name: "Morphing",
+            name: "Morphing".into(),
         });
         result.add(PresetMeta {
-            code: "MT",
+            code: "MT".into(),
             group: PresetGroup::Character,
             index: 25,
-            name: "Metallic",
+            name: "Metallic".into(),
         });
         result.add(PresetMeta {
-            code: "NA",
+            code: "NA".into(),
             group: PresetGroup::Character,
             index: 26,
-            name: "Nature",
+            name: "Nature".into(),
         });
         result.add(PresetMeta {
-            code: "NO",
+            code: "NO".into(),
             group: PresetGroup::Character,
             index: 27,
-            name: "Noise",
+            name: "Noise".into(),
         });
         result.add(PresetMeta {
-            code: "RN",
+            code: "RN".into(),
             group: PresetGroup::Character,
             index: 28,
-            name: "Random",
+            name: "Random".into(),
         });
         result.add(PresetMeta {
-            code: "RV",
+            code: "RV".into(),
             group: PresetGroup::Character,
             index: 29,
-            name: "Reverberant",
+            name: "Reverberant".into(),
         });
         result.add(PresetMeta {
-            code: "SD",
+            code: "SD".into(),
             group: PresetGroup::Character,
             index: 30,
-            name: "Snd Design",
+            name: "Snd Design".into(),
         });
         result.add(PresetMeta {
-            code: "SE",
+            code: "SE".into(),
             group: PresetGroup::Character,
             index: 31,
-            name: "Stereo",
+            name: "Stereo".into(),
         });
         result.add(PresetMeta {
-            code: "SH",
+            code: "SH".into(),
             group: PresetGroup::Character,
             index: 32,
-            name: "Shaking",
+            name: "Shaking".into(),
         });
         result.add(PresetMeta {
-            code: "SI",
+            code: "SI".into(),
             group: PresetGroup::Character,
             index: 33,
-            name: "Simple",
+            name: "Simple".into(),
         });
         result.add(PresetMeta {
-            code: "SO",
+            code: "SO".into(),
             group: PresetGroup::Character,
             index: 34,
-            name: "Soft",
+            name: "Soft".into(),
         });
         result.add(PresetMeta {
-            code: "SR",
+            code: "SR".into(),
             group: PresetGroup::Character,
             index: 35,
-            name: "Strumming",
+            name: "Strumming".into(),
         });
         result.add(PresetMeta {
-            code: "SY",
+            code: "SY".into(),
             group: PresetGroup::Character,
             index: 36,
-            name: "Synthetic",
+            name: "Synthetic".into(),
         });
         result.add(PresetMeta {
-            code: "WA",
+            code: "WA".into(),
             group: PresetGroup::Character,
             index: 37,
-            name: "Warm",
+            name: "Warm".into(),
         });
         result.add(PresetMeta {
-            code: "WO",
+            code: "WO".into(),
             group: PresetGroup::Character,
             index: 38,
-            name: "Woody",
+            name: "Woody".into(),
         });
         result.add(PresetMeta {
-            code: "AD",
+            code: "AD".into(),
             group: PresetGroup::Matrix,
             index: 0,
-            name: "Additive",
+            name: "Additive".into(),
         });
         result.add(PresetMeta {
-            code: "BB",
+            code: "BB".into(),
             group: PresetGroup::Matrix,
             index: 1,
-            name: "BiqBank",
+            name: "BiqBank".into(),
         });
         result.add(PresetMeta {
-            code: "BH",
+            code: "BH".into(),
             group: PresetGroup::Matrix,
             index: 2,
-            name: "BiqGraph",
+            name: "BiqGraph".into(),
         });
         result.add(PresetMeta {
-            code: "BM",
+            code: "BM".into(),
             group: PresetGroup::Matrix,
             index: 3,
-            name: "BiqMouth",
+            name: "BiqMouth".into(),
         });
         result.add(PresetMeta {
-            code: "CM",
+            code: "CM".into(),
             group: PresetGroup::Matrix,
             index: 4,
-            name: "Cutoff Mod",
+            name: "Cutoff Mod".into(),
         });
         result.add(PresetMeta {
-            code: "DF",
+            code: "DF".into(),
             group: PresetGroup::Matrix,
             index: 5,
-            name: "Formula Delay",
+            name: "Formula Delay".into(),
         });
         result.add(PresetMeta {
-            code: "DM",
+            code: "DM".into(),
             group: PresetGroup::Matrix,
             index: 6,
-            name: "Micro Delay",
+            name: "Micro Delay".into(),
         });
         result.add(PresetMeta {
-            code: "DS",
+            code: "DS".into(),
             group: PresetGroup::Matrix,
             index: 7,
-            name: "Sum Delay",
+            name: "Sum Delay".into(),
         });
         result.add(PresetMeta {
-            code: "DV",
+            code: "DV".into(),
             group: PresetGroup::Matrix,
             index: 8,
-            name: "Voice Delay",
+            name: "Voice Delay".into(),
         });
         result.add(PresetMeta {
-            code: "HM",
+            code: "HM".into(),
             group: PresetGroup::Matrix,
             index: 9,
-            name: "HarMan",
+            name: "HarMan".into(),
         });
         result.add(PresetMeta {
-            code: "KI",
+            code: "KI".into(),
             group: PresetGroup::Matrix,
             index: 10,
-            name: "Kinetic",
+            name: "Kinetic".into(),
         });
         result.add(PresetMeta {
-            code: "MM",
+            code: "MM".into(),
             group: PresetGroup::Matrix,
             index: 11,
-            name: "ModMan",
+            name: "ModMan".into(),
         });
         result.add(PresetMeta {
-            code: "OJ",
+            code: "OJ".into(),
             group: PresetGroup::Matrix,
             index: 12,
-            name: "Osc Jenny",
+            name: "Osc Jenny".into(),
         });
         result.add(PresetMeta {
-            code: "OP",
+            code: "OP".into(),
             group: PresetGroup::Matrix,
             index: 13,
-            name: "Osc Phase",
+            name: "Osc Phase".into(),
         });
         result.add(PresetMeta {
-            code: "OS",
+            code: "OS".into(),
             group: PresetGroup::Matrix,
             index: 14,
-            name: "Osc DSF",
+            name: "Osc DSF".into(),
         });
         result.add(PresetMeta {
-            code: "SB",
+            code: "SB".into(),
             group: PresetGroup::Matrix,
             index: 15,
-            name: "SineBank",
+            name: "SineBank".into(),
         });
         result.add(PresetMeta {
-            code: "SS",
+            code: "SS".into(),
             group: PresetGroup::Matrix,
             index: 16,
-            name: "SineSpray",
+            name: "SineSpray".into(),
         });
         result.add(PresetMeta {
-            code: "WB",
+            code: "WB".into(),
             group: PresetGroup::Matrix,
             index: 17,
-            name: "WaveBank",
+            name: "WaveBank".into(),
         });
         result.add(PresetMeta {
-            code: "C1",
+            code: "C1".into(),
             group: PresetGroup::Setting,
             index: 0,
-            name: "Channel 1",
+            name: "Channel 1".into(),
         });
         result.add(PresetMeta {
-            code: "EM",
+            code: "EM".into(),
             group: PresetGroup::Setting,
             index: 1,
-            name: "Ext Midi Clk",
+            name: "Ext Midi Clk".into(),
         });
         result.add(PresetMeta {
-            code: "MI",
+            code: "MI".into(),
             group: PresetGroup::Setting,
             index: 2,
-            name: "Mono Interval",
+            name: "Mono Interval".into(),
         });
         result.add(PresetMeta {
-            code: "PO",
+            code: "PO".into(),
             group: PresetGroup::Setting,
             index: 3,
-            name: "Portamento",
+            name: "Portamento".into(),
         });
         result.add(PresetMeta {
-            code: "RO",
+            code: "RO".into(),
             group: PresetGroup::Setting,
             index: 4,
-            name: "Rounding",
+            name: "Rounding".into(),
         });
         result.add(PresetMeta {
-            code: "SP",
+            code: "SP".into(),
             group: PresetGroup::Setting,
             index: 5,
-            name: "Split Voice",
+            name: "Split Voice".into(),
         });
         result.add(PresetMeta {
-            code: "SV",
+            code: "SV".into(),
             group: PresetGroup::Setting,
             index: 6,
-            name: "Single Voice",
+            name: "Single Voice".into(),
         });
         result.add(PresetMeta {
-            code: "TA",
+            code: "TA".into(),
             group: PresetGroup::Setting,
             index: 7,
-            name: "Touch Area",
+            name: "Touch Area".into(),
         });
         result
     }
@@ -837,3 +970,68 @@ impl PresetBuilder {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_preset_names() {
+        assert!(is_empty_preset_name("Empty"));
+        assert!(is_empty_preset_name("Empty.2"));
+        // found by fuzzing: this used to panic
+        assert!(!is_empty_preset_name("Empty\u{e9}"));
+    }
+
+    #[test]
+    fn decode_reports_unknown_codes() {
+        let catcode = HCCategoryCode::new();
+        assert_eq!(catcode.decode("C=ST").unwrap(), "{category:\"Strings\"}");
+        assert_eq!(
+            catcode.decode("C=ST_BA_Q9").unwrap(),
+            "{category:\"Strings\", type:[\"Bass\"], unknown:[\"Q9\"]}"
+        );
+        assert_eq!(catcode.decode("C=Q9_").unwrap(), "{unknown:[\"Q9\"]}");
+    }
+
+    #[test]
+    fn category_tables_parse() {
+        let known = HCCategoryCode::new();
+        let text = "# newer firmware\nCategory\nST Strings\nWI\tWinds\n\n[Type]\nBA=Bass\nNW New Type\n";
+        let table = parse_category_table(text, &known).unwrap();
+        let entries: Vec<(&str, u8, &str)> = table
+            .iter()
+            .map(|meta| (meta.code.as_str(), meta.index, meta.name.as_str()))
+            .collect();
+        assert_eq!(
+            entries,
+            [("ST", 1, "Strings"), ("WI", 2, "Winds"), ("BA", 0, "Bass"), ("NW", 1, "New Type")]
+        );
+        assert!(table[3].group == PresetGroup::Type);
+
+        let error = |text: &str| parse_category_table(text, &known).err().unwrap().message().to_string();
+        assert_eq!(error("Q9 New\n"), "line 1: 'Q9' is new, so needs a group name before it");
+        assert_eq!(error("Category\nST Strings\nST Again\n"), "line 3: 'ST' is listed twice");
+        assert_eq!(error("Category\nStrings\n"), "line 2: expected a code and a name, found 'Strings'");
+        assert_eq!(error("# nothing\n"), "No codes found");
+    }
+
+    #[test]
+    fn tables_read_from_a_file_fall_back_to_the_built_in_one() {
+        let path = std::env::temp_dir().join(format!("harkive-cats-{}.txt", std::process::id()));
+        std::fs::write(&path, "BA Basses\nCategory\nST Strings\nNC New Category\nType\nNW New Type\n").unwrap();
+        let read = HCCategoryCode::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        let catcode = read.unwrap();
+        assert_eq!(
+            catcode.decode("C=NC_BA_NW").unwrap(),
+            "{category:\"New Category\", type:[\"Basses\", \"New Type\"]}"
+        );
+        // left out of the file, so from the built-in table
+        assert_eq!(catcode.decode("C=KY").unwrap(), "{category:\"Keyboard\"}");
+        // the new category is the second bank, and Winds is still known
+        assert_eq!(catcode.bank_name(2), "New Category");
+        assert_eq!(catcode.decode("C=WI").unwrap(), "{category:\"Winds\"}");
+        assert_eq!(HCCategoryCode::new().decode("C=NW").unwrap(), "{unknown:[\"NW\"]}");
+    }
+}
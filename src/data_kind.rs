@@ -1,7 +1,7 @@
 use crate::midi_traits::*;
 
 #[repr(u8)]
-#[derive(Copy, Clone, Default, PartialEq)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub enum DataKind {
     Name,
     ControlText,
@@ -22,11 +22,25 @@ pub enum DataKind {
     Unknown,
 }
 impl DataKind {
+    #[rustfmt::skip]
     pub fn new(raw: u8) -> Self {
-        if raw <= Self::Convolution as u8 {
-            unsafe { ::std::mem::transmute(raw) }
-        } else {
-            DataKind::Unknown
+        match raw {
+            0  => Self::Name,
+            1  => Self::ControlText,
+            2  => Self::Graph,
+            3  => Self::GraphOffset1,
+            4  => Self::GraphOffset2,
+            5  => Self::GraphT0,
+            6  => Self::GraphT1,
+            7  => Self::Log,
+            8  => Self::Category,
+            9  => Self::DemoAssort,
+            10 => Self::Float,
+            11 => Self::Kinetic,
+            12 => Self::BiquadSin,
+            13 => Self::System,
+            14 => Self::Convolution,
+            _  => Self::Unknown,
         }
     }
 }
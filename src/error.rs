@@ -0,0 +1,98 @@
+use std::fmt;
+
+/// Error reported by harkive operations.
+///
+/// Platform errors (WinRT, ALSA) and I/O errors are converted at the point
+/// they enter the crate, so code above the transport never sees them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A missing or unusable command line argument.
+    Usage(String),
+    /// Reading or writing a file or folder failed.
+    Io(String),
+    /// A preset (.mid) or session file is malformed.
+    FileFormat(String),
+    /// A preset listing could not be parsed.
+    Listing(String),
+    /// The device did not answer in time.
+    Timeout(String),
+    /// The device reported that it could not do what was asked (e.g. ArchiveFail).
+    DeviceRejected(String),
+    /// No suitable device is connected.
+    DeviceNotFound(String),
+    /// The platform MIDI API failed.
+    Midi(String),
+    /// A preset read back after loading differs from the file sent.
+    VerifyFailed(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn message(&self) -> &str {
+        match self {
+            Error::Usage(message)
+            | Error::Io(message)
+            | Error::FileFormat(message)
+            | Error::Listing(message)
+            | Error::Timeout(message)
+            | Error::DeviceRejected(message)
+            | Error::DeviceNotFound(message)
+            | Error::Midi(message)
+            | Error::VerifyFailed(message) => message,
+        }
+    }
+
+    /// The same error, with `context` in front of the message.
+    pub fn context(self, context: &str) -> Self {
+        let message = format!("{context}: {}", self.message());
+        match self {
+            Error::Usage(_) => Error::Usage(message),
+            Error::Io(_) => Error::Io(message),
+            Error::FileFormat(_) => Error::FileFormat(message),
+            Error::Listing(_) => Error::Listing(message),
+            Error::Timeout(_) => Error::Timeout(message),
+            Error::DeviceRejected(_) => Error::DeviceRejected(message),
+            Error::DeviceNotFound(_) => Error::DeviceNotFound(message),
+            Error::Midi(_) => Error::Midi(message),
+            Error::VerifyFailed(_) => Error::VerifyFailed(message),
+        }
+    }
+
+    /// Process exit code for the CLI.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Usage(_) => 1,
+            Error::Io(_) => 2,
+            Error::FileFormat(_) => 3,
+            Error::Listing(_) => 4,
+            Error::Timeout(_) => 5,
+            Error::DeviceRejected(_) => 6,
+            Error::DeviceNotFound(_) => 7,
+            Error::Midi(_) => 8,
+            Error::VerifyFailed(_) => 9,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(windows)]
+impl From<windows::core::Error> for Error {
+    fn from(error: windows::core::Error) -> Self {
+        Error::Midi(error.message().to_string_lossy())
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "alsa"))]
+impl From<alsa::Error> for Error {
+    fn from(error: alsa::Error) -> Self {
+        Error::Midi(error.to_string())
+    }
+}
@@ -49,6 +49,8 @@ pub const DeviceStatus:u8       = 111;
 
 pub const DspPercent:u8         = 114;
 
+pub const EditorPresent:u8      = 116;
+
 pub const EditorReply:u8        = 118;
 
 // ----  values  ------------------------------
@@ -79,13 +81,19 @@ pub const DownloadControl_SaveToFlash:u8        = 8;
 
 pub const DownloadControl_DspDone:u8            = 26;
 
+pub const DownloadControl_RequestUserNames:u8   = 32;
+pub const DownloadControl_RequestSystemNames:u8 = 39;
+
 pub const DownloadControl_BeginUserNames:u8     = 54;
 pub const DownloadControl_EndUserNames:u8       = 55;
 
 pub const DownloadControl_EndSystemNames:u8     = 40;
 pub const DownloadControl_BeginSystemNames:u8   = 49;
 
+pub const DownloadControl_ClearBank:u8          = 115; // first of 8 user banks
+
 // DownloadInfo values
+pub const DownloadInfo_ArchiveCurrent:u8        = 100;
 pub const DownloadInfo_BeginArchive:u8          = 120;
 pub const DownloadInfo_RetrieveArchive:u8       = 121;
 pub const DownloadInfo_EndArchive:u8            = 124;
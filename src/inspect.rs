@@ -0,0 +1,69 @@
+use crate::{
+    continuum_preset::HCCategoryCode,
+    error::{Error, Result},
+    midi_traits::Described,
+    options::Options,
+    preset_archive::PresetArchive,
+    util::is_extension,
+};
+use std::path::{Path, PathBuf};
+
+/// Preset files to inspect: the .mid file given, or every .mid file in a folder.
+fn archive_paths(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let entries = std::fs::read_dir(path)
+        .map_err(|error| Error::Io(format!("{}: {error}", path.display())))?;
+    let mut paths: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| is_extension(path, "mid"))
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+fn print_archive(path: &Path, archive: &PresetArchive, catcode: &HCCategoryCode) {
+    println!("Preset '{}' ({})", archive.name(), path.display());
+    let text = archive.text();
+    println!("  Text: {text}");
+    if let Some(friendly) = catcode.decode(&text) {
+        println!("  {friendly}");
+    }
+    let category = archive.category();
+    if !category.is_empty() {
+        println!("  Category: {category}");
+        if let Some(friendly) = catcode.decode(&category) {
+            println!("  {friendly}");
+        }
+    }
+    println!("  Data streams: {}", archive.streams.len());
+    for stream in &archive.streams {
+        println!("    {}", stream.describe());
+    }
+    println!("  Messages: {}", archive.messages.len());
+    for ((channel, kind), count) in archive.message_counts() {
+        if channel == 0 {
+            println!("    {kind}: {count}");
+        } else {
+            println!("    ch{channel} {kind}: {count}");
+        }
+    }
+}
+
+/// Print what is in saved preset archives, without a device.
+pub fn inspect(options: &Options, catcode: &HCCategoryCode) -> Result<()> {
+    let Some(path) = options.get_path() else {
+        return Err(Error::Usage("Missing preset file to inspect".to_string()));
+    };
+    let paths = archive_paths(&path)?;
+    if paths.is_empty() {
+        println!("No preset .mid files in '{}'", path.display());
+    }
+    for path in paths {
+        let archive = PresetArchive::read(&path)?;
+        print_archive(&path, &archive, catcode);
+    }
+    Ok(())
+}
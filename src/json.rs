@@ -0,0 +1,367 @@
+use crate::error::{Error, Result};
+
+/// Just enough JSON for preset documents: objects keep their key order, and numbers with
+/// a fraction or exponent are kept apart from integers.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+// arrays and objects inside one another; preset documents use four
+const MAX_DEPTH: usize = 64;
+
+fn format_error(message: &str, position: usize) -> Error {
+    Error::FileFormat(format!("{message} at offset {position}"))
+}
+
+impl Json {
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Compact text, as used for one line of a larger document.
+    pub fn to_compact(&self) -> String {
+        let mut text = String::new();
+        self.write(&mut text);
+        text
+    }
+
+    fn write(&self, text: &mut String) {
+        match self {
+            Json::Null => text.push_str("null"),
+            Json::Bool(value) => text.push_str(if *value { "true" } else { "false" }),
+            Json::Number(number) => text.push_str(&number.to_string()),
+            // JSON has no NaN or infinity
+            Json::Float(number) if !number.is_finite() => text.push_str("null"),
+            Json::Float(number) => text.push_str(&format!("{number:?}")),
+            Json::String(value) => write_string(value, text),
+            Json::Array(items) => {
+                text.push('[');
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        text.push_str(", ");
+                    }
+                    item.write(text);
+                }
+                text.push(']');
+            }
+            Json::Object(fields) => {
+                text.push('{');
+                for (index, (name, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        text.push_str(", ");
+                    }
+                    write_string(name, text);
+                    text.push_str(": ");
+                    value.write(text);
+                }
+                text.push('}');
+            }
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Json> {
+        let mut parser = Parser {
+            bytes: text.as_bytes(),
+            index: 0,
+            depth: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_space();
+        if parser.index < parser.bytes.len() {
+            return Err(format_error("Unexpected text after JSON value", parser.index));
+        }
+        Ok(value)
+    }
+}
+
+fn write_string(value: &str, text: &mut String) {
+    text.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => text.push_str("\\\""),
+            '\\' => text.push_str("\\\\"),
+            '\n' => text.push_str("\\n"),
+            '\r' => text.push_str("\\r"),
+            '\t' => text.push_str("\\t"),
+            ch if (ch as u32) < 0x20 || ch as u32 == 0x7F => {
+                text.push_str(&format!("\\u{:04x}", ch as u32))
+            }
+            ch => text.push(ch),
+        }
+    }
+    text.push('"');
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    index: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn skip_space(&mut self) {
+        while let Some(byte) = self.bytes.get(self.index) {
+            if !byte.is_ascii_whitespace() {
+                break;
+            }
+            self.index += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_space();
+        self.bytes.get(self.index).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        if self.peek() == Some(byte) {
+            self.index += 1;
+            Ok(())
+        } else {
+            Err(format_error(&format!("Expected '{}'", byte as char), self.index))
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json> {
+        if self.bytes[self.index..].starts_with(word.as_bytes()) {
+            self.index += word.len();
+            Ok(value)
+        } else {
+            Err(format_error("Unknown value", self.index))
+        }
+    }
+
+    /// An array or object, no deeper than MAX_DEPTH so parsing can't run out of stack.
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Json>) -> Result<Json> {
+        if self.depth == MAX_DEPTH {
+            return Err(format_error("Too deeply nested", self.index));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn value(&mut self) -> Result<Json> {
+        match self.peek() {
+            Some(b'{') => self.nested(Self::object),
+            Some(b'[') => self.nested(Self::array),
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b't') => self.keyword("true", Json::Bool(true)),
+            Some(b'f') => self.keyword("false", Json::Bool(false)),
+            Some(b'n') => self.keyword("null", Json::Null),
+            Some(_) => Err(format_error("Unexpected character", self.index)),
+            None => Err(format_error("Unexpected end of JSON", self.index)),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json> {
+        self.expect(b'{')?;
+        let mut fields = Vec::new();
+        if self.peek() == Some(b'}') {
+            self.index += 1;
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            if self.peek() != Some(b'"') {
+                return Err(format_error("Expected a field name", self.index));
+            }
+            let name = self.string()?;
+            self.expect(b':')?;
+            fields.push((name, self.value()?));
+            match self.peek() {
+                Some(b',') => self.index += 1,
+                Some(b'}') => {
+                    self.index += 1;
+                    return Ok(Json::Object(fields));
+                }
+                _ => return Err(format_error("Expected ',' or '}'", self.index)),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        if self.peek() == Some(b']') {
+            self.index += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            match self.peek() {
+                Some(b',') => self.index += 1,
+                Some(b']') => {
+                    self.index += 1;
+                    return Ok(Json::Array(items));
+                }
+                _ => return Err(format_error("Expected ',' or ']'", self.index)),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json> {
+        let start = self.index;
+        if self.bytes[self.index] == b'-' {
+            self.index += 1;
+        }
+        while self.index < self.bytes.len() && self.bytes[self.index].is_ascii_digit() {
+            self.index += 1;
+        }
+        let digits = |parser: &mut Self| {
+            while parser.index < parser.bytes.len() && parser.bytes[parser.index].is_ascii_digit() {
+                parser.index += 1;
+            }
+        };
+        let mut float = false;
+        if self.bytes.get(self.index) == Some(&b'.') {
+            float = true;
+            self.index += 1;
+            digits(self);
+        }
+        if matches!(self.bytes.get(self.index), Some(b'e' | b'E')) {
+            float = true;
+            self.index += 1;
+            if matches!(self.bytes.get(self.index), Some(b'+' | b'-')) {
+                self.index += 1;
+            }
+            digits(self);
+        }
+        let text = std::str::from_utf8(&self.bytes[start..self.index]).unwrap_or_default();
+        if float {
+            text.parse::<f64>()
+                .map(Json::Float)
+                .map_err(|_| format_error("Expected a number", start))
+        } else {
+            text.parse::<i64>()
+                .map(Json::Number)
+                .map_err(|_| format_error("Expected an integer", start))
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let start = self.index;
+        self.index += 1; // opening quote
+        let mut bytes = Vec::new();
+        loop {
+            let Some(&byte) = self.bytes.get(self.index) else {
+                return Err(format_error("Unterminated string", start));
+            };
+            self.index += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let Some(&escape) = self.bytes.get(self.index) else {
+                        return Err(format_error("Unterminated string", start));
+                    };
+                    self.index += 1;
+                    let ch = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let hex = self
+                                .bytes
+                                .get(self.index..self.index + 4)
+                                .and_then(|hex| std::str::from_utf8(hex).ok())
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .and_then(char::from_u32);
+                            self.index += 4;
+                            hex.ok_or_else(|| format_error("Bad \\u escape", self.index - 6))?
+                        }
+                        _ => return Err(format_error("Bad escape", self.index - 2)),
+                    };
+                    let mut buffer = [0u8; 4];
+                    bytes.extend_from_slice(ch.encode_utf8(&mut buffer).as_bytes());
+                }
+                _ => bytes.push(byte),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| format_error("String is not UTF-8", start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_text() {
+        let value = Json::Object(vec![
+            ("name".to_string(), Json::String("Tab\there \"q\" \u{1}".to_string())),
+            (
+                "data".to_string(),
+                Json::Array(vec![
+                    Json::Number(-3),
+                    Json::Null,
+                    Json::Bool(true),
+                    Json::Float(1.5),
+                    Json::Float(-2e-7),
+                    Json::Float(3.0),
+                ]),
+            ),
+            ("empty".to_string(), Json::Object(Vec::new())),
+        ]);
+        assert_eq!(Json::parse(&value.to_compact()).unwrap(), value);
+    }
+
+    #[test]
+    fn errors_give_the_offset() {
+        let error = Json::parse("{\"a\": [1, 2}").unwrap_err();
+        assert_eq!(
+            error,
+            Error::FileFormat("Expected ',' or ']' at offset 11".to_string())
+        );
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = |depth: usize| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(Json::parse(&nested(MAX_DEPTH)).is_ok());
+        let error = Json::parse(&nested(100_000)).unwrap_err();
+        assert_eq!(
+            error,
+            Error::FileFormat(format!("Too deeply nested at offset {MAX_DEPTH}"))
+        );
+    }
+}
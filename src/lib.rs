@@ -0,0 +1,17 @@
+//! The file formats harkive reads and writes, usable without a MIDI transport
+//! (for fuzzing and other tools). The `harkive` binary builds on these.
+
+#[allow(dead_code)]
+pub mod continuum_preset;
+pub mod error;
+#[allow(dead_code)]
+pub mod midi;
+#[allow(dead_code)]
+pub mod midi_message;
+#[allow(dead_code)]
+pub mod midi_traits;
+#[allow(dead_code)]
+pub mod preset_listing;
+pub mod read_midi_file;
+#[allow(dead_code)]
+pub mod util;
@@ -0,0 +1,562 @@
+use crate::{
+    continuum_preset::{category_list, HCCategoryCode, PresetGroup},
+    error::{Error, Result},
+    json::Json,
+    midi_traits::Named,
+    options::Options,
+    preset_archive::PresetArchive,
+    preset_listing::{listing_line, read_preset_listing, ListingMode},
+    util::is_extension,
+};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+// The library index is JSON, a preset per line:
+//
+//   {
+//     "harkive": 1,
+//     "presets": [
+//       {"file": "/presets/Strings/Cello.mid", "name": "Cello", "slot": 3,
+//        "hash": "c3a8e0f1d2b4a596", "text": "C=ST_BR", "tags": {"category": ["Strings"], ...}}
+//     ]
+//   }
+//
+// "slot" is the preset's number in its folder's listing, when it has one. "hash" is the
+// archive's messages without their timing, so re-saved copies of a preset match.
+const INDEX_VERSION: i64 = 1;
+pub const DEFAULT_INDEX: &str = "harkive-index.json";
+const LISTINGS: [&str; 2] = ["UserPresets.txt", "SystemPresets.txt"];
+const MAX_SLOTS: usize = 128;
+
+const GROUPS: [PresetGroup; 5] = [
+    PresetGroup::Category,
+    PresetGroup::Type,
+    PresetGroup::Character,
+    PresetGroup::Matrix,
+    PresetGroup::Setting,
+];
+
+/// One preset archive in the library.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LibraryEntry {
+    pub file: PathBuf,
+    pub name: String,
+    pub slot: Option<u8>,
+    pub hash: String,
+    pub text: String,
+    /// (group, name) for each category code, as `HCCategoryCode` names them.
+    pub tags: Vec<(String, String)>,
+}
+
+/// FNV-1a: unlike `DefaultHasher`, the same from one build to the next.
+fn content_hash(archive: &PresetArchive) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for message in &archive.messages {
+        for byte in std::iter::once(message.status()).chain(message.data()) {
+            hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    format!("{hash:016x}")
+}
+
+fn tags(catcode: &HCCategoryCode, archive: &PresetArchive) -> Vec<(String, String)> {
+    let mut tags = Vec::new();
+    for text in [archive.text(), archive.category()] {
+        for code in category_list(&text) {
+            if let Some(meta) = catcode.get_by_code(&code) {
+                let tag = (meta.group.name().to_string(), meta.name.to_string());
+                if !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+        }
+    }
+    tags
+}
+
+impl LibraryEntry {
+    fn to_json(&self) -> Json {
+        let string = |text: &str| Json::String(text.to_string());
+        let mut groups: Vec<(String, Json)> = Vec::new();
+        for (group, name) in &self.tags {
+            match groups.iter_mut().find(|(existing, _)| existing == group) {
+                Some((_, Json::Array(names))) => names.push(string(name)),
+                _ => groups.push((group.clone(), Json::Array(vec![string(name)]))),
+            }
+        }
+        Json::Object(vec![
+            ("file".to_string(), string(&self.file.to_string_lossy())),
+            ("name".to_string(), string(&self.name)),
+            (
+                "slot".to_string(),
+                self.slot.map_or(Json::Null, |slot| Json::Number(slot as i64)),
+            ),
+            ("hash".to_string(), string(&self.hash)),
+            ("text".to_string(), string(&self.text)),
+            ("tags".to_string(), Json::Object(groups)),
+        ])
+    }
+
+    fn from_json(value: &Json) -> Result<Self> {
+        let text = |name: &str| {
+            value
+                .get(name)
+                .and_then(Json::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| Error::FileFormat(format!("\"{name}\" must be text")))
+        };
+        let slot = match value.get("slot") {
+            None | Some(Json::Null) => None,
+            Some(slot) => Some(
+                slot.as_i64()
+                    .and_then(|slot| u8::try_from(slot).ok())
+                    .ok_or_else(|| Error::FileFormat("\"slot\" must be a number".to_string()))?,
+            ),
+        };
+        let mut tags = Vec::new();
+        if let Some(Json::Object(groups)) = value.get("tags") {
+            for (group, names) in groups {
+                for name in names.as_array().unwrap_or_default() {
+                    if let Some(name) = name.as_str() {
+                        tags.push((group.clone(), name.to_string()));
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            file: PathBuf::from(text("file")?),
+            name: text("name")?,
+            slot,
+            hash: text("hash")?,
+            text: text("text")?,
+            tags,
+        })
+    }
+
+    /// "Strings, Bright": the tag names, category first.
+    pub fn tag_names(&self) -> String {
+        let names: Vec<&str> = GROUPS
+            .iter()
+            .flat_map(|group| {
+                self.tags
+                    .iter()
+                    .filter(move |(tag_group, _)| tag_group == group.name())
+                    .map(|(_, name)| name.as_str())
+            })
+            .collect();
+        names.join(", ")
+    }
+}
+
+pub fn library_text(entries: &[LibraryEntry]) -> String {
+    let mut text = format!("{{\n  \"harkive\": {INDEX_VERSION},\n  \"presets\": [");
+    for (index, entry) in entries.iter().enumerate() {
+        text += if index == 0 { "\n    " } else { ",\n    " };
+        text += &entry.to_json().to_compact();
+    }
+    text += if entries.is_empty() { "]\n}\n" } else { "\n  ]\n}\n" };
+    text
+}
+
+pub fn parse_library(text: &str) -> Result<Vec<LibraryEntry>> {
+    let document = Json::parse(text)?;
+    if document.get("harkive").and_then(Json::as_i64) != Some(INDEX_VERSION) {
+        return Err(Error::FileFormat(
+            "Not a harkive library index (version 1)".to_string(),
+        ));
+    }
+    let presets = document
+        .get("presets")
+        .and_then(Json::as_array)
+        .ok_or_else(|| Error::FileFormat("Missing \"presets\" list".to_string()))?;
+    presets
+        .iter()
+        .enumerate()
+        .map(|(index, preset)| {
+            LibraryEntry::from_json(preset).map_err(|error| error.context(&format!("presets[{index}]")))
+        })
+        .collect()
+}
+
+/// Listing slots by archive file name, from the listings in `folder`.
+fn listing_slots(folder: &Path) -> HashMap<String, u8> {
+    let mut slots = HashMap::new();
+    for listing in LISTINGS {
+        let path = folder.join(listing);
+        if !path.is_file() {
+            continue;
+        }
+        let mut presets = Vec::new();
+        match read_preset_listing(&path, ListingMode::Lenient, &mut presets) {
+            Ok(()) => {
+                for preset in presets {
+                    slots.entry(preset.name + ".mid").or_insert(preset.number);
+                }
+            }
+            Err(error) => println!("Skipping listing {}", error.message()),
+        }
+    }
+    slots
+}
+
+/// Index every .mid archive in `folder` and the folders inside it.
+pub fn scan_folder(folder: &Path, catcode: &HCCategoryCode, entries: &mut Vec<LibraryEntry>) -> Result<()> {
+    let reader = std::fs::read_dir(folder)
+        .map_err(|error| Error::Io(format!("{}: {error}", folder.display())))?;
+    let mut paths: Vec<PathBuf> = reader.flatten().map(|entry| entry.path()).collect();
+    paths.sort();
+    let slots = listing_slots(folder);
+    for path in paths {
+        if path.is_dir() {
+            // a link to a folder can lead back to one being scanned
+            if !path.is_symlink() {
+                scan_folder(&path, catcode, entries)?;
+            }
+            continue;
+        }
+        if !is_extension(&path, "mid") {
+            continue;
+        }
+        let archive = match PresetArchive::read(&path) {
+            Ok(archive) => archive,
+            Err(error) => {
+                // one damaged file shouldn't stop the rest being indexed
+                println!("Skipping {}", error.message());
+                continue;
+            }
+        };
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        entries.push(LibraryEntry {
+            name: archive.name(),
+            slot: slots.get(&file_name).copied(),
+            hash: content_hash(&archive),
+            text: archive.text(),
+            tags: tags(catcode, &archive),
+            file: path,
+        });
+    }
+    Ok(())
+}
+
+/// One condition of a query.
+#[derive(Debug, PartialEq)]
+enum Term {
+    /// name contains
+    Name(String),
+    /// a tag in the group, by name
+    Tag(String, String),
+    /// file path contains
+    File(String),
+    /// name contains, or any tag
+    Any(String),
+}
+
+// words that only make a query read well: "all Strings with character Bright"
+const FILLER: [&str; 6] = ["all", "with", "and", "contains", "is", "in"];
+
+fn query_words(query: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    for ch in query.chars() {
+        match ch {
+            '"' => quoted = !quoted,
+            ch if ch.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            ch => word.push(ch),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+fn field_term(field: &str, value: String) -> Option<Term> {
+    let value = value.to_lowercase();
+    match field {
+        "name" => Some(Term::Name(value)),
+        "file" => Some(Term::File(value)),
+        _ => GROUPS
+            .iter()
+            .find(|group| group.name() == field)
+            .map(|group| Term::Tag(group.name().to_string(), value)),
+    }
+}
+
+/// "category:Strings character:Bright", or the same as "all Strings with character Bright".
+fn parse_query(query: &str) -> Result<Vec<Term>> {
+    let mut terms = Vec::new();
+    let mut words = query_words(query)
+        .into_iter()
+        .filter(|word| !FILLER.contains(&word.to_lowercase().as_str()));
+    while let Some(word) = words.next() {
+        if let Some((field, value)) = word.split_once(':') {
+            let term = field_term(&field.to_lowercase(), value.to_string())
+                .ok_or_else(|| Error::Usage(format!("Unknown search field '{field}'")))?;
+            terms.push(term);
+            continue;
+        }
+        let field = word.to_lowercase();
+        if field_term(&field, String::new()).is_some() {
+            let value = words
+                .next()
+                .ok_or_else(|| Error::Usage(format!("Search for '{word}' needs a value")))?;
+            terms.extend(field_term(&field, value));
+        } else {
+            terms.push(Term::Any(word.to_lowercase()));
+        }
+    }
+    Ok(terms)
+}
+
+fn matches(entry: &LibraryEntry, term: &Term) -> bool {
+    let has_tag = |group: Option<&str>, value: &str| {
+        entry.tags.iter().any(|(tag_group, name)| {
+            group.is_none_or(|group| group == tag_group) && name.to_lowercase() == value
+        })
+    };
+    match term {
+        Term::Name(value) => entry.name.to_lowercase().contains(value),
+        Term::Tag(group, value) => has_tag(Some(group), value),
+        Term::File(value) => entry.file.to_string_lossy().to_lowercase().contains(value),
+        Term::Any(value) => entry.name.to_lowercase().contains(value) || has_tag(None, value),
+    }
+}
+
+/// Whether `query` can be used, for checking options before anything is read.
+pub fn check_query(query: &str) -> Result<()> {
+    parse_query(query).map(|_| ())
+}
+
+/// Entries matching every term of `query`, by name.
+pub fn search_library<'a>(entries: &'a [LibraryEntry], query: &str) -> Result<Vec<&'a LibraryEntry>> {
+    let terms = parse_query(query)?;
+    let mut found: Vec<&LibraryEntry> = entries
+        .iter()
+        .filter(|entry| terms.iter().all(|term| matches(entry, term)))
+        .collect();
+    found.sort_by(|a, b| (&a.name, &a.file).cmp(&(&b.name, &b.file)));
+    Ok(found)
+}
+
+fn library_path(options: &Options) -> PathBuf {
+    options
+        .library
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_INDEX))
+}
+
+fn read_library(path: &Path) -> Result<Vec<LibraryEntry>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|error| Error::Io(format!("{}: {error}", path.display())))?;
+    parse_library(&text).map_err(|error| error.context(&path.display().to_string()))
+}
+
+/// Whether `file` is in `folder` (canonical) or a folder inside it.
+fn in_folder(file: &Path, folder: &Path) -> bool {
+    file.starts_with(folder) || file.canonicalize().is_ok_and(|file| file.starts_with(folder))
+}
+
+/// Scan the folders given into the library index, replacing what it had for them.
+pub fn index(options: &Options, catcode: &HCCategoryCode) -> Result<()> {
+    let path = library_path(options);
+    let mut entries = if path.is_file() {
+        read_library(&path)?
+    } else {
+        Vec::new()
+    };
+    for folder in &options.folders {
+        // the same folder spelled another way replaces what was indexed for it
+        let folder = folder
+            .canonicalize()
+            .map_err(|error| Error::Io(format!("{}: {error}", folder.display())))?;
+        entries.retain(|entry| !in_folder(&entry.file, &folder));
+        let before = entries.len();
+        scan_folder(&folder, catcode, &mut entries)?;
+        println!("Indexed {} preset(s) in '{}'", entries.len() - before, folder.display());
+    }
+    entries.sort_by(|a, b| a.file.cmp(&b.file));
+    std::fs::write(&path, library_text(&entries))
+        .map_err(|error| Error::Io(format!("Couldn't save '{}' : {error}", path.display())))?;
+    println!("Library '{}' has {} preset(s)", path.display(), entries.len());
+    Ok(())
+}
+
+/// A file name in `folder` for `name` not used by an earlier result.
+fn result_file_name(name: &str, used: &mut Vec<String>) -> String {
+    let stem = if name.is_empty() { "anon" } else { name };
+    let mut file = format!("{stem}.mid");
+    let mut count = 1;
+    while used.contains(&file.to_lowercase()) {
+        count += 1;
+        file = format!("{stem} ({count}).mid");
+    }
+    used.push(file.to_lowercase());
+    file
+}
+
+/// Copy found archives to `folder` with a listing, ready for --load. The folder must be
+/// empty, so no preset or listing already there is overwritten.
+fn write_results(found: &[&LibraryEntry], folder: &Path) -> Result<()> {
+    let mut contents = std::fs::read_dir(folder)
+        .map_err(|error| Error::Io(format!("{}: {error}", folder.display())))?;
+    if contents.next().is_some() {
+        return Err(Error::Usage(format!(
+            "'{}' is not empty: search results are copied to an empty folder",
+            folder.display()
+        )));
+    }
+    let mut seen = Vec::new();
+    let mut used = Vec::new();
+    let mut listing = String::new();
+    for entry in found {
+        if seen.contains(&&entry.hash) {
+            continue; // the same preset saved in more than one place
+        }
+        if seen.len() == MAX_SLOTS {
+            println!("Only the first {MAX_SLOTS} presets fit in a listing");
+            break;
+        }
+        seen.push(&entry.hash);
+        let file = result_file_name(&entry.name, &mut used);
+        let target = folder.join(&file);
+        std::fs::copy(&entry.file, &target).map_err(|error| {
+            Error::Io(format!("Couldn't copy '{}' : {error}", entry.file.display()))
+        })?;
+        // listed last slot first, as harkive saves them
+        listing.insert_str(0, &listing_line(seen.len(), &file));
+    }
+    let path = folder.join(LISTINGS[0]);
+    std::fs::write(&path, listing)
+        .map_err(|error| Error::Io(format!("Couldn't save '{}' : {error}", path.display())))?;
+    println!("Saved {} preset(s) and '{}' for --load", seen.len(), path.display());
+    Ok(())
+}
+
+/// Print the presets in the library index matching the query.
+pub fn search(options: &Options) -> Result<()> {
+    let Some(query) = &options.query else {
+        return Err(Error::Usage("Missing search query".to_string()));
+    };
+    let entries = read_library(&library_path(options))?;
+    let found = search_library(&entries, query)?;
+    if found.is_empty() {
+        println!("No presets match '{query}'");
+        return Ok(());
+    }
+    for entry in &found {
+        let slot = entry.slot.map_or(String::new(), |slot| format!(" #{slot}"));
+        println!("{} ({}){slot}: {}", entry.name, entry.tag_names(), entry.file.display());
+    }
+    println!("{} preset(s)", found.len());
+    if let Some(folder) = options.get_path() {
+        write_results(&found, &folder)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{haken_midi::cc16, midi_file::MidiFile, stream_decode::stream_messages};
+
+    fn entry(name: &str, file: &str, tags: &[(&str, &str)]) -> LibraryEntry {
+        LibraryEntry {
+            file: PathBuf::from(file),
+            name: name.to_string(),
+            slot: None,
+            hash: format!("{:016x}", name.len()),
+            text: String::new(),
+            tags: tags
+                .iter()
+                .map(|(group, name)| (group.to_string(), name.to_string()))
+                .collect(),
+        }
+    }
+
+    fn library() -> Vec<LibraryEntry> {
+        vec![
+            entry("Bright Cello", "/a/Cello.mid", &[("category", "Strings"), ("character", "Bright")]),
+            entry("Dark Pad", "/b/Pad.mid", &[("category", "Other"), ("character", "Dark")]),
+            entry("Viola", "/b/Viola.mid", &[("category", "Strings"), ("character", "Dark")]),
+        ]
+    }
+
+    fn names(query: &str) -> Vec<String> {
+        let library = library();
+        search_library(&library, query)
+            .unwrap()
+            .iter()
+            .map(|entry| entry.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn queries_read_as_written() {
+        assert_eq!(names("all Strings with character Bright"), vec!["Bright Cello"]);
+        assert_eq!(names("category:strings"), vec!["Bright Cello", "Viola"]);
+        assert_eq!(names("name contains pad"), vec!["Dark Pad"]);
+        // a bare word is a name or any tag: "Bright" the character, "Dark" in a name
+        assert_eq!(names("dark"), vec!["Dark Pad", "Viola"]);
+        assert_eq!(names("file:/b/ strings"), vec!["Viola"]);
+        assert_eq!(
+            parse_query("colour:red").unwrap_err(),
+            Error::Usage("Unknown search field 'colour'".to_string())
+        );
+        assert_eq!(parse_query("character").unwrap_err().exit_code(), 1);
+    }
+
+    #[test]
+    fn index_round_trips() {
+        let mut library = library();
+        library[0].slot = Some(3);
+        assert_eq!(parse_library(&library_text(&library)).unwrap(), library);
+    }
+
+    fn temp_folder(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("harkive-{name}-{}", std::process::id()));
+        _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    #[test]
+    fn results_go_to_an_empty_folder() {
+        let folder = temp_folder("results");
+        std::fs::write(folder.join(LISTINGS[0]), "1,\"Mine.mid\"\n").unwrap();
+        let library = library();
+        let error = write_results(&[&library[0]], &folder).unwrap_err();
+        let listing = std::fs::read_to_string(folder.join(LISTINGS[0])).unwrap();
+        _ = std::fs::remove_dir_all(&folder);
+        assert_eq!(error.exit_code(), 1);
+        assert_eq!(listing, "1,\"Mine.mid\"\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn folders_are_indexed_once() {
+        let folder = temp_folder("index");
+        let mut file = MidiFile::default();
+        for message in stream_messages(cc16::DataStream_Name, b"Cello") {
+            file.add_delta_message(0, &message);
+        }
+        std::fs::write(folder.join("Cello.mid"), file.finish()).unwrap();
+        // a link back to the folder isn't followed
+        std::os::unix::fs::symlink(&folder, folder.join("again")).unwrap();
+        let mut options = Options::default();
+        options.library = Some(folder.join("index.json"));
+        // the same folder, spelled two ways
+        options.folders = vec![folder.clone(), folder.join("again").join(".")];
+        let indexed = index(&options, &HCCategoryCode::new()).and_then(|()| read_library(&folder.join("index.json")));
+        let expected = folder.canonicalize().unwrap().join("Cello.mid");
+        _ = std::fs::remove_dir_all(&folder);
+        let files: Vec<PathBuf> = indexed.unwrap().into_iter().map(|entry| entry.file).collect();
+        assert_eq!(files, [expected]);
+    }
+}
@@ -1,54 +1,65 @@
-use std::{sync::mpsc::*, thread};
+use std::{process::ExitCode, sync::mpsc::*, thread};
 
-use windows::{
-    core::*,
-    Devices::Midi::*,
-    Win32::UI::Input::KeyboardAndMouse::{
-        GetAsyncKeyState, VK_ESCAPE, VK_LCONTROL, VK_LSHIFT, VK_RCONTROL, VK_RSHIFT, VK_SPACE,
-    },
+#[cfg(windows)]
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    GetAsyncKeyState, VK_ESCAPE, VK_LCONTROL, VK_LSHIFT, VK_RCONTROL, VK_RSHIFT, VK_SPACE,
 };
 
 mod acquire_device;
+#[cfg(all(target_os = "linux", feature = "alsa"))]
+mod alsa_transport;
+mod archive_diff;
 mod cc_text;
-#[allow(dead_code)]
-mod continuum_preset;
-#[allow(dead_code)]
 mod data_kind;
 mod gather_state;
 mod haken_midi;
+mod inspect;
+mod json;
+mod library;
 mod matrix_handler;
-#[allow(dead_code)]
-mod midi;
 mod midi_file;
 mod midi_handler;
 mod midi_monitor;
 mod midi_source;
-#[allow(dead_code)]
-mod midi_traits;
 mod options;
-#[allow(dead_code)]
-mod preset_listing;
+mod pacing;
+mod preset_archive;
+mod preset_document;
+mod preset_metadata;
 mod step_load;
 mod preset_manager;
-mod read_midi_file;
+mod session;
+mod sim_device;
+mod step_backup;
+mod step_clear;
 mod stepper;
 mod step_names;
 mod step_save;
+mod stream_decode;
 mod thread_control;
-#[allow(dead_code)]
-mod util;
+mod transport;
+mod watchdog;
+#[cfg(windows)]
+mod winrt_transport;
+
+use harkive::{
+    continuum_preset, error, midi, midi_message, midi_traits, preset_listing, read_midi_file,
+    util,
+};
 
 use acquire_device::*;
+use continuum_preset::HCCategoryCode;
+use error::{Error, Result};
 use midi_handler::*;
+use midi_message::{MidiMessage, TimedMessage};
 use midi_monitor::MidiMonitor;
 use midi_source::MidiSource;
+use midi_traits::Named;
 use options::{Action, Options};
 use thread_control::*;
+use transport::{open_transport, MidiOutput, MidiTransport};
 
-fn main() -> Result<()> {
-    futures::executor::block_on(main_async())
-}
-
+#[cfg(windows)]
 fn is_quit_key_pressed() -> bool {
     0 != unsafe {
         GetAsyncKeyState(VK_LCONTROL.0 as i32)
@@ -60,17 +71,29 @@ fn is_quit_key_pressed() -> bool {
     }
 }
 
-async fn midi_monitor(options: &Options) -> Result<()> {
-    fn send_cc(port: &MidiOutPort, channel: u8, cc: u8, value: u8) -> Result<()> {
-        port.SendMessage(&MidiControlChangeMessage::CreateMidiControlChangeMessage(
-            channel, cc, value,
-        )?)
+// No console key polling elsewhere: Ctrl+C ends the monitor.
+#[cfg(not(windows))]
+fn is_quit_key_pressed() -> bool {
+    false
+}
+
+fn midi_monitor(transport: &dyn MidiTransport, options: &Options) -> Result<()> {
+    fn send_cc(port: &dyn MidiOutput, channel: u8, cc: u8, value: u8) -> Result<()> {
+        port.send(&MidiMessage::cc(channel, cc, value))
     }
 
-    println!("Monitoring MIDI.\nPress any of (SPACE, CTRL, ESC) then a note to stop.");
-    if let Some((input, output)) = get_haken_io(&options.device).await {
-        println!("Using {}", input.description.friendly_name());
-        let (midi_tx, midi_rx) = channel::<WinMidi>();
+    if cfg!(windows) {
+        println!("Monitoring MIDI.\nPress any of (SPACE, CTRL, ESC) then a note to stop.");
+    } else {
+        println!("Monitoring MIDI.\nPress CTRL+C to stop.");
+    }
+    if let Some((input, mut output)) = get_haken_io(transport, &options.device) {
+        println!(
+            "Using {} ({})",
+            input.description.friendly_name(),
+            input.description.kind.name()
+        );
+        let (midi_tx, midi_rx) = channel::<TimedMessage>();
         let (thread_tx, thread_rx) = ThreadControl::make_channels();
         let joiner = thread::spawn(move || {
             let midi_source = MidiSource::new(midi_tx, thread_rx, input);
@@ -78,12 +101,12 @@ async fn midi_monitor(options: &Options) -> Result<()> {
         });
         let mut handler = MidiMonitor::default();
         println!("[Enabling detailed MIDI output]");
-        send_cc(&output.port, 15, 116, 85)?; // editor present
+        send_cc(output.port.as_ref(), 15, 116, 85)?; // editor present
         println!("[Request User preset names]");
-        send_cc(&output.port, 15, 109, 32)?; // send names
-                                             //send_cc(&output.port,15, 109, 39)?; // sys names
+        send_cc(output.port.as_ref(), 15, 109, 32)?; // send names
+                                             //send_cc(output.port.as_ref(),15, 109, 39)?; // sys names
         println!("[Request updates when presets change]");
-        send_cc(&output.port, 15, 55, 1)?; // send updates
+        send_cc(output.port.as_ref(), 15, 55, 1)?; // send updates
         let mut last = std::time::SystemTime::now();
         while let Ok(msg) = midi_rx.recv() {
             if is_quit_key_pressed() {
@@ -94,7 +117,7 @@ async fn midi_monitor(options: &Options) -> Result<()> {
                 if let Ok(dt) = now.duration_since(last) {
                     if dt.as_millis() > 1_000 {
                         println!("[Poll device status, DSP]");
-                        send_cc(&output.port, 15, 116, 85)?; // editor present
+                        send_cc(output.port.as_ref(), 15, 116, 85)?; // editor present
                     }
                 }
                 last = now;
@@ -106,56 +129,104 @@ async fn midi_monitor(options: &Options) -> Result<()> {
                 println!("Error {error:?}");
             }
         }
+        output.port.close();
     } else {
-        println!("Unable to find a suitable device");
+        return Err(Error::DeviceNotFound("Unable to find a suitable device".to_string()));
     }
     Ok(())
 }
 
-async fn main_async() -> Result<()> {
-    if let Some(options) = Options::get_options() {
-        match options.action {
-            Action::Nothing | Action::Usage => {
-                Options::usage();
-            }
-            Action::Docs => {
-                Options::docs();
-            }
-            Action::ListMidi => {
-                println!("MIDI devices:");
-                list_midi_devices().await;
+fn run(options: &Options) -> Result<ExitCode> {
+    let catcode = match &options.categories {
+        Some(path) => HCCategoryCode::read(path)?,
+        None => HCCategoryCode::new(),
+    };
+    match options.action {
+        Action::Nothing | Action::Usage => {
+            Options::usage();
+        }
+        Action::Docs => {
+            Options::docs();
+        }
+        Action::ListMidi => {
+            let transport = open_transport(options)?;
+            println!("MIDI devices:");
+            list_midi_devices(transport.as_ref());
+        }
+        Action::Clear if options.dry_run => {
+            step_clear::print_clear_plan();
+        }
+        Action::ListNames | Action::ListSystem | Action::Clear => {
+            let transport = open_transport(options)?;
+            preset_manager::PresetManager::new(transport.as_ref(), options, &catcode)?.run()?;
+        }
+        Action::Inspect => {
+            inspect::inspect(options, &catcode)?;
+        }
+        Action::Diff => {
+            if archive_diff::diff(options, &catcode)? {
+                return Ok(ExitCode::from(archive_diff::DIFFERENT_EXIT_CODE));
             }
-            Action::ListNames | Action::Clear => {
-                if let Some(mut manager) = preset_manager::PresetManager::new(&options).await {
-                    manager.run()?;
+        }
+        Action::Export => {
+            preset_document::export(options)?;
+        }
+        Action::Import => {
+            preset_document::import(options)?;
+        }
+        Action::Index => {
+            library::index(options, &catcode)?;
+        }
+        Action::Search => {
+            library::search(options)?;
+        }
+        Action::Monitor => {
+            let transport = open_transport(options)?;
+            midi_monitor(transport.as_ref(), options)?;
+        }
+        Action::SaveCurrent | Action::Save | Action::Load => {
+            let act = match options.action {
+                Action::SaveCurrent => "Save edit",
+                Action::Save => "Save",
+                Action::Load => "Load",
+                _ => unreachable!(),
+            };
+            if let Some(folder) = options.get_path_display_name() {
+                if let Some(device) = &options.device {
+                    println!("{act} preset for {device} with {folder}");
+                } else {
+                    println!("{act} preset with {folder}");
                 }
+            } else {
+                unreachable!();
             }
-            Action::Monitor => {
-                midi_monitor(&options).await?;
+            if options.dry_run {
+                step_load::print_load_plan(options)?;
+                return Ok(ExitCode::SUCCESS);
             }
-            Action::SaveCurrent | Action::Save | Action::Load => {
-                let act = match options.action {
-                    Action::SaveCurrent => "Save edit",
-                    Action::Save => "Save",
-                    Action::Load => "Load",
-                    _ => unreachable!(),
-                };
-                if let Some(folder) = options.get_path_display_name() {
-                    if let Some(device) = &options.device {
-                        println!("{act} preset for {device} with {folder}");
-                    } else {
-                        println!("{act} preset with {folder}");
-                    }
-                } else {
-                    unreachable!();
-                }
-                if let Some(mut manager) = preset_manager::PresetManager::new(&options).await {
-                    manager.run()?;
-                }
+            let transport = open_transport(options)?;
+            preset_manager::PresetManager::new(transport.as_ref(), options, &catcode)?.run()?;
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn main() -> ExitCode {
+    let options = match Options::get_options() {
+        Ok(options) => options,
+        Err(error) => {
+            println!("Error: {error}");
+            if let Error::Usage(_) = error {
+                Options::usage();
             }
+            return ExitCode::from(error.exit_code());
+        }
+    };
+    match run(&options) {
+        Ok(code) => code,
+        Err(error) => {
+            println!("Error: {error}");
+            ExitCode::from(error.exit_code())
         }
-    } else {
-        Options::usage();
     }
-    Ok(())
 }
@@ -5,11 +5,14 @@ use crate::{
 use crate::{
     continuum_preset::*,
     data_kind::DataKind,
+    midi_traits::Named,
+    error::Result,
     midi::{CHANNEL15, CHANNEL16},
     midi_handler::*,
+    midi_message::MidiMessage,
+    watchdog::{Phase, RetryPolicy, Watchdog},
 };
 use std::io::Write;
-use windows::{core::*, Devices::Midi::*};
 
 #[derive(Clone, Copy, Default, PartialOrd, PartialEq)]
 #[allow(dead_code)]
@@ -49,8 +52,10 @@ pub struct MatrixHandler {
     presets: Vec<ContinuumPreset>,
     in_preset_names: bool,
     in_archive: bool,
+    // collect an archive outside of a save action (load verification)
+    read_back: bool,
+    archive_received: bool,
     progress_count: usize,
-    next_bank: u8,
     midi_file: MidiFile,
     done: bool,
     receive_editor_reply: bool,
@@ -58,13 +63,16 @@ pub struct MatrixHandler {
     tick_tock: bool,
     receive_sync: bool,
     archive_state: ArchiveState,
+    // FirmwareVersionHi waiting on its Lo
+    firmware_hi: u8,
+    firmware_version: Option<u16>,
+    watchdog: Watchdog,
+    retry_policy: RetryPolicy,
 }
 
 impl Drop for MatrixHandler {
     fn drop(&mut self) {
-        if let Err(error) = self.output.port.Close() {
-            println!("Error closing MIDI out handle: {}", error.message());
-        }
+        self.output.port.close();
     }
 }
 
@@ -79,8 +87,9 @@ impl MatrixHandler {
             presets: Vec::default(),
             in_preset_names: false,
             in_archive: false,
+            read_back: false,
+            archive_received: false,
             progress_count: 0,
-            next_bank: u8::MAX,
             midi_file: MidiFile::default(),
             done: false,
             receive_editor_reply: false,
@@ -88,10 +97,14 @@ impl MatrixHandler {
             tick_tock: true,
             receive_sync: false,
             archive_state: ArchiveState::Unknown,
+            firmware_hi: 0,
+            firmware_version: None,
+            watchdog: Watchdog::default(),
+            retry_policy: RetryPolicy::default(),
         }
     }
-    pub fn output_port(&self) -> &MidiOutPort {
-        &self.output.port
+    pub fn send(&self, message: &MidiMessage) -> Result<()> {
+        self.output.port.send(message)
     }
 
     pub fn is_ready(&self) -> bool {
@@ -103,6 +116,40 @@ impl MatrixHandler {
     pub fn editor_reply(&self) -> bool {
         self.receive_editor_reply
     }
+    pub fn dsp_done(&self) -> bool {
+        self.receive_sync
+    }
+    pub fn clear_dsp_done(&mut self) {
+        self.receive_sync = false;
+    }
+
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    /// Save archives without arrival timing (see `MidiFile::set_canonical`).
+    pub fn set_canonical(&mut self, canonical: bool) {
+        self.midi_file.set_canonical(canonical);
+    }
+
+    /// Wait on `phase` for the one-based `slot`, re-sending when it times out.
+    pub fn expect(&mut self, phase: Phase, slot: Option<u8>) {
+        self.watchdog.arm(phase, slot);
+    }
+    /// The awaited reply arrived.
+    pub fn satisfied(&mut self) {
+        self.watchdog.disarm();
+    }
+    /// A reply to the phase being waited on restarts the timeout; other traffic doesn't.
+    pub fn note_activity(&mut self, message: &MidiMessage) {
+        if self.watchdog.phase().is_some_and(|phase| phase.is_reply(message)) {
+            self.watchdog.activity();
+        }
+    }
+    /// Ok(true) when the awaited reply timed out and the request should be re-sent.
+    pub fn check_timeout(&mut self) -> Result<bool> {
+        self.watchdog.check(&self.retry_policy)
+    }
 
     pub fn get_archive_data(&mut self) -> Vec<u8> {
         self.midi_file.finish()
@@ -115,6 +162,15 @@ impl MatrixHandler {
         self.archive_state = ArchiveState::Unknown
     }
 
+    /// Firmware version the device reported, once it has.
+    pub fn firmware_version(&self) -> Option<u16> {
+        self.firmware_version
+    }
+
+    pub fn device_kind(&self) -> &'static str {
+        self.output.description.kind.name()
+    }
+
     pub fn get_presets(&self) -> &Vec<ContinuumPreset> {
         &self.presets
     }
@@ -124,7 +180,18 @@ impl MatrixHandler {
     }
 
     fn is_saving(&self) -> bool {
-        self.verb == Action::SaveCurrent || self.verb == Action::Save
+        self.read_back || self.verb == Action::SaveCurrent || self.verb == Action::Save
+    }
+
+    /// Archive the selected preset back from the device, whatever the action.
+    pub fn read_back_current(&mut self) -> Result<()> {
+        self.read_back = true;
+        self.archive_received = false;
+        self.transmit_archive_current()
+    }
+
+    pub fn archive_received(&self) -> bool {
+        self.archive_received
     }
 
     pub fn terse(&self) -> bool {
@@ -147,30 +214,48 @@ impl MatrixHandler {
     }
 
     pub fn send_cc(&self, channel: u8, cc: u8, value: u8) -> Result<()> {
-        self.output
-            .port
-            .SendMessage(&MidiControlChangeMessage::CreateMidiControlChangeMessage(
-                channel, cc, value,
-            )?)
+        self.send(&MidiMessage::cc(channel, cc, value))
+    }
+
+    pub fn send_program_change(&self, channel: u8, program: u8) -> Result<()> {
+        self.send(&MidiMessage::program_change(channel, program))
     }
 
     pub fn send_string(&self, kind: u8, text: &str) -> Result<()> {
         self.send_cc(CHANNEL16, 56, kind)?;
         for ch in text.bytes() {
-            self.output.port.SendMessage(
-                &MidiChannelPressureMessage::CreateMidiChannelPressureMessage(CHANNEL16, ch)?,
-            )?;
+            self.send(&MidiMessage::channel_pressure(CHANNEL16, ch))?;
         }
         self.send_cc(CHANNEL16, 56, 127)?;
         Ok(())
     }
 
     pub fn start_action(&mut self, act: Action) -> Result<()> {
+        // the first reply each action waits on; steppers take it from there
         match act {
-            Action::Nothing | Action::Usage | Action::Docs | Action::ListMidi | Action::Monitor => {
+            Action::ListNames | Action::ListSystem | Action::Save => {
+                self.expect(Phase::PresetNames, None)
+            }
+            Action::SaveCurrent => self.expect(Phase::Archive, None),
+            Action::Clear => self.expect(Phase::ClearBank(0), None),
+            _ => {}
+        }
+        match act {
+            Action::Nothing
+            | Action::Usage
+            | Action::Docs
+            | Action::ListMidi
+            | Action::Monitor
+            | Action::Inspect
+            | Action::Diff
+            | Action::Export
+            | Action::Import
+            | Action::Index
+            | Action::Search => {
                 unreachable!()
             }
             Action::ListNames => self.start_list_names(),
+            Action::ListSystem => self.start_list_system(),
             Action::SaveCurrent => self.start_save_current(),
             Action::Save => self.start_save_presets(),
             Action::Load => self.start_load_presets(),
@@ -195,6 +280,12 @@ impl MatrixHandler {
         self.send_cc(CHANNEL16, 116, 85) // editor present ensures End of Preset Names is sent.
     }
 
+    fn start_list_system(&mut self) -> Result<()> {
+        self.action_prelude(Action::ListSystem)?;
+        self.transmit_system_names()?;
+        self.send_cc(CHANNEL16, 116, 85)
+    }
+
     fn start_save_current(&mut self) -> Result<()> {
         self.action_prelude(Action::SaveCurrent)?;
         self.transmit_archive_current()?;
@@ -214,10 +305,7 @@ impl MatrixHandler {
 
     fn start_clear(&mut self) -> Result<()> {
         self.action_prelude(Action::Clear)?;
-        self.receive_sync = false;
-        self.clear_bank(0)?;
-        self.next_bank = 1;
-        Ok(())
+        self.clear_bank(0)
     }
 
     pub fn editor_present(&mut self) -> Result<()> {
@@ -230,32 +318,20 @@ impl MatrixHandler {
     pub fn choose_edit_slot(&self) -> Result<()> {
         self.send_cc(CHANNEL16, 0, 126)?;
         self.send_cc(CHANNEL16, 32, 0)?;
-        self.output
-            .port
-            .SendMessage(&MidiProgramChangeMessage::CreateMidiProgramChangeMessage(
-                CHANNEL16, 0,
-            )?)
+        self.send_program_change(CHANNEL16, 0)
     }
 
     pub fn set_edit_slot(&self) -> Result<()> {
         self.send_cc(CHANNEL16, 0, 126)?;
         self.send_cc(CHANNEL16, 32, 0)?;
-        self.output
-            .port
-            .SendMessage(&MidiProgramChangeMessage::CreateMidiProgramChangeMessage(
-                CHANNEL15, 1,
-            )?)
+        self.send_program_change(CHANNEL15, 1)
     }
 
     // setting for store (save to flash) is one-based index on channel 15
     pub fn set_slot(&self, slot:u8) -> Result<()> {
         self.send_cc(CHANNEL16, 0, 0)?;
         self.send_cc(CHANNEL16, 32, 0)?;
-        self.output
-            .port
-            .SendMessage(&MidiProgramChangeMessage::CreateMidiProgramChangeMessage(
-                CHANNEL15, slot-1
-            )?)
+        self.send_program_change(CHANNEL15, slot-1)
     }
 
     // selection is zero-based slot index on channel 16
@@ -263,38 +339,49 @@ impl MatrixHandler {
         if index == 128 {
             self.send_cc(CHANNEL16, 0, 126)?;
             self.send_cc(CHANNEL16, 32, 1)?;
-            self.output
-                .port
-                .SendMessage(&MidiProgramChangeMessage::CreateMidiProgramChangeMessage(
-                    CHANNEL16, 1,
-                )?)
+            self.send_program_change(CHANNEL16, 1)
         } else {
             // bank
             self.send_cc(CHANNEL16, 0, 0)?;
             // category
             self.send_cc(CHANNEL16, 32, 0)?;
             // preset#
-            self.output
-                .port
-                .SendMessage(&MidiProgramChangeMessage::CreateMidiProgramChangeMessage(
-                    CHANNEL16, index,
-                )?)
+            self.send_program_change(CHANNEL16, index)
         }
     }
 
+    /// Select a system preset by the bank and number it was listed with.
+    pub fn choose_system_preset(&self, preset: &ContinuumPreset) -> Result<()> {
+        self.send_cc(CHANNEL16, 0, preset.bank_hi)?;
+        self.send_cc(CHANNEL16, 32, preset.bank_lo)?;
+        self.send_program_change(CHANNEL16, preset.number)
+    }
+
     /// bank = 0-based user bank 0..7
-    pub fn clear_bank(&self, bank: u8) -> Result<()> {
+    pub fn clear_bank(&mut self, bank: u8) -> Result<()> {
         println!("[>Clearing preset bank {bank}]");
+        self.receive_sync = false;
         self.send_cc(CHANNEL16, 109, 115 + bank)
     }
 
-    fn transmit_names(&mut self) -> Result<()> {
+    pub fn transmit_names(&mut self) -> Result<()> {
         self.terse_message("[>Request names]");
         self.send_cc(CHANNEL16, 109, 32)?; // user presets
         self.editor_present()?; // editor present
         Ok(())
     }
 
+    pub fn transmit_system_names(&mut self) -> Result<()> {
+        self.terse_message("[>Request system names]");
+        self.send_cc(
+            CHANNEL16,
+            cc16::DownloadControl,
+            cc16::DownloadControl_RequestSystemNames,
+        )?;
+        self.editor_present()?;
+        Ok(())
+    }
+
     fn transmit_quiet(&self) -> Result<()> {
         for channel in 0..=12 {
             for cc in [120, 121, 122] {
@@ -304,7 +391,7 @@ impl MatrixHandler {
         Ok(())
     }
 
-    fn transmit_archive_current(&self) -> Result<()> {
+    pub fn transmit_archive_current(&self) -> Result<()> {
         self.terse_message("[>Archive active preset]");
         self.send_cc(CHANNEL16, 110, 100)?;
         Ok(())
@@ -331,6 +418,12 @@ impl MatrixHandler {
                 self.bin_type = DataKind::new(value);
                 self.gather_state = Self::gather_state_for_data(self.bin_type);
             }
+            cc16::FirmwareVersionHi => {
+                self.firmware_hi = value;
+            }
+            cc16::FirmwareVersionLo => {
+                self.firmware_version = Some((self.firmware_hi as u16) << 7 | value as u16);
+            }
             cc16::DownloadControl => match value {
                 cc16::DownloadControl_ArchiveOk => {
                     self.archive_state = ArchiveState::Ok;
@@ -350,18 +443,13 @@ impl MatrixHandler {
                     self.receive_sync = true;
 
                     if Action::Clear == self.verb {
-                        if self.next_bank < 8 {
-                            self.receive_sync = false;
-                            _ = self.clear_bank(self.next_bank);
-                            self.next_bank += 1;
-                        } else {
-                            self.next_bank = u8::MAX;
-                            self.done = true;
-                        }
+                        self.done = true;
                     }
                 }
                 cc16::DownloadControl_BeginSystemNames | cc16::DownloadControl_BeginUserNames => {
                     self.terse_message("[---- Begin preset names ----]");
+                    // drop anything gathered from archive streams
+                    self.preset_builder.start();
                     self.in_preset_names = true;
                     self.progress_count = 0;
                 }
@@ -388,6 +476,8 @@ impl MatrixHandler {
                         self.terse_message("[---- End archive ----]");
                         if self.is_saving() {
                             self.in_archive = false;
+                            self.read_back = false;
+                            self.archive_received = true;
                             self.done = true;
                         }
                     }
@@ -419,10 +509,7 @@ impl MidiHandler for MatrixHandler {
         note: u8,
         pressure: u8,
     ) -> Result<()> {
-        unreachable!();
-        // let channel = 1 + channel;
-        // Self::log_channel_message2(ticks, "PolyKeyPressure", channel, note, pressure);
-        // Ok(())
+        Ok(())
     }
 
     fn on_control_change(&mut self, ticks: i64, channel: u8, cc: u8, value: u8) -> Result<()> {
@@ -451,7 +538,7 @@ impl MidiHandler for MatrixHandler {
                 if self.in_preset_names {
                     if self.verbose() {
                         self.progress_count += 1;
-                        _ = std::io::stdout().write(&[b'.']);
+                        _ = std::io::stdout().write(b".");
                         if self.progress_count == 16 {
                             println!();
                             self.progress_count = 0;
@@ -501,62 +588,50 @@ impl MidiHandler for MatrixHandler {
     }
 
     fn on_system_exclusive(&mut self, ticks: i64, data: Vec<u8>) -> Result<()> {
-        unreachable!();
-        // Ok(())
+        Ok(())
     }
 
     fn on_midi_time_code(&mut self, ticks: i64, frame: u8, values: u8) -> Result<()> {
-        unreachable!();
-        // Ok(())
+        Ok(())
     }
 
     fn on_song_position_pointer(&mut self, ticks: i64, beats: u16) -> Result<()> {
-        unreachable!();
-        // Ok(())
+        Ok(())
     }
 
     fn on_song_select(&mut self, ticks: i64, song: u8) -> Result<()> {
-        unreachable!();
-        // Ok(())
+        Ok(())
     }
 
     fn on_tune_request(&mut self, ticks: i64) -> Result<()> {
-        unreachable!();
-        // Ok(())
+        Ok(())
     }
 
     fn on_end_system_exclusive(&mut self, ticks: i64, data: Vec<u8>) -> Result<()> {
-        unreachable!();
-        // Ok(())
+        Ok(())
     }
 
     fn on_timing_clock(&mut self, ticks: i64) -> Result<()> {
-        unreachable!();
-        // Ok(())
+        Ok(())
     }
 
     fn on_start(&mut self, ticks: i64) -> Result<()> {
-        unreachable!();
-        // Ok(())
+        Ok(())
     }
 
     fn on_continue(&mut self, ticks: i64) -> Result<()> {
-        unreachable!();
-        // Ok(())
+        Ok(())
     }
 
     fn on_stop(&mut self, ticks: i64) -> Result<()> {
-        unreachable!();
-        // Ok(())
+        Ok(())
     }
 
     fn on_active_sensing(&mut self, ticks: i64) -> Result<()> {
-        unreachable!();
-        // Ok(())
+        Ok(())
     }
 
     fn on_system_reset(&mut self, ticks: i64) -> Result<()> {
-        unreachable!();
-        // Ok(())
+        Ok(())
     }
 }
@@ -1,11 +1,7 @@
-use crate::midi_traits::*;
-use windows::core::*;
-
-#[derive(Default, Clone)]
-pub struct MidiDeviceInfo {
-    pub name: HSTRING,
-    pub id: HSTRING,
-}
+use crate::{
+    error::{Error, Result},
+    midi_traits::*,
+};
 
 pub const STATUS_NOTE_OFF: u8 = 0x80;
 pub const STATUS_NOTE_ON: u8 = 0x90;
@@ -314,6 +310,41 @@ pub fn is_midi_header(data: &[u8]) -> bool {
     }
 }
 
+/// How a MIDI file's ticks map to real time: `division` ticks per quarter note,
+/// `tempo` microseconds per quarter note.
+///
+/// Real time is counted in 100 ns units, as MIDI input timestamps are. When a tick is a
+/// whole number of those units, `file_ticks` and `ticks` are exact inverses.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TimeBase {
+    pub division: u16,
+    pub tempo: u32,
+}
+
+impl Default for TimeBase {
+    /// 120 bpm at 500 ticks per quarter note: one tick per millisecond.
+    fn default() -> Self {
+        Self {
+            division: 500,
+            tempo: 500_000,
+        }
+    }
+}
+
+impl TimeBase {
+    /// Whole file ticks in `ticks` 100 ns units.
+    pub fn file_ticks(&self, ticks: i64) -> u64 {
+        let units_per_quarter = self.tempo.max(1) as u128 * 10;
+        (ticks.max(0) as u128 * self.division as u128 / units_per_quarter) as u64
+    }
+
+    /// 100 ns units in `file_ticks` ticks.
+    pub fn ticks(&self, file_ticks: u64) -> i64 {
+        let units = file_ticks as u128 * self.tempo as u128 * 10 / self.division.max(1) as u128;
+        units.min(i64::MAX as u128) as i64
+    }
+}
+
 pub fn is_midi_track_header(data: &[u8]) -> bool {
     if data.len() < 4 {
         false
@@ -341,9 +372,8 @@ impl VariableLengthValue {
     #[allow(dead_code)]
     pub fn value_in_range(value: u32) -> Result<u32> {
         if value > Self::MAX_VALUE {
-            return Err(Error::new(
-                windows::Win32::Foundation::DISP_E_OVERFLOW, // hack: reuse IDispatch error code
-                h!("value out of range for MIDI variable-length value").clone(),
+            return Err(Error::FileFormat(
+                "value out of range for MIDI variable-length value".to_string(),
             ));
         }
         Ok(value)
@@ -408,9 +438,8 @@ impl VariableLengthValue {
 
     pub fn finish(&mut self) -> Result<u32> {
         if self.pending {
-            return Err(Error::new(
-                HRESULT(0x8000000Au32 as i32), //E_PENDING
-                h!("need more data for MIDI variable-length value").clone(),
+            return Err(Error::FileFormat(
+                "need more data for MIDI variable-length value".to_string(),
             ));
         }
         let result = self.value;
@@ -444,6 +473,8 @@ pub struct BinBuild {
     encoded: bool,
     binary: Vec<u8>,
     decoder: VariableLengthValue,
+    // a value has been started but not finished
+    partial: bool,
 }
 
 impl BinBuild {
@@ -451,7 +482,8 @@ impl BinBuild {
         BinBuild::default()
     }
     fn flush_decoder(&mut self) {
-        if self.decoder.is_pending() {
+        if self.partial {
+            self.partial = false;
             while self.decoder.add_byte(0) {} //zero pad
             let value = self.decoder.finish().unwrap();
             for decoded_byte in value.to_be_bytes() {
@@ -475,6 +507,7 @@ impl BinBuild {
         self.encoded = encoded;
         self.binary.clear();
         self.decoder.start();
+        self.partial = false;
     }
 
     fn add_raw(&mut self, byte: u8) {
@@ -484,7 +517,8 @@ impl BinBuild {
 
     fn add_encoded(&mut self, byte: u8) {
         debug_assert!(!self.encoded);
-        if !self.decoder.add_byte(byte) {
+        self.partial = self.decoder.add_byte(byte);
+        if !self.partial {
             let value = self.decoder.finish().unwrap();
             for decoded_byte in value.to_be_bytes() {
                 self.binary.push(decoded_byte);
@@ -512,7 +546,25 @@ impl BinBuild {
 
 #[cfg(test)]
 mod tests {
-    use crate::midi::VariableLengthValue;
+    use crate::midi::{TimeBase, VariableLengthValue};
+
+    #[test]
+    fn time_base_conversions_are_inverses() {
+        for time_base in [
+            TimeBase::default(),
+            TimeBase {
+                division: 96,
+                tempo: 600_000,
+            },
+        ] {
+            for file_ticks in [0, 1, 95, 96, 1_000_000, 0x0FFF_FFFF] {
+                assert_eq!(time_base.file_ticks(time_base.ticks(file_ticks)), file_ticks);
+            }
+        }
+        // one tick per millisecond, partial ticks round down
+        assert_eq!(TimeBase::default().file_ticks(19_999), 1);
+        assert_eq!(TimeBase::default().ticks(2), 20_000);
+    }
 
     fn round_trip(value: u32) {
         let mut decoder = VariableLengthValue::new();
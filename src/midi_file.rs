@@ -1,30 +1,50 @@
-use crate::{midi::*, midi_handler::*};
+use crate::{error::Result, midi::*, midi_handler::*, midi_message::MidiMessage};
 
 #[derive(Default)]
 pub struct MidiFile {
     running_status: u8,
     bytes: Vec<u8>,
-    last_tick: i64,
+    time_base: TimeBase,
+    // arrival time of the first message, which is written at file tick 0
+    start_tick: Option<i64>,
+    // file ticks written so far
+    file_ticks: u64,
+    // write every delta time as zero, so the same content gives the same file
+    canonical: bool,
 }
 
 const HEADER_LENGTH: usize = 14;
 const TRACK_HEADER_LENGTH: usize = 8;
+const TEMPO_LENGTH: usize = 7; // delta time of 0 + FF 51 03 + tempo
 const TRACK_END_LENGTH: usize = 4; // (varlen delta time of 0 = 1 byte) + FF 2F 00
 
 impl MidiFile {
+    /// Leave arrival timing out of the file. Loading doesn't use it: the upload is
+    /// paced by the device's replies.
+    pub fn set_canonical(&mut self, canonical: bool) {
+        self.canonical = canonical;
+    }
+
+    /// Division written to the header and tempo written at the start of the track.
+    pub fn set_time_base(&mut self, time_base: TimeBase) {
+        self.time_base = time_base;
+    }
+
     pub fn clear(&mut self) {
         self.bytes.clear();
         self.running_status = 0;
-        self.last_tick = 0;
+        self.start_tick = None;
+        self.file_ticks = 0;
     }
 
     pub fn finish(&mut self) -> Vec<u8> {
-        let data_length = self.bytes.len();
+        let data_length = self.bytes.len() + TEMPO_LENGTH;
         let capacity = HEADER_LENGTH + TRACK_HEADER_LENGTH + data_length + TRACK_END_LENGTH;
 
         let mut result = Vec::with_capacity(capacity);
-        Self::add_file_header(&mut result);
+        Self::add_file_header(&mut result, self.time_base.division);
         Self::add_track_header(&mut result, data_length as u32);
+        Self::add_tempo(&mut result, self.time_base.tempo);
         result.extend_from_slice(&self.bytes[0..]);
         Self::add_end_of_track(&mut result);
         debug_assert_eq!(capacity, result.len()); // if this fires, then capacity needs adjustment
@@ -32,24 +52,28 @@ impl MidiFile {
 
         result
     }
-    fn add_file_header(bytes: &mut Vec<u8>) {
+    fn add_file_header(bytes: &mut Vec<u8>, division: u16) {
         bytes.extend_from_slice(b"MThd");
         Self::add_u32(bytes, 6); // length
         Self::add_word(bytes, 0); // format
         Self::add_word(bytes, 1); // #tracks
-        Self::add_word(bytes, 96); // division
+        Self::add_word(bytes, division); // ticks per quarter note
     }
     fn add_track_header(bytes: &mut Vec<u8>, length: u32) {
         bytes.extend_from_slice(b"MTrk");
         Self::add_u32(bytes, length);
     }
+    fn add_tempo(bytes: &mut Vec<u8>, tempo: u32) {
+        bytes.extend_from_slice(&[0, 0xFF, 0x51, 3]);
+        bytes.extend_from_slice(&tempo.to_be_bytes()[1..]); // microseconds per quarter note
+    }
     fn add_end_of_track(bytes: &mut Vec<u8>) {
         bytes.push(0); // variable length delta time of 0 (same as in test mid files)
         bytes.push(0xFF);
         bytes.push(0x2F);
         bytes.push(0);
     }
-    fn unexpected(msg: &str) -> windows::core::Result<()> {
+    fn unexpected(msg: &str) -> Result<()> {
         println!("Unexpected {msg}");
         Ok(())
     }
@@ -65,27 +89,18 @@ impl MidiFile {
         bytes.extend_from_slice(&value.to_be_bytes());
     }
 
-    fn msec_to_delta_ticks(ms: u64) -> u32 {
-        let dt = ms / 1920;
-        debug_assert!(dt <= u32::MAX as u64);
-        dt as u32
-    }
-    fn next_tick(&self, tick: i64) -> u32 {
-        assert!(tick >= 0);
-        assert!(tick >= self.last_tick);
-        Self::msec_to_delta_ticks((tick - self.last_tick) as u64)
-    }
+    /// Delta time to `tick` (100 ns units). The delta comes from the time since the first
+    /// message, so rounding to whole file ticks doesn't add up over a long save.
     pub fn add_tick(&mut self, tick: i64) {
-        if self.last_tick == 0 {
-            self.last_tick = tick;
+        if self.canonical {
             self.add_var_len(0);
-        } else {
-            let next = self.next_tick(tick);
-            self.add_var_len(next);
-            if next > 0 {
-                self.last_tick = tick;
-            }
+            return;
         }
+        let start = *self.start_tick.get_or_insert(tick);
+        let at = self.time_base.file_ticks(tick - start).max(self.file_ticks);
+        let delta = (at - self.file_ticks).min(VariableLengthValue::MAX_VALUE as u64);
+        self.file_ticks += delta;
+        self.add_var_len(delta as u32);
     }
 
     // fn clear_running_status(&mut self) {
@@ -102,6 +117,12 @@ impl MidiFile {
         self.add_running_status(status);
         self.bytes.push(byte);
     }
+    /// Add a message `delta` file ticks after the previous one, bypassing tick conversion.
+    pub fn add_delta_message(&mut self, delta: u32, message: &MidiMessage) {
+        self.add_var_len(delta);
+        self.add_running_status(message.status());
+        self.bytes.extend(message.data());
+    }
     pub fn add_message_two(&mut self, ticks: i64, status: u8, byte1: u8, byte2: u8) {
         self.add_tick(ticks);
         self.add_running_status(status);
@@ -117,7 +138,7 @@ impl MidiHandler for MidiFile {
         _channel: u8,
         _note: u8,
         _velocity: u8,
-    ) -> windows::core::Result<()> {
+    ) -> Result<()> {
         Self::unexpected("note_off")
     }
     fn on_note_on(
@@ -126,7 +147,7 @@ impl MidiHandler for MidiFile {
         _channel: u8,
         _note: u8,
         _velocity: u8,
-    ) -> windows::core::Result<()> {
+    ) -> Result<()> {
         Self::unexpected("note_on")
     }
     fn on_polyphonic_key_pressure(
@@ -135,7 +156,7 @@ impl MidiHandler for MidiFile {
         channel: u8,
         note: u8,
         pressure: u8,
-    ) -> windows::core::Result<()> {
+    ) -> Result<()> {
         self.add_message_two(ticks, STATUS_POLY_KEY_PRESSURE | channel, note, pressure);
         Ok(())
     }
@@ -146,7 +167,7 @@ impl MidiHandler for MidiFile {
         channel: u8,
         cc: u8,
         value: u8,
-    ) -> windows::core::Result<()> {
+    ) -> Result<()> {
         self.add_message_two(ticks, STATUS_CC | channel, cc, value);
         Ok(())
     }
@@ -155,7 +176,7 @@ impl MidiHandler for MidiFile {
         ticks: i64,
         channel: u8,
         program: u8,
-    ) -> windows::core::Result<()> {
+    ) -> Result<()> {
         self.add_message_one(ticks, STATUS_PROGRAM_CHANGE | channel, program);
         Ok(())
     }
@@ -164,7 +185,7 @@ impl MidiHandler for MidiFile {
         ticks: i64,
         channel: u8,
         pressure: u8,
-    ) -> windows::core::Result<()> {
+    ) -> Result<()> {
         self.add_message_one(ticks, STATUS_CHANNEL_PRESSURE | channel, pressure);
         Ok(())
     }
@@ -173,13 +194,13 @@ impl MidiHandler for MidiFile {
         ticks: i64,
         channel: u8,
         bend: u16,
-    ) -> windows::core::Result<()> {
+    ) -> Result<()> {
         let byte2 = (bend & 0x7F) as u8;
         let byte1 = ((bend & 0x7f80) >> 7) as u8;
         self.add_message_two(ticks, STATUS_PITCH_BEND | channel, byte1, byte2);
         Ok(())
     }
-    fn on_system_exclusive(&mut self, _ticks: i64, _data: Vec<u8>) -> windows::core::Result<()> {
+    fn on_system_exclusive(&mut self, _ticks: i64, _data: Vec<u8>) -> Result<()> {
         Self::unexpected("system_exclusive")
     }
     fn on_midi_time_code(
@@ -187,41 +208,114 @@ impl MidiHandler for MidiFile {
         _ticks: i64,
         _frame: u8,
         _values: u8,
-    ) -> windows::core::Result<()> {
+    ) -> Result<()> {
         Self::unexpected("midi_time_code")
     }
-    fn on_song_position_pointer(&mut self, _ticks: i64, _beats: u16) -> windows::core::Result<()> {
+    fn on_song_position_pointer(&mut self, _ticks: i64, _beats: u16) -> Result<()> {
         Self::unexpected("song_position_pointer")
     }
-    fn on_song_select(&mut self, _ticks: i64, _song: u8) -> windows::core::Result<()> {
+    fn on_song_select(&mut self, _ticks: i64, _song: u8) -> Result<()> {
         Self::unexpected("song_select")
     }
-    fn on_tune_request(&mut self, _ticks: i64) -> windows::core::Result<()> {
+    fn on_tune_request(&mut self, _ticks: i64) -> Result<()> {
         Self::unexpected("tune_request")
     }
     fn on_end_system_exclusive(
         &mut self,
         _ticks: i64,
         _data: Vec<u8>,
-    ) -> windows::core::Result<()> {
+    ) -> Result<()> {
         Self::unexpected("end_system_exclusive")
     }
-    fn on_timing_clock(&mut self, _ticks: i64) -> windows::core::Result<()> {
+    fn on_timing_clock(&mut self, _ticks: i64) -> Result<()> {
         Self::unexpected("timing_clock")
     }
-    fn on_start(&mut self, _ticks: i64) -> windows::core::Result<()> {
+    fn on_start(&mut self, _ticks: i64) -> Result<()> {
         Self::unexpected("start")
     }
-    fn on_continue(&mut self, _ticks: i64) -> windows::core::Result<()> {
+    fn on_continue(&mut self, _ticks: i64) -> Result<()> {
         Self::unexpected("continue")
     }
-    fn on_stop(&mut self, _ticks: i64) -> windows::core::Result<()> {
+    fn on_stop(&mut self, _ticks: i64) -> Result<()> {
         Self::unexpected("stop")
     }
-    fn on_active_sensing(&mut self, _ticks: i64) -> windows::core::Result<()> {
+    fn on_active_sensing(&mut self, _ticks: i64) -> Result<()> {
         Self::unexpected("active_sensing")
     }
-    fn on_system_reset(&mut self, _ticks: i64) -> windows::core::Result<()> {
+    fn on_system_reset(&mut self, _ticks: i64) -> Result<()> {
         Self::unexpected("system_reset")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_midi_file::ReadMidiFile;
+    use std::time::Duration;
+
+    fn save(canonical: bool, start: i64, spacing: i64) -> Vec<u8> {
+        let mut file = MidiFile::default();
+        file.set_canonical(canonical);
+        for cc in 1..=4u8 {
+            let ticks = start + cc as i64 * spacing;
+            file.on_control_change(ticks, CHANNEL15, cc, cc * 10).unwrap();
+        }
+        file.finish()
+    }
+
+    /// Save a control change at each time (100 ns units), then read back the time of each
+    /// from the start of the file.
+    fn round_trip(time_base: TimeBase, times: &[i64]) -> Vec<i64> {
+        let mut file = MidiFile::default();
+        file.set_time_base(time_base);
+        for (index, ticks) in times.iter().enumerate() {
+            file.on_control_change(*ticks, CHANNEL15, 1, index as u8 & 0x7F).unwrap();
+        }
+        let data = file.finish();
+        let mut reader = ReadMidiFile::new(&data);
+        assert_eq!(reader.time_base().unwrap(), Some(time_base));
+        let mut elapsed = Duration::ZERO;
+        let mut result = Vec::new();
+        while let Some((dt, _)) = reader.next().unwrap() {
+            elapsed += dt;
+            result.push((elapsed.as_nanos() / 100) as i64);
+        }
+        result
+    }
+
+    #[test]
+    fn reading_gives_back_the_times_written() {
+        // whole ticks come back exactly: 1 ms by default, 6.25 ms at 96 per quarter and 100 bpm
+        let start = 7_654_321;
+        let times = [0, 10_000, 20_000, 1_230_000, 1_240_000, 600_000_000];
+        let expected: Vec<i64> = times.to_vec();
+        let times: Vec<i64> = times.iter().map(|time| start + time).collect();
+        assert_eq!(round_trip(TimeBase::default(), &times), expected);
+
+        let slow = TimeBase {
+            division: 96,
+            tempo: 600_000,
+        };
+        // times between ticks round down
+        let times = [0, 62_500, 100_000, 6_250_000];
+        let expected = vec![0, 62_500, 62_500, 6_250_000];
+        let times: Vec<i64> = times.iter().map(|time| start + time).collect();
+        assert_eq!(round_trip(slow, &times), expected);
+    }
+
+    #[test]
+    fn rounding_does_not_add_up() {
+        // messages 1.5 ms apart: each lands within a tick of its arrival
+        let times: Vec<i64> = (0..1000).map(|index| index * 15_000).collect();
+        let read = round_trip(TimeBase::default(), &times);
+        for (time, read) in times.iter().zip(read) {
+            assert!((0..10_000).contains(&(time - read)), "{time} read as {read}");
+        }
+    }
+
+    #[test]
+    fn canonical_files_ignore_arrival_times() {
+        assert_ne!(save(false, 1_000, 20_000), save(false, 5_000, 90_000));
+        assert_eq!(save(true, 1_000, 20_000), save(true, 5_000, 90_000));
+    }
+}
@@ -0,0 +1,234 @@
+use crate::{midi::*, util::*};
+
+/// One complete MIDI message, independent of any platform MIDI API.
+///
+/// Channels are zero-based (see `CHANNEL16`). System exclusive data includes
+/// the leading status byte, as it is received.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MidiMessage {
+    NoteOff { channel: u8, note: u8, velocity: u8 },
+    NoteOn { channel: u8, note: u8, velocity: u8 },
+    PolyKeyPressure { channel: u8, note: u8, pressure: u8 },
+    ControlChange { channel: u8, cc: u8, value: u8 },
+    ProgramChange { channel: u8, program: u8 },
+    ChannelPressure { channel: u8, pressure: u8 },
+    PitchBend { channel: u8, bend: u16 },
+    SystemExclusive(Vec<u8>),
+    MidiTimeCode { frame: u8, values: u8 },
+    SongPositionPointer(u16),
+    SongSelect(u8),
+    TuneRequest,
+    EndSystemExclusive(Vec<u8>),
+    TimingClock,
+    Start,
+    Continue,
+    Stop,
+    ActiveSensing,
+    SystemReset,
+}
+
+/// A message with the time it was received.
+///
+/// `ticks` are in 100ns units (the WinRT timestamp unit), relative to an
+/// arbitrary start point chosen by the transport.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TimedMessage {
+    pub ticks: i64,
+    pub message: MidiMessage,
+}
+
+impl MidiMessage {
+    pub fn cc(channel: u8, cc: u8, value: u8) -> Self {
+        Self::ControlChange { channel, cc, value }
+    }
+
+    pub fn program_change(channel: u8, program: u8) -> Self {
+        Self::ProgramChange { channel, program }
+    }
+
+    pub fn channel_pressure(channel: u8, pressure: u8) -> Self {
+        Self::ChannelPressure { channel, pressure }
+    }
+
+    /// Decode one complete message (status byte first, no running status).
+    ///
+    /// Missing data bytes read as zero. Returns None for an empty message,
+    /// a stray data byte, or an undefined system status.
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let &status = data.first()?;
+        let byte = |index: usize| data.get(index).copied().unwrap_or_default() & 0x7F;
+        let channel = lo_nybble(status);
+        let message = match hi_nybble(status) {
+            STATUS_NOTE_OFF => Self::NoteOff {
+                channel,
+                note: byte(1),
+                velocity: byte(2),
+            },
+            STATUS_NOTE_ON => Self::NoteOn {
+                channel,
+                note: byte(1),
+                velocity: byte(2),
+            },
+            STATUS_POLY_KEY_PRESSURE => Self::PolyKeyPressure {
+                channel,
+                note: byte(1),
+                pressure: byte(2),
+            },
+            STATUS_CC => Self::cc(channel, byte(1), byte(2)),
+            STATUS_PROGRAM_CHANGE => Self::program_change(channel, byte(1)),
+            STATUS_CHANNEL_PRESSURE => Self::channel_pressure(channel, byte(1)),
+            STATUS_PITCH_BEND => Self::PitchBend {
+                channel,
+                bend: u16_from_midi_bytes(byte(1), byte(2)),
+            },
+            STATUS_SYSTEM => match status {
+                0xF0 => Self::SystemExclusive(data.to_vec()),
+                0xF1 => Self::MidiTimeCode {
+                    frame: (byte(1) & 0x70) >> 4,
+                    values: byte(1) & 0x0F,
+                },
+                0xF2 => Self::SongPositionPointer(u16_from_midi_bytes(byte(1), byte(2))),
+                0xF3 => Self::SongSelect(byte(1)),
+                0xF6 => Self::TuneRequest,
+                0xF7 => Self::EndSystemExclusive(data.to_vec()),
+                0xF8 => Self::TimingClock,
+                0xFA => Self::Start,
+                0xFB => Self::Continue,
+                0xFC => Self::Stop,
+                0xFE => Self::ActiveSensing,
+                0xFF => Self::SystemReset,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(message)
+    }
+
+    /// The status byte, including the channel for channel messages.
+    pub fn status(&self) -> u8 {
+        match self {
+            Self::NoteOff { channel, .. } => STATUS_NOTE_OFF | channel,
+            Self::NoteOn { channel, .. } => STATUS_NOTE_ON | channel,
+            Self::PolyKeyPressure { channel, .. } => STATUS_POLY_KEY_PRESSURE | channel,
+            Self::ControlChange { channel, .. } => STATUS_CC | channel,
+            Self::ProgramChange { channel, .. } => STATUS_PROGRAM_CHANGE | channel,
+            Self::ChannelPressure { channel, .. } => STATUS_CHANNEL_PRESSURE | channel,
+            Self::PitchBend { channel, .. } => STATUS_PITCH_BEND | channel,
+            Self::SystemExclusive(_) => 0xF0,
+            Self::MidiTimeCode { .. } => 0xF1,
+            Self::SongPositionPointer(_) => 0xF2,
+            Self::SongSelect(_) => 0xF3,
+            Self::TuneRequest => 0xF6,
+            Self::EndSystemExclusive(_) => 0xF7,
+            Self::TimingClock => 0xF8,
+            Self::Start => 0xFA,
+            Self::Continue => 0xFB,
+            Self::Stop => 0xFC,
+            Self::ActiveSensing => 0xFE,
+            Self::SystemReset => 0xFF,
+        }
+    }
+
+    /// Zero-based channel of a channel message.
+    pub fn channel(&self) -> Option<u8> {
+        match self {
+            Self::NoteOff { channel, .. }
+            | Self::NoteOn { channel, .. }
+            | Self::PolyKeyPressure { channel, .. }
+            | Self::ControlChange { channel, .. }
+            | Self::ProgramChange { channel, .. }
+            | Self::ChannelPressure { channel, .. }
+            | Self::PitchBend { channel, .. } => Some(*channel),
+            _ => None,
+        }
+    }
+
+    /// The data bytes following the status byte.
+    pub fn data(&self) -> Vec<u8> {
+        match self {
+            Self::NoteOff { note, velocity, .. } | Self::NoteOn { note, velocity, .. } => {
+                vec![*note, *velocity]
+            }
+            Self::PolyKeyPressure { note, pressure, .. } => vec![*note, *pressure],
+            Self::ControlChange { cc, value, .. } => vec![*cc, *value],
+            Self::ProgramChange { program, .. } => vec![*program],
+            Self::ChannelPressure { pressure, .. } => vec![*pressure],
+            Self::PitchBend { bend, .. } | Self::SongPositionPointer(bend) => {
+                vec![(bend & 0x7F) as u8, ((bend >> 7) & 0x7F) as u8]
+            }
+            Self::SystemExclusive(data) | Self::EndSystemExclusive(data) => {
+                data.iter().skip(1).copied().collect()
+            }
+            Self::MidiTimeCode { frame, values } => vec![((frame & 0x07) << 4) | (values & 0x0F)],
+            Self::SongSelect(song) => vec![*song],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Encode as wire bytes (status byte first).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.status()];
+        bytes.extend(self.data());
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_messages_round_trip() {
+        let messages = [
+            MidiMessage::NoteOn {
+                channel: 0,
+                note: 60,
+                velocity: 100,
+            },
+            MidiMessage::cc(CHANNEL16, 109, 32),
+            MidiMessage::program_change(CHANNEL15, 127),
+            MidiMessage::channel_pressure(CHANNEL16, b'A'),
+            MidiMessage::PitchBend {
+                channel: 3,
+                bend: 0x2001,
+            },
+        ];
+        for message in messages {
+            assert_eq!(MidiMessage::from_bytes(&message.to_bytes()), Some(message));
+        }
+    }
+
+    #[test]
+    fn decodes_status_and_channel() {
+        let message = MidiMessage::from_bytes(&[0xBF, 56, 127]).unwrap();
+        assert_eq!(message, MidiMessage::cc(CHANNEL16, 56, 127));
+        assert_eq!(message.status(), 0xBF);
+        assert_eq!(message.channel(), Some(CHANNEL16));
+        assert_eq!(message.data(), vec![56, 127]);
+    }
+
+    #[test]
+    fn system_messages() {
+        let sysex = [0xF0, 0x7D, 0x01, 0xF7];
+        assert_eq!(
+            MidiMessage::from_bytes(&sysex),
+            Some(MidiMessage::SystemExclusive(sysex.to_vec()))
+        );
+        assert_eq!(
+            MidiMessage::SystemExclusive(sysex.to_vec()).to_bytes(),
+            sysex
+        );
+        assert_eq!(
+            MidiMessage::from_bytes(&[0xF8]),
+            Some(MidiMessage::TimingClock)
+        );
+        assert_eq!(MidiMessage::TimingClock.channel(), None);
+    }
+
+    #[test]
+    fn rejects_incomplete_input() {
+        assert_eq!(MidiMessage::from_bytes(&[]), None);
+        assert_eq!(MidiMessage::from_bytes(&[0x40, 0x00]), None);
+        assert_eq!(MidiMessage::from_bytes(&[0xF4]), None);
+    }
+}
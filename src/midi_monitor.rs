@@ -1,9 +1,9 @@
 use crate::{cc_text::*, gather_state::GatherState, haken_midi::cc16};
 use crate::{
     continuum_preset::*, data_kind::DataKind, midi::*, midi_handler::*, midi_traits::*,
-    util::make_hex_string,
+    stream_decode::StreamCollector, util::make_hex_string,
 };
-use windows::core::*;
+use crate::error::Result;
 
 const PITCH_BEND_CENTER: u16 = 8192;
 const PITCH_BEND_CENTER_EX: i64 = ((PITCH_BEND_CENTER as u64) << 7) as i64;
@@ -14,8 +14,7 @@ fn mpe_pitch_bend(bend: u16, lsb: u16) -> i64 {
 
 pub struct MidiMonitor {
     gather: GatherState,
-    bin_type: DataKind,
-    binbuild: BinBuild,
+    streams: StreamCollector,
     preset_builder: PresetBuilder,
     cc_text: CcText,
     catcode: HCCategoryCode,
@@ -35,8 +34,7 @@ impl MidiMonitor {
     pub fn new() -> Self {
         Self {
             gather: GatherState::None,
-            bin_type: DataKind::Unknown,
-            binbuild: BinBuild::default(),
+            streams: StreamCollector::default(),
             preset_builder: PresetBuilder::default(),
             cc_text: CcText::default(),
             catcode: HCCategoryCode::default(),
@@ -167,10 +165,10 @@ impl MidiMonitor {
             127 => self.end_stream(),
             _ => {
                 // other data
-                self.bin_type = DataKind::new(value);
-                if self.bin_type == DataKind::Unknown {
+                if DataKind::new(value) == DataKind::Unknown {
                     println!("?Binary data {value}");
                 }
+                self.streams.stream_control(value);
                 self.gather = GatherState::Binary;
             }
         }
@@ -178,14 +176,9 @@ impl MidiMonitor {
     fn end_stream(&mut self) {
         match self.gather {
             GatherState::Binary => {
-                let data = self.binbuild.flush();
-                println!(
-                    "Binary data {}: {} [{}]",
-                    self.bin_type.name(),
-                    data.len(),
-                    make_hex_string(&data)
-                );
-                self.bin_type = DataKind::Unknown;
+                if let Some(stream) = self.streams.stream_control(cc16::DataStream_End) {
+                    println!("Binary data {}", stream.describe());
+                }
             }
             GatherState::None | GatherState::Name | GatherState::Text | GatherState::Category => {}
         };
@@ -330,7 +323,7 @@ impl MidiHandler for MidiMonitor {
                     self.preset_builder.category_add(pressure as char);
                 }
                 GatherState::Binary => {
-                    self.binbuild.add(pressure);
+                    self.streams.add(pressure);
                 }
                 _ => {
                     println!(
@@ -1,16 +1,15 @@
 use std::sync::mpsc::*;
 
-use crate::{acquire_device::*, midi_handler::*, thread_control::*};
-use windows::{core::*, Devices::Midi::*, Foundation::*, Win32::Foundation::E_FAIL};
+use crate::{acquire_device::*, error::Result, midi_message::TimedMessage, thread_control::*};
 
 pub struct MidiSource {
-    tx: Sender<WinMidi>,
+    tx: Sender<TimedMessage>,
     rx: Receiver<ThreadControl>,
     in_port: InPortDescription,
 }
 
 impl MidiSource {
-    pub fn new(tx: Sender<WinMidi>, rx: Receiver<ThreadControl>, input: InPortDescription) -> Self {
+    pub fn new(tx: Sender<TimedMessage>, rx: Receiver<ThreadControl>, input: InPortDescription) -> Self {
         Self {
             tx,
             rx,
@@ -18,31 +17,10 @@ impl MidiSource {
         }
     }
 
-    fn hook_events(&self) -> windows::core::Result<()> {
-        let tx = self.tx.clone();
-        self.in_port.port.MessageReceived(&TypedEventHandler::new(
-            move |_, arg: &Option<MidiMessageReceivedEventArgs>| {
-                if arg.is_some() {
-                    let imsg = unsafe { arg.as_ref().unwrap_unchecked().Message()? };
-                    match tx.send(concrete_message(&imsg)) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            let message = e.to_string();
-                            println!("Channel tx failed: {message}");
-                            return Err(windows::core::Error::new(E_FAIL, HSTRING::from(message)));
-                        }
-                    }
-                };
-                Ok(())
-            },
-        ))?;
-        Ok(())
-    }
-
-    pub fn run(&self) -> windows::core::Result<()> {
-        self.hook_events()?;
+    pub fn run(mut self) -> Result<()> {
+        self.in_port.port.listen(self.tx.clone())?;
         _ = self.rx.recv(); // any signal stops thread
-        _ = self.in_port.port.Close();
+        self.in_port.port.close();
         Ok(())
     }
 }
@@ -1,5 +1,10 @@
-use crate::util::count_leading;
-use std::{env, path::*};
+use crate::{
+    error::{Error, Result},
+    library::check_query,
+    util::count_leading,
+    watchdog::RetryPolicy,
+};
+use std::{env, ffi::OsString, path::*, time::Duration};
 
 #[derive(Copy, Clone, PartialEq)]
 #[allow(dead_code)]
@@ -10,16 +15,71 @@ pub enum Action {
     Monitor,
     ListMidi,
     ListNames,
+    ListSystem,
     SaveCurrent,
     Save,
     Load,
     Clear,
+    Inspect,
+    Diff,
+    Export,
+    Import,
+    Index,
+    Search,
+}
+
+/// An option waiting for its value, the argument after it.
+#[derive(Copy, Clone, PartialEq)]
+enum PendingValue {
+    Device,
+    Record,
+    Replay,
+    Backup,
+    Timeout,
+    Retries,
+    Query,
+    Library,
+    Categories,
+}
+
+impl PendingValue {
+    fn missing(self) -> Error {
+        let option = match self {
+            PendingValue::Device => "--device",
+            PendingValue::Record => "--record",
+            PendingValue::Replay => "--replay",
+            PendingValue::Backup => "--backup",
+            PendingValue::Timeout => "--timeout",
+            PendingValue::Retries => "--retries",
+            PendingValue::Query => "--search",
+            PendingValue::Library => "--library",
+            PendingValue::Categories => "--categories",
+        };
+        Error::Usage(format!("{option} needs a value"))
+    }
 }
 
 pub struct Options {
     pub action: Action,
     path: Option<PathBuf>,
+    // second file: the other archive for --diff, the output of --export/--import
+    pub other_path: Option<PathBuf>,
     pub device: Option<String>,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub retry: RetryPolicy,
+    pub with_system: bool,
+    pub verify: bool,
+    pub canonical: bool,
+    pub lenient: bool,
+    pub dry_run: bool,
+    pub backup: Option<PathBuf>,
+    pub no_backup: bool,
+    // folders to scan for --index
+    pub folders: Vec<PathBuf>,
+    pub query: Option<String>,
+    pub library: Option<PathBuf>,
+    pub categories: Option<PathBuf>,
 }
 
 impl Options {
@@ -41,15 +101,69 @@ For example, '-d Mini' is often sufficient to find a ContinuuMini, even if
 other EaganMatrix devices are connected. If no device name is given, the
 first suitable device is used.
 
+'-d sim' uses a built-in EaganMatrix simulator instead of a MIDI device,
+for trying harkive out without hardware. Its presets last only for the run.
+
+--record <file>  (-r) Write every MIDI message to and from the device to a
+                 session file, to reproduce a problem elsewhere.
+--replay <file>  Use a recorded session file in place of the device. The
+                 session answers what is sent just as the device did, and
+                 the run stops at the first message sent that differs from
+                 the recording.
+
+--timeout <seconds>  How long to wait on each reply from the device before
+                 sending the request again. By default each step of the
+                 exchange has its own timeout, from 2 to 10 seconds.
+--retries <n>    How many times to re-send before giving up (default 2).
+--with-system    With --save, also save the system (factory) presets.
+--verify         With --load, read each preset back after it is saved and
+                 compare it with the file sent.
+--canonical      With --save or --edit, write preset files without the
+                 timing of the messages as they arrived, so saving the same
+                 preset always gives the same file. Loading is unaffected.
+--lenient        With --load, read a preset list as the Haken Editor does,
+                 stopping at the first line that can't be used. Otherwise
+                 every bad line, slot number and missing preset file is
+                 reported, and nothing is loaded.
+--dry-run   (-n) With --load or --clear, print which slots would be
+                 overwritten, and from which files, without using the device.
+--backup <folder>  Before --load or --clear, the user presets are saved to a
+                 new timestamped folder in <folder>, "harkive-backups" in the
+                 current folder by default. Nothing is changed on the device
+                 if the backup fails.
+--no-backup      Skip the backup before --load or --clear.
+--categories <file>  Add the preset categories in a text file (see
+                 "Category tables" in doc/harkive.md) to the table built into
+                 harkive, for codes added by newer firmware.
+--library <file>  The library index for --index and --search,
+                 "harkive-index.json" in the current folder by default.
+
 <action> is one of:
 
 --input    (-i) Print list of connected MIDI devices.
 --monitor  (-m) Log MIDI received from the selected device.
 --clear    (-c) Clear all user presets from the device.
 --print    (-p) Print list of user presets.
+--system   (-y) Print list of system presets, grouped by category bank.
 --edit     (-e) Save current editing slot.
 --save     (-s) Save user presets from the device to <path>.
 --load     (-l) Load user presets from <path> to the device.
+--inspect  (-x) Print what is in the preset .mid file at <path>, or in each
+                 preset .mid file in a folder. No device is used.
+--diff <file> <file>  Compare two preset .mid files: name, text, categories,
+                 data streams and matrix settings. Exits with 0 when they are
+                 the same and 10 when they differ. No device is used.
+--export <file> [<output>]  Write a preset .mid file as a JSON document, to
+                 <output> or to the same name with a .json extension.
+--import <file> [<output>]  Write the preset .mid file for a JSON document
+                 made by --export, to <output> or the same name with .mid.
+--index <folder>...  Add the preset .mid files in the folders, and the
+                 folders inside them, to the library index. Links to folders
+                 are not followed. No device is used.
+--search <query> [<folder>]  Print the presets in the library index that
+                 match <query>. With <folder>, which must be empty, the
+                 matching presets are copied there with a UserPresets.txt,
+                 ready for --load.
 --help     (-h, -?) Help. The short forms print short help.
 
 Preset lists are similar to Haken Editor group lists.
@@ -58,6 +172,21 @@ Preset lists are similar to Haken Editor group lists.
 
 --input, --monitor, and --clear do not use <path>.
 
+--search: <query> is a list of words, all of which must match. A word
+matches a preset whose name contains it or which has a category, type,
+character, matrix or setting of that name. "name", "file", "category",
+"type", "character", "matrix" and "setting" followed by a word match only
+that; "name:pad" is the same as "name pad". "all", "with", "and", "contains",
+"is" and "in" are ignored, so 'all Strings with character Bright' and
+'name contains pad' can be used as written. Use "double quotes" around
+names with spaces.
+
+--inspect: <path> is a preset .mid file or a folder of them. Prints the name,
+control text, categories, data streams and message counts of each.
+
+--print, --system: When <path> is given, it is the folder for a preset list,
+"UserPresets.txt" or "SystemPresets.txt".
+
 <path> is required to load or save. The folder of the path must exist on disk.
 
 <path> can generally be either a file path or a folder. When no file name is
@@ -65,6 +194,8 @@ given, a default name is assumed or generated.
 
 --save: When <path> is a file path, it is a preset list and the preset .mid
 files go to the same folder. The default file name is "UserPresets.txt".
+With --with-system, the system presets are saved as well, to a "System"
+folder next to the user presets, listed in "SystemPresets.txt".
 
 --edit: If <path> ends with <name>.mid, the editing slot midi data is written
 to that filename. Otherwise, <path> is a folder. If the slot is unnamed or
@@ -84,136 +215,274 @@ contains a UserPresets.txt file, that list file is used. Otherwise all preset
 
     pub fn usage() {
         println!(
-            r#"harkive [--device <name>] <action> [<path>]
+            r#"harkive [--device <name>] [--record|--replay <file>] <action> [<path>]
 
 --device   (-d) Name of device to save/restore from.
+--record   (-r) Record the device session to <file>.
+--replay        Replay a recorded session <file> in place of the device.
+--timeout       Seconds to wait on each device reply before re-sending.
+--retries       Times to re-send before giving up (default 2).
+--with-system   With --save, also save system presets.
+--verify        With --load, read back and check each preset.
+--canonical     With --save or --edit, write the same file for the same preset.
+--lenient       With --load, stop at the first bad preset list line.
+--dry-run  (-n) With --load or --clear, print the plan and change nothing.
+--backup        Folder for backups made before --load and --clear.
+--no-backup     Skip the backup before --load and --clear.
+--categories    Read preset categories from a table <file>.
+--library       Library index file for --index and --search.
 --input    (-i) Print list of connected MIDI devices.
 --monitor  (-m) Log MIDI received from the selected device.
 --clear    (-c) Clear all user presets from the device.
 --print    (-p) Print list of user presets.
+--system   (-y) Print list of system presets.
 --edit     (-e) Save current editing slot.
 --save     (-s) Save user presets from the device to <path>.
 --load     (-l) Load user presets from <path> to the device.
+--inspect  (-x) Print what is in preset .mid files, without a device.
+--diff          Compare two preset .mid files, without a device.
+--export        Write a preset .mid file as a JSON document.
+--import        Write a preset .mid file from a JSON document.
+--index         Add folders of preset .mid files to the library index.
+--search        Find presets in the library index, without a device.
 --help     (-h, -?) Print help info. The short forms print this summary info.
 "#
         );
     }
 
-    pub fn validate(&self) -> bool {
+    pub fn validate(&self) -> Result<()> {
+        let missing = |message: &str| Err(Error::Usage(message.to_string()));
+        if self.dry_run && !matches!(self.action, Action::Load | Action::Clear) {
+            return missing("--dry-run only applies to --load and --clear.");
+        }
+        if self.lenient && self.action != Action::Load {
+            return missing("--lenient only applies to --load.");
+        }
+        if self.verify && self.action != Action::Load {
+            return missing("--verify only applies to --load.");
+        }
+        if self.with_system && self.action != Action::Save {
+            return missing("--with-system only applies to --save.");
+        }
+        if self.canonical && !matches!(self.action, Action::Save | Action::SaveCurrent) {
+            return missing("--canonical only applies to --save and --edit.");
+        }
+        if (self.backup.is_some() || self.no_backup) && !matches!(self.action, Action::Load | Action::Clear) {
+            return missing("--backup and --no-backup only apply to --load and --clear.");
+        }
         match self.action {
             Action::Nothing
             | Action::Usage
             | Action::Docs
             | Action::ListMidi
             | Action::ListNames
-            | Action::Monitor => true,
-
-            Action::Save | Action::SaveCurrent | Action::Load => {
-                if self.path.is_none() {
-                    println!("Missing folder to save/restore to/from.");
-                    false
-                } else {
-                    true
-                }
+            | Action::ListSystem
+            | Action::Monitor
+            | Action::Clear => Ok(()),
+
+            Action::Save | Action::SaveCurrent | Action::Load if self.path.is_none() => {
+                missing("Missing folder to save/restore to/from.")
+            }
+            Action::Inspect if self.path.is_none() => {
+                missing("Missing preset file or folder to inspect.")
             }
-            Action::Clear => true,
+            Action::Diff if self.other_path.is_none() => missing("--diff needs two preset files."),
+            Action::Export | Action::Import if self.path.is_none() => {
+                missing("Missing file to convert.")
+            }
+            Action::Index if self.folders.is_empty() => missing("Missing folder to index."),
+            Action::Search => match &self.query {
+                None => missing("Missing search query."),
+                Some(query) => check_query(query),
+            },
+            _ => Ok(()),
         }
     }
 
-    fn set_action(&mut self, act: Action) -> bool {
+    fn set_action(&mut self, act: Action) -> Result<()> {
         match self.action {
             Action::Nothing | Action::Usage | Action::Docs => {
                 self.action = act;
-                true
-            }
-            _ => {
-                println!("Only one action per run can be used.");
-                false
+                Ok(())
             }
+            _ => Err(Error::Usage("Only one action per run can be used.".to_string())),
         }
     }
 
-    pub fn get_options() -> Option<Self> {
+    pub fn get_options() -> Result<Self> {
+        Self::parse_args(env::args_os().skip(1))
+    }
+
+    fn parse_args(args: impl Iterator<Item = OsString>) -> Result<Self> {
         let mut options = Self::default();
-        let mut expect_device = false;
-        for arg in env::args_os().skip(1) {
+        let mut pending: Option<PendingValue> = None;
+        for arg in args {
+            if let Some(value) = pending.take() {
+                options.set_value(value, &arg)?;
+                continue;
+            }
             if let Ok(sarg) = arg.clone().into_string() {
                 match &sarg[0..] {
                     "--help" | "--doc" | "--man" => {
                         options.action = Action::Docs;
-                        return Some(options);
+                        return Ok(options);
                     }
                     "-h" | "-?" => {
                         options.action = Action::Usage;
-                        return Some(options);
+                        return Ok(options);
                     }
                     "--print" | "-p" => {
-                        if !options.set_action(Action::ListNames) {
-                            return None;
-                        }
+                        options.set_action(Action::ListNames)?;
+                    }
+                    "--system" | "-y" => {
+                        options.set_action(Action::ListSystem)?;
                     }
                     "--monitor" | "-m" => {
-                        if !options.set_action(Action::Monitor) {
-                            return None;
-                        }
+                        options.set_action(Action::Monitor)?;
                     }
                     "--input" | "-i" => {
-                        if !options.set_action(Action::ListMidi) {
-                            return None;
-                        }
+                        options.set_action(Action::ListMidi)?;
                     }
                     "--save" | "-s" => {
-                        if !options.set_action(Action::Save) {
-                            return None;
-                        }
+                        options.set_action(Action::Save)?;
                     }
                     "--load" | "-l" => {
-                        if !options.set_action(Action::Load) {
-                            return None;
-                        }
+                        options.set_action(Action::Load)?;
                     }
                     "--edit" | "-e" => {
-                        if !options.set_action(Action::SaveCurrent) {
-                            return None;
-                        }
+                        options.set_action(Action::SaveCurrent)?;
                     }
                     "--clear" | "-c" => {
-                        if !options.set_action(Action::Clear) {
-                            return None;
-                        }
+                        options.set_action(Action::Clear)?;
+                    }
+                    "--inspect" | "-x" => {
+                        options.set_action(Action::Inspect)?;
+                    }
+                    "--diff" => {
+                        options.set_action(Action::Diff)?;
+                    }
+                    "--export" => {
+                        options.set_action(Action::Export)?;
+                    }
+                    "--import" => {
+                        options.set_action(Action::Import)?;
+                    }
+                    "--index" => {
+                        options.set_action(Action::Index)?;
+                    }
+                    "--search" => {
+                        options.set_action(Action::Search)?;
+                        pending = Some(PendingValue::Query);
+                    }
+                    "--library" => {
+                        pending = Some(PendingValue::Library);
+                    }
+                    "--categories" => {
+                        pending = Some(PendingValue::Categories);
                     }
                     "--device" | "-d" => {
-                        expect_device = true;
+                        pending = Some(PendingValue::Device);
+                    }
+                    "--record" | "-r" => {
+                        pending = Some(PendingValue::Record);
+                    }
+                    "--replay" => {
+                        pending = Some(PendingValue::Replay);
+                    }
+                    "--timeout" => {
+                        pending = Some(PendingValue::Timeout);
+                    }
+                    "--with-system" => {
+                        options.with_system = true;
+                    }
+                    "--verify" => {
+                        options.verify = true;
+                    }
+                    "--canonical" => {
+                        options.canonical = true;
+                    }
+                    "--lenient" => {
+                        options.lenient = true;
+                    }
+                    "--dry-run" | "-n" => {
+                        options.dry_run = true;
+                    }
+                    "--backup" => {
+                        pending = Some(PendingValue::Backup);
+                    }
+                    "--no-backup" => {
+                        options.no_backup = true;
+                    }
+                    "--retries" => {
+                        pending = Some(PendingValue::Retries);
                     }
                     _ => {
                         if count_leading('-', &sarg[0..]) > 0 {
-                            println!("Unknown option {sarg}");
-                            return None;
-                        } else {
-                            if expect_device {
-                                options.device = Some(arg.to_string_lossy().to_string());
-                                expect_device = false;
-                                continue;
-                            }
-                            let path = Path::new(&arg);
-                            match path.canonicalize() {
-                                Ok(path) => {
-                                    options.path = Some(path);
-                                }
-                                Err(error) => {
-                                    println!("Error: {error}");
-                                    return None;
-                                }
-                            };
+                            return Err(Error::Usage(format!("Unknown option {sarg}")));
+                        }
+                        let path = Path::new(&arg);
+                        if options.path.is_some()
+                            && matches!(options.action, Action::Export | Action::Import)
+                        {
+                            // an output file, which need not exist yet
+                            options.other_path = Some(path.to_path_buf());
+                            continue;
                         }
+                        match path.canonicalize() {
+                            Ok(path) if options.path.is_some() && options.action == Action::Diff => {
+                                options.other_path = Some(path);
+                            }
+                            Ok(path) if options.action == Action::Index => {
+                                options.folders.push(path);
+                            }
+                            Ok(path) => {
+                                options.path = Some(path);
+                            }
+                            Err(error) => {
+                                return Err(Error::Io(format!("{}: {error}", path.display())));
+                            }
+                        };
                     }
                 }
             }
         }
-        if options.validate() {
-            Some(options)
-        } else {
-            None
+        if let Some(value) = pending {
+            return Err(value.missing());
+        }
+        options.validate()?;
+        Ok(options)
+    }
+
+    /// The value of the option before it. Only a search query may start with '-'.
+    fn set_value(&mut self, value: PendingValue, arg: &OsString) -> Result<()> {
+        let text = arg.to_string_lossy();
+        if value != PendingValue::Query && text.starts_with('-') {
+            return Err(value.missing());
+        }
+        match value {
+            PendingValue::Device => self.device = Some(text.to_string()),
+            PendingValue::Record => self.record = Some(PathBuf::from(arg)),
+            PendingValue::Replay => self.replay = Some(PathBuf::from(arg)),
+            PendingValue::Backup => self.backup = Some(PathBuf::from(arg)),
+            PendingValue::Library => self.library = Some(PathBuf::from(arg)),
+            PendingValue::Categories => self.categories = Some(PathBuf::from(arg)),
+            PendingValue::Query => self.query = Some(text.to_string()),
+            PendingValue::Timeout => self.retry.timeout = Some(parse_timeout(&text)?),
+            PendingValue::Retries => match text.parse::<u32>() {
+                Ok(retries) => self.retry.retries = retries,
+                Err(_) => {
+                    return Err(Error::Usage(format!("Invalid retry count '{text}'")));
+                }
+            },
+        }
+        Ok(())
+    }
+    #[cfg(test)]
+    pub fn with_path(action: Action, path: &Path) -> Self {
+        Self {
+            action,
+            path: Some(path.to_path_buf()),
+            ..Self::default()
         }
     }
     pub fn get_path(&self) -> Option<PathBuf> {
@@ -234,7 +503,83 @@ impl Default for Options {
         Self {
             action: Action::Usage,
             path: None,
+            other_path: None,
             device: None,
+            record: None,
+            replay: None,
+            retry: RetryPolicy::default(),
+            with_system: false,
+            verify: false,
+            canonical: false,
+            lenient: false,
+            dry_run: false,
+            backup: None,
+            no_backup: false,
+            folders: Vec::new(),
+            query: None,
+            library: None,
+            categories: None,
+        }
+    }
+}
+
+/// A --timeout in seconds: more than zero, and short enough for a `Duration`.
+fn parse_timeout(text: &str) -> Result<Duration> {
+    text.parse::<f64>()
+        .ok()
+        .filter(|seconds| *seconds > 0.0)
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| Error::Usage(format!("Invalid timeout '{text}' (seconds)")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options> {
+        Options::parse_args(args.split(' ').map(OsString::from))
+    }
+
+    #[test]
+    fn options_need_their_values() {
+        let options = parse("-d sim -p --timeout 5 --retries 3").unwrap();
+        assert_eq!(options.device.as_deref(), Some("sim"));
+        assert_eq!(options.retry.timeout, Some(Duration::from_secs(5)));
+        assert_eq!(options.retry.retries, 3);
+        let needs = |option: &str| Some(Error::Usage(format!("{option} needs a value")));
+        assert_eq!(parse("-p --device").err(), needs("--device"));
+        assert_eq!(parse("-d sim -p --device --timeout 5").err(), needs("--device"));
+        assert_eq!(parse("--timeout -1 -p").err(), needs("--timeout"));
+        assert_eq!(parse("-p --retries").err(), needs("--retries"));
+        // a query may start with '-'
+        assert_eq!(parse("--search -pad").unwrap().query.as_deref(), Some("-pad"));
+    }
+
+    #[test]
+    fn flags_only_go_with_their_actions() {
+        let usage = |message: &str| Some(Error::Usage(message.to_string()));
+        assert_eq!(parse("--verify -p").err(), usage("--verify only applies to --load."));
+        assert_eq!(parse("--with-system -y").err(), usage("--with-system only applies to --save."));
+        assert_eq!(
+            parse("--canonical -c").err(),
+            usage("--canonical only applies to --save and --edit.")
+        );
+        assert_eq!(
+            parse("--no-backup -p").err(),
+            usage("--backup and --no-backup only apply to --load and --clear.")
+        );
+        assert!(parse("--no-backup -c").is_ok());
+    }
+
+    #[test]
+    fn timeouts_are_positive_and_finite() {
+        assert_eq!(parse_timeout("2.5"), Ok(Duration::from_millis(2500)));
+        for text in ["0", "-1", "inf", "NaN", "1e30", "five"] {
+            assert_eq!(
+                parse_timeout(text),
+                Err(Error::Usage(format!("Invalid timeout '{text}' (seconds)"))),
+                "{text}"
+            );
         }
     }
 }
@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+const MIN_BURST: usize = 8;
+const START_BURST: usize = 32;
+const MAX_BURST: usize = 512;
+// round trips this much over the best seen are not counted as congestion
+const JITTER: Duration = Duration::from_millis(5);
+
+/// Flow control for sending a preset archive to the device.
+///
+/// Archive data goes out in bursts, each followed by EditorPresent, and the
+/// next burst waits for the EditorReply. Quick round trips grow the burst;
+/// slow ones, or a lost reply, shrink it.
+pub struct Pacer {
+    burst: usize,
+    best_rtt: Option<Duration>,
+}
+
+impl Default for Pacer {
+    fn default() -> Self {
+        Self {
+            burst: START_BURST,
+            best_rtt: None,
+        }
+    }
+}
+
+impl Pacer {
+    /// Number of messages to send before waiting on the device.
+    pub fn burst(&self) -> usize {
+        self.burst
+    }
+
+    /// The device answered a burst after `rtt`.
+    pub fn acknowledged(&mut self, rtt: Duration) {
+        let best = match self.best_rtt {
+            Some(best) if best <= rtt => best,
+            _ => {
+                self.best_rtt = Some(rtt);
+                rtt
+            }
+        };
+        if rtt > best * 2 + JITTER {
+            self.back_off();
+        } else {
+            self.burst = (self.burst * 2).min(MAX_BURST);
+        }
+    }
+
+    /// The device fell behind or a reply was lost.
+    pub fn back_off(&mut self) {
+        self.burst = (self.burst / 2).max(MIN_BURST);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grows_on_quick_replies_and_shrinks_on_slow_ones() {
+        let mut pacer = Pacer::default();
+        for _ in 0..10 {
+            pacer.acknowledged(Duration::from_millis(3));
+        }
+        assert_eq!(pacer.burst(), MAX_BURST);
+        pacer.acknowledged(Duration::from_millis(40));
+        assert_eq!(pacer.burst(), MAX_BURST / 2);
+        // small jitter over the best round trip is fine
+        pacer.acknowledged(Duration::from_millis(9));
+        assert_eq!(pacer.burst(), MAX_BURST);
+    }
+
+    #[test]
+    fn back_off_keeps_a_minimum_burst() {
+        let mut pacer = Pacer::default();
+        for _ in 0..10 {
+            pacer.back_off();
+        }
+        assert_eq!(pacer.burst(), MIN_BURST);
+    }
+}
@@ -0,0 +1,134 @@
+use crate::{
+    data_kind::DataKind,
+    error::{Error, Result},
+    haken_midi::cc16,
+    midi::CHANNEL16,
+    midi_message::MidiMessage,
+    read_midi_file::ReadMidiFile,
+    stream_decode::*,
+};
+use std::{collections::BTreeMap, path::Path};
+
+/// A saved preset archive (.mid), decoded without a device.
+pub struct PresetArchive {
+    pub messages: Vec<MidiMessage>,
+    pub streams: Vec<DataStream>,
+}
+
+fn message_kind(message: &MidiMessage) -> &'static str {
+    match message {
+        MidiMessage::NoteOff { .. } => "Note Off",
+        MidiMessage::NoteOn { .. } => "Note On",
+        MidiMessage::PolyKeyPressure { .. } => "Poly Key Pressure",
+        MidiMessage::ControlChange { .. } => "Control Change",
+        MidiMessage::ProgramChange { .. } => "Program Change",
+        MidiMessage::ChannelPressure { .. } => "Channel Pressure",
+        MidiMessage::PitchBend { .. } => "Pitch Bend",
+        _ => "System",
+    }
+}
+
+impl PresetArchive {
+    pub fn read(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path)
+            .map_err(|error| Error::Io(format!("{}: {error}", path.display())))?;
+        Self::parse(&data).map_err(|error| error.context(&path.display().to_string()))
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut file = ReadMidiFile::new(data);
+        let mut messages = Vec::new();
+        while let Some((_, message)) = file.next()? {
+            messages.push(message);
+        }
+        Ok(Self::from_messages(messages))
+    }
+
+    pub fn from_messages(messages: Vec<MidiMessage>) -> Self {
+        let streams = decode_streams(&messages);
+        Self { messages, streams }
+    }
+
+    /// Text of the first stream of `kind`, or empty.
+    fn stream_text(&self, kind: DataKind) -> String {
+        self.streams
+            .iter()
+            .find(|stream| stream.kind == kind)
+            .and_then(|stream| stream.data.text().map(str::to_string))
+            .unwrap_or_default()
+    }
+
+    pub fn name(&self) -> String {
+        self.stream_text(DataKind::Name)
+    }
+
+    pub fn text(&self) -> String {
+        self.stream_text(DataKind::ControlText)
+    }
+
+    pub fn category(&self) -> String {
+        self.stream_text(DataKind::Category)
+    }
+
+    /// Number of messages by channel (1-16, 0 for system messages) and kind.
+    pub fn message_counts(&self) -> BTreeMap<(u8, &'static str), usize> {
+        let mut counts = BTreeMap::new();
+        for message in &self.messages {
+            let channel = message.channel().map_or(0, |channel| channel + 1);
+            *counts.entry((channel, message_kind(message))).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Messages outside the ch16 data streams: matrix settings and the like.
+    pub fn control_messages(&self) -> Vec<MidiMessage> {
+        let mut in_stream = false;
+        let mut result = Vec::new();
+        for message in &self.messages {
+            match *message {
+                MidiMessage::ControlChange { channel, cc, value }
+                    if channel == CHANNEL16 && cc == cc16::DataStream =>
+                {
+                    in_stream = value != cc16::DataStream_End;
+                }
+                MidiMessage::ChannelPressure { channel, .. } if channel == CHANNEL16 && in_stream => {}
+                _ => result.push(message.clone()),
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        midi::CHANNEL15, midi_file::MidiFile, midi_handler::dispatch_midi,
+        midi_message::TimedMessage, stream_decode::stream_messages,
+    };
+
+    #[test]
+    fn reads_a_saved_archive() {
+        let mut messages = stream_messages(cc16::DataStream_Name, b"Sine Pad");
+        messages.extend(stream_messages(cc16::DataStream_Text, b"C=OT_SU"));
+        messages.push(MidiMessage::cc(CHANNEL15, 1, 13));
+        messages.push(MidiMessage::cc(CHANNEL15, 2, 26));
+        let mut file = MidiFile::default();
+        for (tick, message) in messages.iter().enumerate() {
+            let message = TimedMessage {
+                ticks: tick as i64 * 10_000,
+                message: message.clone(),
+            };
+            dispatch_midi(&mut file, &message).unwrap();
+        }
+        let archive = PresetArchive::parse(&file.finish()).unwrap();
+        assert_eq!(archive.name(), "Sine Pad");
+        assert_eq!(archive.text(), "C=OT_SU");
+        assert_eq!(archive.category(), "");
+        assert_eq!(archive.streams.len(), 2);
+        assert_eq!(archive.control_messages().len(), 2);
+        let counts = archive.message_counts();
+        assert_eq!(counts.get(&(15, "Control Change")), Some(&2));
+        assert_eq!(counts.get(&(16, "Channel Pressure")), Some(&15));
+    }
+}
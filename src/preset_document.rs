@@ -0,0 +1,530 @@
+use crate::{
+    data_kind::DataKind,
+    error::{Error, Result},
+    haken_midi::cc16,
+    json::Json,
+    midi::{TimeBase, CHANNEL16},
+    midi_file::MidiFile,
+    midi_message::MidiMessage,
+    midi_traits::Named,
+    options::Options,
+    preset_archive::PresetArchive,
+    read_midi_file::ReadMidiFile,
+    stream_decode::{StreamCollector, StreamData},
+    util::is_extension,
+};
+use std::path::{Path, PathBuf};
+
+// Preset documents are JSON, one archive message (or whole data stream) per line:
+//
+//   {
+//     "harkive": 1,
+//     "name": "Sine Pad",
+//     "text": "C=OT_SU",
+//     "category": "",
+//     "bank": [0, 0],
+//     "program": null,
+//     "division": 500,
+//     "tempo": 500000,
+//     "messages": [
+//       {"stream": "Name", "text": "Sine Pad"},
+//       {"stream": "Graph", "data": [1, 8, 15, 22]},
+//       {"dt": 2, "cc": [15, 1, 14]},
+//       {"program": [16, 3]},
+//       {"midi": [160, 60, 1]}
+//     ]
+//   }
+//
+// "messages" is the archive itself; the fields above it summarize it and must agree.
+// "division" (ticks per quarter note) and "tempo" (microseconds per quarter note) give the
+// file's time base. Channels are 1-16. "dt" is the delta time in file ticks, left out when zero.
+// A stream with any nonzero delta inside it lists them all in "dts" (one per byte, then the end).
+// Text streams are written as "text"; "data" is the 7-bit bytes of other streams, and of
+// text streams that don't decode exactly (see stream_json).
+const DOCUMENT_VERSION: i64 = 1;
+
+fn field(name: &str, value: Json) -> (String, Json) {
+    (name.to_string(), value)
+}
+
+fn number(value: u8) -> Json {
+    Json::Number(value as i64)
+}
+
+fn bytes_json(bytes: &[u8]) -> Json {
+    Json::Array(bytes.iter().map(|byte| number(*byte)).collect())
+}
+
+const STREAM_FIELDS: [&str; 2] = ["text", "data"];
+
+/// The field a stream of `kind` is written to, decoded.
+fn stream_field(kind: DataKind) -> &'static str {
+    match kind {
+        DataKind::Name
+        | DataKind::ControlText
+        | DataKind::Log
+        | DataKind::Category
+        | DataKind::DemoAssort => "text",
+        _ => "data",
+    }
+}
+
+/// Decoded stream data as JSON, or None for data that only decodes to bytes.
+fn decoded_json(data: &StreamData) -> Option<Json> {
+    data.text().map(|text| Json::String(text.to_string()))
+}
+
+fn kind_from_name(name: &str) -> Option<u8> {
+    (0..=DataKind::Convolution as u8).find(|raw| DataKind::new(*raw).name() == name)
+}
+
+fn message_json(delta: u32, message: &MidiMessage) -> Json {
+    let mut fields = Vec::new();
+    if delta != 0 {
+        fields.push(field("dt", Json::Number(delta as i64)));
+    }
+    fields.push(match *message {
+        MidiMessage::ControlChange { channel, cc, value } => {
+            field("cc", bytes_json(&[channel + 1, cc, value]))
+        }
+        MidiMessage::ProgramChange { channel, program } => {
+            field("program", bytes_json(&[channel + 1, program]))
+        }
+        _ => field("midi", bytes_json(&message.to_bytes())),
+    });
+    Json::Object(fields)
+}
+
+/// A complete data stream starting at `events[start]`, as one entry, with the number of
+/// messages it covers. None if the stream isn't closed before some other message.
+fn stream_json(events: &[(u32, MidiMessage)], start: usize) -> Option<(Json, usize)> {
+    let (delta, message) = &events[start];
+    let mut collector = StreamCollector::default();
+    collector.add_message(message);
+    let mut dts = Vec::new();
+    let mut ended = None;
+    for (delta, message) in &events[start + 1..] {
+        if !collector.continues(message) {
+            return None;
+        }
+        dts.push(*delta);
+        ended = collector.add_message(message);
+        if ended.is_some() {
+            break;
+        }
+    }
+    let stream = ended.filter(|stream| stream.kind != DataKind::Unknown)?;
+    let mut fields = Vec::new();
+    if *delta != 0 {
+        fields.push(field("dt", Json::Number(*delta as i64)));
+    }
+    fields.push(field("stream", Json::String(stream.kind.name().to_string())));
+    // Import rebuilds the stream from its decoded data, so the raw bytes are kept when that
+    // wouldn't give these bytes back. The check reads the value back as written.
+    let bytes = stream.data.encode();
+    let name = stream_field(stream.kind);
+    let decoded = decoded_json(&stream.data).filter(|value| {
+        let written = Json::parse(&value.to_compact()).ok();
+        written.and_then(|value| field_bytes(name, &value, 0).ok()).as_ref() == Some(&bytes)
+    });
+    match decoded {
+        Some(value) => fields.push(field(name, value)),
+        None => fields.push(field("data", bytes_json(&bytes))),
+    }
+    if dts.iter().any(|delta| *delta != 0) {
+        let dts = dts.iter().map(|delta| Json::Number(*delta as i64)).collect();
+        fields.push(field("dts", Json::Array(dts)));
+    }
+    Some((Json::Object(fields), stream.size + 2))
+}
+
+/// Last ch16 value of bank select `cc`, or ch16 program change when `cc` is None.
+fn bank_value(events: &[(u32, MidiMessage)], cc: Option<u8>) -> Option<u8> {
+    events.iter().rev().find_map(|(_, message)| match (message, cc) {
+        (MidiMessage::ControlChange { channel, cc, value }, Some(wanted))
+            if *channel == CHANNEL16 && *cc == wanted =>
+        {
+            Some(*value)
+        }
+        (MidiMessage::ProgramChange { channel, program }, None) if *channel == CHANNEL16 => {
+            Some(*program)
+        }
+        _ => None,
+    })
+}
+
+fn summary_fields(events: &[(u32, MidiMessage)]) -> Vec<(String, Json)> {
+    let archive = PresetArchive::from_messages(events.iter().map(|(_, message)| message.clone()).collect());
+    let bank = match (
+        bank_value(events, Some(cc16::BankSelect)),
+        bank_value(events, Some(cc16::PresetGroup)),
+    ) {
+        (Some(hi), Some(lo)) => bytes_json(&[hi, lo]),
+        _ => Json::Null,
+    };
+    vec![
+        field("harkive", Json::Number(DOCUMENT_VERSION)),
+        field("name", Json::String(archive.name())),
+        field("text", Json::String(archive.text())),
+        field("category", Json::String(archive.category())),
+        field("bank", bank),
+        field("program", bank_value(events, None).map_or(Json::Null, number)),
+    ]
+}
+
+fn read_events(data: &[u8]) -> Result<Vec<(u32, MidiMessage)>> {
+    let mut file = ReadMidiFile::new(data);
+    let mut events = Vec::new();
+    while let Some(event) = file.next_event()? {
+        events.push(event);
+    }
+    Ok(events)
+}
+
+/// The preset document for a .mid archive.
+pub fn export_document(data: &[u8]) -> Result<String> {
+    let events = read_events(data)?;
+    let time_base = ReadMidiFile::new(data).time_base()?.ok_or_else(|| {
+        Error::FileFormat("SMPTE time can't be kept in a preset document".to_string())
+    })?;
+    let mut text = String::from("{\n");
+    for (name, value) in summary_fields(&events) {
+        text += &format!("  \"{name}\": {},\n", value.to_compact());
+    }
+    text += &format!("  \"division\": {},\n", time_base.division);
+    text += &format!("  \"tempo\": {},\n", time_base.tempo);
+    text += "  \"messages\": [";
+    let mut index = 0;
+    let mut first = true;
+    while index < events.len() {
+        let (entry, count) = stream_json(&events, index)
+            .unwrap_or_else(|| (message_json(events[index].0, &events[index].1), 1));
+        text += if first { "\n    " } else { ",\n    " };
+        text += &entry.to_compact();
+        first = false;
+        index += count;
+    }
+    text += if first { "]\n}\n" } else { "\n  ]\n}\n" };
+    Ok(text)
+}
+
+fn entry_error(index: usize, message: &str) -> Error {
+    Error::FileFormat(format!("messages[{index}]: {message}"))
+}
+
+fn entry_delta(entry: &Json, index: usize) -> Result<u32> {
+    match entry.get("dt") {
+        None => Ok(0),
+        Some(dt) => dt
+            .as_i64()
+            .and_then(|dt| u32::try_from(dt).ok())
+            .ok_or_else(|| entry_error(index, "\"dt\" must be a tick count")),
+    }
+}
+
+fn data_bytes(value: &Json, index: usize, what: &str) -> Result<Vec<u8>> {
+    let error = || entry_error(index, &format!("\"{what}\" must be a list of bytes"));
+    let items = value.as_array().ok_or_else(error)?;
+    items
+        .iter()
+        .map(|item| item.as_i64().and_then(|byte| u8::try_from(byte).ok()).ok_or_else(error))
+        .collect()
+}
+
+fn seven_bit(bytes: &[u8], index: usize, what: &str) -> Result<()> {
+    if bytes.iter().any(|byte| *byte > 0x7F) {
+        Err(entry_error(index, &format!("\"{what}\" values must be 0-127")))
+    } else {
+        Ok(())
+    }
+}
+
+fn channel_message<'a>(bytes: &'a [u8], index: usize, what: &str) -> Result<(u8, &'a [u8])> {
+    match bytes.split_first() {
+        Some((channel @ 1..=16, rest)) => {
+            seven_bit(rest, index, what)?;
+            Ok((channel - 1, rest))
+        }
+        _ => Err(entry_error(index, &format!("\"{what}\" needs a channel 1-16"))),
+    }
+}
+
+/// The stream bytes for one stream field, from its decoded (or raw) data.
+fn field_bytes(name: &str, value: &Json, index: usize) -> Result<Vec<u8>> {
+    let error = |what: &str| entry_error(index, &format!("\"{name}\" must be {what}"));
+    let bytes = match name {
+        "text" => {
+            let text = value.as_str().ok_or_else(|| error("a string"))?;
+            if !text.is_ascii() {
+                return Err(error("ASCII"));
+            }
+            text.bytes().collect()
+        }
+        _ => data_bytes(value, index, name)?,
+    };
+    seven_bit(&bytes, index, name)?;
+    Ok(bytes)
+}
+
+fn stream_events(entry: &Json, index: usize, delta: u32) -> Result<Vec<(u32, MidiMessage)>> {
+    let name = entry.get("stream").and_then(Json::as_str).unwrap_or_default();
+    let kind = kind_from_name(name)
+        .ok_or_else(|| entry_error(index, &format!("unknown stream \"{name}\"")))?;
+    let decoded = stream_field(DataKind::new(kind));
+    let present: Vec<&str> = STREAM_FIELDS
+        .into_iter()
+        .filter(|field| entry.get(field).is_some())
+        .collect();
+    let bytes = match present[..] {
+        [field] if field == decoded || field == "data" => {
+            field_bytes(field, entry.get(field).unwrap_or(&Json::Null), index)?
+        }
+        _ if decoded == "data" => {
+            return Err(entry_error(index, &format!("a {name} stream needs \"data\"")))
+        }
+        _ => {
+            return Err(entry_error(
+                index,
+                &format!("a {name} stream needs \"{decoded}\" or \"data\""),
+            ))
+        }
+    };
+    let dts = match entry.get("dts") {
+        None => vec![0; bytes.len() + 1],
+        Some(dts) => {
+            let items = dts.as_array().unwrap_or_default();
+            let dts: Vec<u32> = items
+                .iter()
+                .filter_map(|dt| dt.as_i64().and_then(|dt| u32::try_from(dt).ok()))
+                .collect();
+            if dts.len() != bytes.len() + 1 || dts.len() != items.len() {
+                return Err(entry_error(index, "\"dts\" needs a tick count per byte, then the end"));
+            }
+            dts
+        }
+    };
+    let mut events = vec![(delta, MidiMessage::cc(CHANNEL16, cc16::DataStream, kind))];
+    for (byte, dt) in bytes.iter().zip(dts.iter()) {
+        events.push((*dt, MidiMessage::channel_pressure(CHANNEL16, *byte)));
+    }
+    events.push((
+        dts[bytes.len()],
+        MidiMessage::cc(CHANNEL16, cc16::DataStream, cc16::DataStream_End),
+    ));
+    Ok(events)
+}
+
+fn entry_events(entry: &Json, index: usize) -> Result<Vec<(u32, MidiMessage)>> {
+    let delta = entry_delta(entry, index)?;
+    if entry.get("stream").is_some() {
+        return stream_events(entry, index, delta);
+    }
+    let message = if let Some(cc) = entry.get("cc") {
+        let bytes = data_bytes(cc, index, "cc")?;
+        match channel_message(&bytes, index, "cc")? {
+            (channel, [cc, value]) => MidiMessage::cc(channel, *cc, *value),
+            _ => return Err(entry_error(index, "\"cc\" is [channel, cc, value]")),
+        }
+    } else if let Some(program) = entry.get("program") {
+        let bytes = data_bytes(program, index, "program")?;
+        match channel_message(&bytes, index, "program")? {
+            (channel, [program]) => MidiMessage::program_change(channel, *program),
+            _ => return Err(entry_error(index, "\"program\" is [channel, program]")),
+        }
+    } else if let Some(midi) = entry.get("midi") {
+        let bytes = data_bytes(midi, index, "midi")?;
+        match MidiMessage::from_bytes(&bytes) {
+            Some(message) if message.channel().is_some() && message.to_bytes() == bytes => message,
+            _ => return Err(entry_error(index, "\"midi\" is not a channel message")),
+        }
+    } else {
+        return Err(entry_error(index, "expected \"stream\", \"cc\", \"program\" or \"midi\""));
+    };
+    Ok(vec![(delta, message)])
+}
+
+/// The document's "division" and "tempo", with the default time base for any left out.
+fn document_time_base(document: &Json) -> Result<TimeBase> {
+    let default = TimeBase::default();
+    let value = |name: &str, default: u32, max: u32| match document.get(name) {
+        None => Ok(default),
+        Some(value) => value
+            .as_i64()
+            .and_then(|value| u32::try_from(value).ok())
+            .filter(|value| (1..=max).contains(value))
+            .ok_or_else(|| Error::FileFormat(format!("\"{name}\" must be from 1 to {max}"))),
+    };
+    Ok(TimeBase {
+        // a negative division would mean SMPTE time
+        division: value("division", default.division as u32, 0x7FFF)? as u16,
+        tempo: value("tempo", default.tempo, 0xFF_FFFF)?,
+    })
+}
+
+/// Rebuild the .mid archive a preset document describes.
+pub fn import_document(text: &str) -> Result<Vec<u8>> {
+    let document = Json::parse(text)?;
+    match document.get("harkive").and_then(Json::as_i64) {
+        Some(DOCUMENT_VERSION) => {}
+        _ => {
+            return Err(Error::FileFormat(
+                "Not a harkive preset document (version 1)".to_string(),
+            ))
+        }
+    }
+    let entries = document
+        .get("messages")
+        .and_then(Json::as_array)
+        .ok_or_else(|| Error::FileFormat("Missing \"messages\" list".to_string()))?;
+    let mut events = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        events.extend(entry_events(entry, index)?);
+    }
+    for (name, value) in summary_fields(&events) {
+        if document.get(&name) != Some(&value) {
+            return Err(Error::FileFormat(format!(
+                "\"{name}\" should be {} to match the messages",
+                value.to_compact()
+            )));
+        }
+    }
+    let mut file = MidiFile::default();
+    file.set_time_base(document_time_base(&document)?);
+    for (delta, message) in &events {
+        file.add_delta_message(*delta, message);
+    }
+    Ok(file.finish())
+}
+
+/// `path` with its extension changed, for an output file that wasn't given.
+fn default_output(path: &Path, extension: &str) -> PathBuf {
+    path.with_extension(extension)
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|error| Error::Io(format!("{}: {error}", path.display())))
+}
+
+fn write_file(path: &Path, data: &[u8]) -> Result<()> {
+    std::fs::write(path, data).map_err(|error| Error::Io(format!("{}: {error}", path.display())))?;
+    println!("Wrote '{}'", path.display());
+    Ok(())
+}
+
+/// Write the preset document for a .mid archive.
+pub fn export(options: &Options) -> Result<()> {
+    let Some(path) = options.get_path() else {
+        return Err(Error::Usage("Missing preset file to export".to_string()));
+    };
+    let output = options
+        .other_path
+        .clone()
+        .unwrap_or_else(|| default_output(&path, "json"));
+    let text = export_document(&read_file(&path)?)
+        .map_err(|error| error.context(&path.display().to_string()))?;
+    write_file(&output, text.as_bytes())
+}
+
+/// Write the .mid archive for a preset document.
+pub fn import(options: &Options) -> Result<()> {
+    let Some(path) = options.get_path() else {
+        return Err(Error::Usage("Missing preset document to import".to_string()));
+    };
+    let output = options
+        .other_path
+        .clone()
+        .unwrap_or_else(|| default_output(&path, "mid"));
+    if !is_extension(&output, "mid") {
+        return Err(Error::Usage(format!(
+            "'{}' should be a .mid file",
+            output.display()
+        )));
+    }
+    let text = String::from_utf8(read_file(&path)?)
+        .map_err(|_| Error::FileFormat(format!("{}: not UTF-8 text", path.display())))?;
+    let data = import_document(&text).map_err(|error| error.context(&path.display().to_string()))?;
+    write_file(&output, &data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        midi::CHANNEL15, midi_handler::dispatch_midi, midi_message::TimedMessage,
+        stream_decode::stream_messages,
+    };
+
+    /// An archive as `MatrixHandler` saves one: received messages through `MidiFile`.
+    fn saved_archive(messages: &[(i64, MidiMessage)]) -> Vec<u8> {
+        let mut file = MidiFile::default();
+        for (ticks, message) in messages {
+            let message = TimedMessage {
+                ticks: *ticks,
+                message: message.clone(),
+            };
+            dispatch_midi(&mut file, &message).unwrap();
+        }
+        file.finish()
+    }
+
+    fn stream(messages: &mut Vec<(i64, MidiMessage)>, ticks: &mut i64, kind: u8, bytes: &[u8]) {
+        for message in stream_messages(kind, bytes) {
+            messages.push((*ticks, message));
+            *ticks += 5_000;
+        }
+    }
+
+    fn sample() -> Vec<u8> {
+        let mut messages = Vec::new();
+        let mut ticks = 1_000_000;
+        messages.push((ticks, MidiMessage::cc(CHANNEL16, cc16::BankSelect, 0)));
+        messages.push((ticks, MidiMessage::cc(CHANNEL16, cc16::PresetGroup, 0)));
+        stream(&mut messages, &mut ticks, cc16::DataStream_Name, b"Sine \"Pad\"");
+        stream(&mut messages, &mut ticks, cc16::DataStream_Text, b"C=OT_SU\x01");
+        for cc in 1..=8u8 {
+            ticks += 20_000;
+            messages.push((ticks, MidiMessage::cc(CHANNEL15, cc, cc * 13)));
+        }
+        stream(&mut messages, &mut ticks, cc16::DataStream_Graph, &[1, 8, 15, 127]);
+        stream(&mut messages, &mut ticks, cc16::DataStream_Kinetic, &[0x03, 0x7E, 0, 0, 0]);
+        // a stream cut short is kept message by message
+        messages.push((ticks, MidiMessage::cc(CHANNEL16, cc16::DataStream, cc16::DataStream_Float)));
+        messages.push((ticks, MidiMessage::cc(CHANNEL15, 9, 1)));
+        saved_archive(&messages)
+    }
+
+    #[test]
+    fn saved_archives_round_trip() {
+        let data = sample();
+        let document = export_document(&data).unwrap();
+        assert!(document.contains("{\"stream\": \"Name\", \"text\": \"Sine \\\"Pad\\\"\""));
+        assert!(document.contains("\"bank\": [0, 0],"));
+        assert!(document.contains("\"stream\": \"Graph\", \"data\": [1, 8, 15, 127]"));
+        assert!(document.contains("\"stream\": \"Kinetic\", \"data\": [3, 126, 0, 0, 0]"));
+        assert_eq!(import_document(&document).unwrap(), data);
+    }
+
+    #[test]
+    fn documents_keep_the_time_base() {
+        let document = export_document(&sample()).unwrap();
+        assert!(document.contains("  \"division\": 500,\n  \"tempo\": 500000,\n"));
+        let slow = document.replacen("\"division\": 500", "\"division\": 96", 1);
+        let data = import_document(&slow).unwrap();
+        assert_eq!(export_document(&data).unwrap(), slow);
+        let bad = document.replacen("\"tempo\": 500000", "\"tempo\": 0", 1);
+        assert!(import_document(&bad).is_err());
+    }
+
+    #[test]
+    fn documents_must_agree_with_their_messages() {
+        let document = export_document(&sample()).unwrap();
+        let edited = document.replacen("\"name\": \"Sine", "\"name\": \"Cosine", 1);
+        let error = import_document(&edited).unwrap_err();
+        assert!(error.message().starts_with("\"name\" should be"));
+        let edited = document.replacen("[1, 8, 15, 127]", "[1, 8, 15, 128]", 1);
+        assert!(import_document(&edited).is_err());
+        let edited = document.replacen("\"data\": [1, 8, 15, 127]", "\"points\": [[136, 2047]]", 1);
+        let error = import_document(&edited).unwrap_err();
+        assert!(error.message().ends_with("a Graph stream needs \"data\""));
+    }
+}
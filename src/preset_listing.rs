@@ -1,90 +1,317 @@
 use crate::continuum_preset::*;
-use std::{
-    fs::File,
-    io::{self, BufRead},
-    path::*,
-};
-use windows::{core::*, Win32::Foundation::E_FAIL};
-
-pub fn save_preset_listing(presets: &[ContinuumPreset], folder: Option<PathBuf>) {
+use std::path::*;
+use crate::error::{Error, Result};
+
+/// A listing line, `3,"Tin Whistle.mid"`. Quotes in the name are doubled, as in CSV, so
+/// names with commas and quotes read back the same; other names are written as the
+/// Haken Editor writes them.
+pub fn listing_line(number: usize, file_name: &str) -> String {
+    format!("{number},\"{}\"\n", file_name.replace('"', "\"\""))
+}
+
+pub fn save_preset_listing(presets: &[ContinuumPreset], folder: Option<PathBuf>) -> Result<()> {
     if let Some(folder) = folder {
         let mut text = String::new();
         for preset in presets.iter().rev() {
-            text += &format!("{},\"{}.mid\"\n", 1 + preset.number, preset.name);
+            text += &listing_line(1 + preset.number as usize, &format!("{}.mid", preset.name));
         }
-        let mut path = folder;
-        path.push("UserPresets.txt");
-        match std::fs::write(&path, text) {
-            Ok(_) => {
-                println!("Saved preset list: '{}'", path.to_string_lossy());
-            }
-            Err(error) => {
-                println!(
-                    "Unable to save preset list '{}': {}",
-                    path.to_string_lossy(),
-                    error
-                );
+        write_listing(folder.join("UserPresets.txt"), text)?;
+    }
+    Ok(())
+}
+
+/// System presets are numbered in listing order, as their program numbers
+/// repeat from one cat-code bank to the next.
+pub fn save_system_listing(presets: &[ContinuumPreset], folder: Option<PathBuf>) -> Result<()> {
+    if let Some(folder) = folder {
+        let mut text = String::new();
+        for (index, preset) in presets.iter().enumerate() {
+            text += &listing_line(1 + index, &format!("{}.mid", preset.name));
+        }
+        write_listing(folder.join("SystemPresets.txt"), text)?;
+    }
+    Ok(())
+}
+
+fn write_listing(path: PathBuf, text: String) -> Result<()> {
+    match std::fs::write(&path, text) {
+        Ok(_) => {
+            println!("Saved preset list: '{}'", path.to_string_lossy());
+            Ok(())
+        }
+        Err(error) => Err(Error::Io(format!(
+            "Unable to save preset list '{}': {}",
+            path.to_string_lossy(),
+            error
+        ))),
+    }
+}
+
+/// How to treat a listing with lines that can't be used.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ListingMode {
+    /// Report every bad line, slot number and missing preset file, and use none of it.
+    Strict,
+    /// Stop at the first bad line, as the Haken Editor does, and use the lines before it.
+    Lenient,
+}
+
+pub fn read_preset_listing(
+    path: &PathBuf,
+    mode: ListingMode,
+    presets: &mut Vec<ContinuumPreset>,
+) -> Result<()> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|error| Error::Io(format!("{}: {error}", path.to_string_lossy())))?;
+    parse_listing(&text, &path.to_string_lossy(), mode, path.parent(), presets)
+}
+
+/// Parse the text of a listing; `source` names it in errors.
+pub fn parse_preset_listing(
+    text: &str,
+    source: &str,
+    mode: ListingMode,
+    presets: &mut Vec<ContinuumPreset>,
+) -> Result<()> {
+    parse_listing(text, source, mode, None, presets)
+}
+
+/// The file name field of a line: quoted, with `""` for a quote, or up to the next comma.
+/// Spaces are kept inside quotes and trimmed outside them. Strict parsing won't cut a name
+/// without quotes at a comma.
+fn listed_name(field: &str, mode: ListingMode) -> std::result::Result<String, String> {
+    let space: &[char] = &[' ', '\t'];
+    let field = field.trim_start_matches(space);
+    let Some(quoted) = field.strip_prefix('"') else {
+        let name = field.split(',').next().unwrap_or_default();
+        if mode == ListingMode::Strict && name.len() < field.len() {
+            return Err(format!("'{field}' has a comma, so needs quotes"));
+        }
+        return Ok(name.trim_matches(space).to_string());
+    };
+    let mut name = String::new();
+    let mut chars = quoted.chars();
+    loop {
+        match chars.next() {
+            None => return Err("no closing quote".to_string()),
+            Some('"') if chars.as_str().starts_with('"') => {
+                chars.next();
+                name.push('"');
             }
+            Some('"') => break,
+            Some(ch) => name.push(ch),
         }
     }
+    let rest = chars.as_str().trim_matches(space);
+    if !rest.is_empty() && !rest.starts_with(',') {
+        return Err(format!("'{rest}' after the closing quote"));
+    }
+    Ok(name)
 }
 
-fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
-where
-    P: AsRef<Path>,
-{
-    let file = File::open(filename)?;
-    Ok(io::BufReader::new(file).lines())
+/// The slot number and preset name on one line: `3,"Tin Whistle.mid"`.
+fn parse_line(line: &str, mode: ListingMode) -> std::result::Result<(u8, String), String> {
+    let Some((number, name)) = line.split_once(',') else {
+        return Err(format!("expected <number>,<file name>, found '{line}'"));
+    };
+    let number = number.trim();
+    let number = number
+        .parse::<u8>()
+        .map_err(|_| format!("'{number}' is not a preset number"))?;
+    let name = listed_name(name, mode)?;
+    let name = name.strip_suffix(".mid").unwrap_or(&name);
+    if name.is_empty() {
+        return Err("no preset file name".to_string());
+    }
+    Ok((number, name.to_string()))
 }
 
-// Matches HakenEditor behavior where encountering an invalid line
-// silently stops parsing.
-pub fn read_preset_listing(path: &PathBuf, presets: &mut Vec<ContinuumPreset>) -> Result<()> {
+/// Strict parsing also checks slot numbers, and that the files are in `folder` when given.
+fn parse_listing(
+    text: &str,
+    source: &str,
+    mode: ListingMode,
+    folder: Option<&Path>,
+    presets: &mut Vec<ContinuumPreset>,
+) -> Result<()> {
     let mut builder = PresetBuilder::default();
-    match read_lines(path) {
-        Ok(lines) => {
-            let string_trim: &[char] = &[' ', '\t', '"'];
-            for line in lines {
-                match line {
-                    Ok(line) => {
-                        builder.start();
-                        let mut pieces = line.split(',');
-                        if let Some(mut s) = pieces.next() {
-                            s = s.trim();
-                            match s.parse::<u8>() {
-                                Ok(n) => {
-                                    builder.set_number(n);
-                                }
-                                Err(error) => {
-                                    return Err(Error::new(E_FAIL, HSTRING::from(error.to_string())));
-                                }
-                            }
-                        } else {
-                            break;
-                        }
-                        if let Some(mut s) = pieces.next() {
-                            s = s.trim_matches(string_trim);
-                            if s.ends_with(".mid") {
-                                builder.add_name_chars(&s[0..s.len()-4]);
-                            } else {
-                                builder.add_name_chars(s);
-                            }
-                            if let Some(preset) = builder.finish() {
-                                presets.push(preset);
-                            } else {
-                                unreachable!();
-                            }
-                        } else {
-                            break;
-                        }
-                    }
-                    Err(error) => {
-                        return Err(Error::new(E_FAIL, HSTRING::from(error.to_string())));
-                    }
+    let mut problems = Vec::new();
+    let mut slot_lines = [0usize; 129];
+    let mut listed = Vec::new();
+    let mut blank_line = None;
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        // a blank line ends the list in the Haken Editor, so only blank lines at the end
+        // are no mistake
+        if line.trim().is_empty() {
+            if mode == ListingMode::Lenient {
+                break;
+            }
+            blank_line = blank_line.or(Some(line_number));
+            continue;
+        }
+        if let Some(blank) = blank_line.take() {
+            problems.push(format!("line {blank}: a blank line ends the list before line {line_number}"));
+        }
+        let (number, name) = match (parse_line(line, mode), mode) {
+            (Ok(entry), _) => entry,
+            (Err(reason), ListingMode::Strict) => {
+                problems.push(format!("line {line_number}: {reason}"));
+                continue;
+            }
+            (Err(reason), ListingMode::Lenient) => {
+                println!("Stopped reading '{source}' at line {line_number}: {reason}");
+                break;
+            }
+        };
+        if mode == ListingMode::Strict {
+            if !(1..=128).contains(&number) {
+                problems.push(format!("line {line_number}: slot {number} is not from 1 to 128"));
+                continue;
+            }
+            match slot_lines[number as usize] {
+                0 => slot_lines[number as usize] = line_number,
+                first => {
+                    problems.push(format!("line {line_number}: slot {number} is also on line {first}"));
+                    continue;
                 }
             }
+            if let Some(folder) = folder {
+                if !folder.join(format!("{name}.mid")).is_file() {
+                    problems.push(format!("line {line_number}: '{name}.mid' is not in the folder"));
+                    continue;
+                }
+            }
+        }
+        builder.start();
+        builder.set_number(number);
+        builder.add_name_chars(&name);
+        // a number the builder can't hold ends the list too
+        match builder.finish() {
+            Some(preset) => listed.push(preset),
+            None => {
+                println!("Stopped reading '{source}' at line {line_number}: slot {number} can't be used");
+                break;
+            }
         }
-        Err(error) => return Err(Error::new(E_FAIL, HSTRING::from(error.to_string()))),
     }
+    if !problems.is_empty() {
+        return Err(Error::Listing(format!(
+            "{source}: {} line(s) can't be used, so none are (--lenient uses the lines before the first):\n  {}",
+            problems.len(),
+            problems.join("\n  ")
+        )));
+    }
+    presets.extend(listed);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_quoted_names() {
+        let mut presets = Vec::new();
+        let text = "3,\"Tin Whistle.mid\"\n1, Sine Pad\n";
+        parse_preset_listing(text, "test", ListingMode::Strict, &mut presets).unwrap();
+        let names: Vec<(u8, &str)> = presets.iter().map(|p| (p.number, p.name.as_str())).collect();
+        assert_eq!(names, vec![(3, "Tin Whistle"), (1, "Sine Pad")]);
+    }
+
+    #[test]
+    fn lines_it_cannot_use_end_the_list() {
+        // found by fuzzing: these used to panic
+        for text in ["1,\n2,B", "255,A\n2,B", "1,\"\"\n2,B", "x,A\n2,B"] {
+            let mut presets = Vec::new();
+            parse_preset_listing(text, "test", ListingMode::Lenient, &mut presets).unwrap();
+            assert!(presets.is_empty(), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn strict_listings_report_every_problem() {
+        let text = "1,A\n\nx,B\n200,C\n1,D\n2\n3,\"\"\n4,E.mid\n";
+        let mut presets = Vec::new();
+        let error = parse_preset_listing(text, "test", ListingMode::Strict, &mut presets).unwrap_err();
+        assert_eq!(
+            error.message(),
+            "test: 6 line(s) can't be used, so none are (--lenient uses the lines before the first):\n  \
+             line 2: a blank line ends the list before line 3\n  \
+             line 3: 'x' is not a preset number\n  \
+             line 4: slot 200 is not from 1 to 128\n  \
+             line 5: slot 1 is also on line 1\n  \
+             line 6: expected <number>,<file name>, found '2'\n  \
+             line 7: no preset file name"
+        );
+        assert!(presets.is_empty());
+
+        // the same listing, read as the Haken Editor reads it
+        parse_preset_listing(text, "test", ListingMode::Lenient, &mut presets).unwrap();
+        let names: Vec<&str> = presets.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["A"]);
+    }
+
+    #[test]
+    fn blank_lines_end_the_list_in_both_modes() {
+        for mode in [ListingMode::Strict, ListingMode::Lenient] {
+            let mut presets = Vec::new();
+            parse_preset_listing("1,A\n2,B\n\n  \n", "test", mode, &mut presets).unwrap();
+            assert_eq!(presets.len(), 2);
+        }
+        let mut presets = Vec::new();
+        parse_preset_listing("1,A\n\n2,B\n", "test", ListingMode::Lenient, &mut presets).unwrap();
+        assert_eq!(presets.len(), 1);
+        let error = parse_preset_listing("1,A\n\n2,B\n", "test", ListingMode::Strict, &mut Vec::new());
+        assert!(error.unwrap_err().message().ends_with("line 2: a blank line ends the list before line 3"));
+    }
+
+    #[test]
+    fn strict_listings_quote_names_with_commas() {
+        let text = "1, Pad, warm.mid\n";
+        let error = parse_preset_listing(text, "test", ListingMode::Strict, &mut Vec::new()).unwrap_err();
+        assert!(error.message().ends_with("line 1: 'Pad, warm.mid' has a comma, so needs quotes"));
+        let mut presets = Vec::new();
+        parse_preset_listing(text, "test", ListingMode::Lenient, &mut presets).unwrap();
+        assert_eq!(presets[0].name, "Pad");
+    }
+
+    #[test]
+    fn names_with_commas_quotes_and_spaces_round_trip() {
+        let names = ["Pad, warm", "Say \"hi\"", " Lead ", "\"Quoted\"", "a\"\",b", "Plain"];
+        let text: String = names
+            .iter()
+            .enumerate()
+            .map(|(index, name)| listing_line(index + 1, &format!("{name}.mid")))
+            .collect();
+        assert_eq!(text.lines().last(), Some("6,\"Plain.mid\""));
+        let mut presets = Vec::new();
+        parse_preset_listing(&text, "test", ListingMode::Strict, &mut presets).unwrap();
+        let read: Vec<&str> = presets.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(read, names);
+    }
+
+    #[test]
+    fn quoting_mistakes_are_reported() {
+        let text = "1,\"Pad, warm.mid\n2,\"Pad\" warm.mid\"\n3, \"Lead.mid\" ,extra\n";
+        let error = parse_preset_listing(text, "test", ListingMode::Strict, &mut Vec::new()).unwrap_err();
+        assert!(error.message().ends_with(
+            "\n  line 1: no closing quote\n  line 2: 'warm.mid\"' after the closing quote"
+        ));
+    }
+
+    #[test]
+    fn strict_listings_need_their_files() {
+        let folder = std::env::temp_dir().join(format!("harkive-listing-{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(folder.join("Here.mid"), b"").unwrap();
+        let listing = folder.join("UserPresets.txt");
+        std::fs::write(&listing, "1,\"Here.mid\"\n2,\"Gone.mid\"\n").unwrap();
+        let result = read_preset_listing(&listing, ListingMode::Strict, &mut Vec::new());
+        let mut presets = Vec::new();
+        read_preset_listing(&listing, ListingMode::Lenient, &mut presets).unwrap();
+        std::fs::remove_dir_all(&folder).unwrap();
+        assert!(result.unwrap_err().message().ends_with("line 2: 'Gone.mid' is not in the folder"));
+        assert_eq!(presets.len(), 2);
+    }
+}
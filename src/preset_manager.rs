@@ -1,43 +1,91 @@
 use std::sync::mpsc::*;
 use crate::{
     acquire_device::*,
+    continuum_preset::HCCategoryCode,
+    error::{Error, Result},
     matrix_handler::MatrixHandler,
     midi_handler::*,
+    midi_message::TimedMessage,
     midi_source::MidiSource,
+    midi_traits::Named,
     options::{Action, Options},
+    step_backup::{backup_folder, BackupFirst},
+    step_clear::Clearer,
     step_load::PresetLoader,
     stepper::*,
-    step_names::NameList,
+    step_names::{NameList, SystemList},
     step_save::*,
     thread_control::*,
+    transport::MidiTransport,
 };
 
 pub struct PresetManager<'a> {
     options: &'a Options,
-    input: InPortDescription,
+    catcode: &'a HCCategoryCode,
+    input: Option<InPortDescription>,
     handler: MatrixHandler,
     stepper: Box<dyn Stepper>,
+    error: Option<Error>,
 }
 
+/// The thread reading MIDI input, stopped and joined when dropped, so it ends however
+/// `run` returns.
+struct SourceThread {
+    control: Sender<ThreadControl>,
+    joiner: Option<std::thread::JoinHandle<Result<()>>>,
+}
+
+impl Drop for SourceThread {
+    fn drop(&mut self) {
+        _ = self.control.send(ThreadControl::Stop);
+        if let Some(joiner) = self.joiner.take() {
+            if let Err(error) = joiner.join() {
+                println!("Thread join error: {error:?}");
+            }
+        }
+    }
+}
+
+// How often to check for timeouts while the device is quiet.
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
 impl<'a> PresetManager<'a> {
-    pub async fn new(options: &'a Options) -> Option<PresetManager<'a>> {
-        if let Some((input, output)) = get_haken_io(&options.device).await {
-            Some(PresetManager {
+    pub fn new(
+        transport: &dyn MidiTransport,
+        options: &'a Options,
+        catcode: &'a HCCategoryCode,
+    ) -> Result<PresetManager<'a>> {
+        if let Some((input, output)) = get_haken_io(transport, &options.device) {
+            println!(
+                "Using {} ({})",
+                output.description.friendly_name(),
+                output.description.kind.name()
+            );
+            Ok(PresetManager {
                 options,
-                input,
-                handler: MatrixHandler::new(output),
+                catcode,
+                input: Some(input),
+                handler: {
+                    let mut handler = MatrixHandler::new(output);
+                    handler.set_retry_policy(options.retry);
+                    handler.set_canonical(options.canonical);
+                    handler
+                },
                 stepper: Box::new(NilStepper{}),
+                error: None,
             })
         } else {
-            println!("Unable to find a suitable available device.");
-            None
+            Err(Error::DeviceNotFound("Unable to find a suitable available device.".to_string()))
         }
     }
 
-    fn start_action(&mut self) -> windows::core::Result<()> {
+    fn start_action(&mut self) -> Result<()> {
         match self.options.action {
             Action::ListNames => {
-                self.stepper = Box::new(NameList{});
+                self.stepper = Box::new(NameList::new(self.catcode.clone()));
+            }
+            Action::ListSystem => {
+                self.stepper = Box::new(SystemList::new(self.catcode.clone()));
             }
             Action::SaveCurrent => {
                 self.stepper = Box::new(SingleSaver{});
@@ -48,36 +96,66 @@ impl<'a> PresetManager<'a> {
             Action::Load => {
                 self.stepper = Box::new(PresetLoader::new());
             }
+            Action::Clear => {
+                self.stepper = Box::new(Clearer::new());
+            }
             _ => {}
         };
+        if matches!(self.options.action, Action::Load | Action::Clear) {
+            if let Some(folder) = backup_folder(self.options)? {
+                let then = std::mem::replace(&mut self.stepper, Box::new(NilStepper {}));
+                self.stepper = Box::new(BackupFirst::new(folder, self.options.action, then));
+                return self.handler.start_action(Action::Save);
+            }
+        }
         self.handler.start_action(self.options.action)
     }
 
+    fn check_timeout(&mut self) -> WorkingStatus {
+        let result = match self.handler.check_timeout() {
+            Ok(true) => self.stepper.retry(self.options, &mut self.handler),
+            Ok(false) => Ok(()),
+            Err(error) => Err(error),
+        };
+        match result {
+            Ok(()) => WorkingStatus::Working,
+            Err(error) => {
+                self.error = Some(error);
+                WorkingStatus::Finished
+            }
+        }
+    }
+
     fn step_action(&mut self) -> WorkingStatus {
         match self.stepper.next(self.options, &mut self.handler) {
             Ok(status) => status,
             Err(error) => {
-                println!("{error}");
+                self.error = Some(error);
                 WorkingStatus::Finished
             }
         }
     }
 
-    fn handle_midi(&mut self, msg: &WinMidi, thread_tx: &Sender<ThreadControl>) -> bool {
-        if dispatch_midi(&mut self.handler, msg).is_err()
-            || (self.handler.is_ready() && (WorkingStatus::Finished == self.step_action()))
-        {
-            _ = thread_tx.send(ThreadControl::Stop);
-            return false;
+    fn handle_midi(&mut self, msg: &TimedMessage) -> bool {
+        self.handler.note_activity(&msg.message);
+        if let Err(error) = dispatch_midi(&mut self.handler, msg) {
+            self.error = Some(error);
         }
-        true
+        !(self.error.is_some()
+            || (self.handler.is_ready() && (WorkingStatus::Finished == self.step_action())))
     }
 
-    pub fn run(&mut self) -> windows::core::Result<()> {
-        let (midi_tx, midi_rx) = channel::<WinMidi>();
+    pub fn run(&mut self) -> Result<()> {
+        let Some(input) = self.input.take() else {
+            return Ok(());
+        };
+        let (midi_tx, midi_rx) = channel::<TimedMessage>();
         let (thread_tx, thread_rx) = ThreadControl::make_channels();
-        let midi_source = MidiSource::new(midi_tx, thread_rx, self.input.clone());
-        let joiner = std::thread::spawn(move || midi_source.run());
+        let midi_source = MidiSource::new(midi_tx, thread_rx, input);
+        let source = SourceThread {
+            control: thread_tx,
+            joiner: Some(std::thread::spawn(move || midi_source.run())),
+        };
 
         self.start_action()?;
         std::thread::sleep(std::time::Duration::from_millis(100));
@@ -86,7 +164,7 @@ impl<'a> PresetManager<'a> {
             match midi_rx.try_recv() {
                 // pump ready messages
                 Ok(msg) => {
-                    if !self.handle_midi(&msg, &thread_tx) {
+                    if !self.handle_midi(&msg) {
                         break;
                     }
                 }
@@ -99,26 +177,30 @@ impl<'a> PresetManager<'a> {
                         if self.handler.is_ready()
                             && (WorkingStatus::Finished == self.step_action())
                         {
-                            _ = thread_tx.send(ThreadControl::Stop);
                             break;
                         }
-                        // wait for next midi message
-                        match midi_rx.recv() {
+                        if WorkingStatus::Finished == self.check_timeout() {
+                            break;
+                        }
+                        // wait for next midi message, waking to check for timeouts
+                        match midi_rx.recv_timeout(POLL_INTERVAL) {
                             Ok(msg) => {
-                                if !self.handle_midi(&msg, &thread_tx) {
+                                if !self.handle_midi(&msg) {
                                     break;
                                 }
                             }
-                            Err(_) => break,
+                            Err(RecvTimeoutError::Timeout) => {}
+                            Err(RecvTimeoutError::Disconnected) => break,
                         }
                     }
                     TryRecvError::Disconnected => break,
                 },
             }
         }
-        if let Err(error) = joiner.join() {
-            println!("Thread join error: {error:?}");
+        drop(source);
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => Ok(()),
         }
-        Ok(())
     }
 }
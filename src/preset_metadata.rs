@@ -0,0 +1,273 @@
+use crate::{
+    continuum_preset::category_list,
+    error::{Error, Result},
+    json::Json,
+};
+use std::path::{Path, PathBuf};
+
+// Metadata sidecars are JSON, saved beside the archives they describe:
+//
+//   Sine Pad.mid
+//   Sine Pad.meta.json      what harkive knew about the preset when it saved it
+//   UserPresets.txt
+//   UserPresets.meta.json   every preset saved to the folder, one per line
+//
+// A preset's own sidecar wins over the folder's.
+const METADATA_VERSION: i64 = 1;
+const SIDECAR_EXTENSION: &str = "meta.json";
+
+/// What harkive knew about a preset when it saved it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PresetMetadata {
+    /// Archive file name, without the folder.
+    pub file: String,
+    pub name: String,
+    /// One-based user slot, or None for the editing slot and system presets.
+    pub slot: Option<u8>,
+    /// System preset bank (hi, lo) and program.
+    pub bank: Option<(u8, u8)>,
+    pub program: Option<u8>,
+    pub text: String,
+    pub category: String,
+    pub firmware: Option<u16>,
+    pub device: String,
+    /// UTC "YYYYMMDD-HHMMSS".
+    pub saved: String,
+}
+
+fn field(name: &str, value: Json) -> (String, Json) {
+    (name.to_string(), value)
+}
+
+fn string(text: &str) -> Json {
+    Json::String(text.to_string())
+}
+
+fn optional(value: Option<i64>) -> Json {
+    value.map_or(Json::Null, Json::Number)
+}
+
+/// The file for `archive`'s own metadata: "Name.meta.json" beside "Name.mid".
+pub fn sidecar_path(archive: &Path) -> PathBuf {
+    archive.with_extension(SIDECAR_EXTENSION)
+}
+
+/// The folder metadata file beside a listing: "UserPresets.meta.json" for "UserPresets.txt".
+pub fn folder_metadata_path(listing: &Path) -> PathBuf {
+    listing.with_extension(SIDECAR_EXTENSION)
+}
+
+impl PresetMetadata {
+    fn fields(&self) -> Vec<(String, Json)> {
+        let bank = match self.bank {
+            Some((hi, lo)) => Json::Array(vec![Json::Number(hi as i64), Json::Number(lo as i64)]),
+            None => Json::Null,
+        };
+        let categories = category_list(&self.text).iter().map(|code| string(code)).collect();
+        vec![
+            field("file", string(&self.file)),
+            field("name", string(&self.name)),
+            field("slot", optional(self.slot.map(i64::from))),
+            field("bank", bank),
+            field("program", optional(self.program.map(i64::from))),
+            field("text", string(&self.text)),
+            field("categories", Json::Array(categories)),
+            field("category", string(&self.category)),
+            field("firmware", optional(self.firmware.map(i64::from))),
+            field("device", string(&self.device)),
+            field("saved", string(&self.saved)),
+        ]
+    }
+
+    /// The sidecar text for one preset, a field per line.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{{\n  \"harkive\": {METADATA_VERSION}");
+        for (name, value) in self.fields() {
+            text += &format!(",\n  \"{name}\": {}", value.to_compact());
+        }
+        text + "\n}\n"
+    }
+
+    fn from_json(value: &Json) -> Result<Self> {
+        let text = |name: &str| -> Result<String> {
+            match value.get(name) {
+                None | Some(Json::Null) => Ok(String::new()),
+                Some(Json::String(text)) => Ok(text.clone()),
+                Some(_) => Err(Error::FileFormat(format!("\"{name}\" must be text"))),
+            }
+        };
+        let number = |name: &str, max: i64| -> Result<Option<i64>> {
+            match value.get(name) {
+                None | Some(Json::Null) => Ok(None),
+                Some(Json::Number(number)) if (0..=max).contains(number) => Ok(Some(*number)),
+                Some(_) => Err(Error::FileFormat(format!(
+                    "\"{name}\" must be a number from 0 to {max}"
+                ))),
+            }
+        };
+        let bank = match value.get("bank") {
+            None | Some(Json::Null) => None,
+            Some(bank) => match bank.as_array().unwrap_or_default() {
+                [Json::Number(hi @ 0..=127), Json::Number(lo @ 0..=127)] => {
+                    Some((*hi as u8, *lo as u8))
+                }
+                _ => {
+                    return Err(Error::FileFormat(
+                        "\"bank\" must be [hi, lo] from 0 to 127".to_string(),
+                    ))
+                }
+            },
+        };
+        Ok(Self {
+            file: text("file")?,
+            name: text("name")?,
+            slot: number("slot", 128)?.map(|slot| slot as u8),
+            bank,
+            program: number("program", 127)?.map(|program| program as u8),
+            text: text("text")?,
+            category: text("category")?,
+            firmware: number("firmware", 0x3FFF)?.map(|firmware| firmware as u16),
+            device: text("device")?,
+            saved: text("saved")?,
+        })
+    }
+
+    /// Parse a preset's sidecar.
+    pub fn parse(text: &str) -> Result<Self> {
+        let document = Json::parse(text)?;
+        check_version(&document)?;
+        Self::from_json(&document)
+    }
+}
+
+fn check_version(document: &Json) -> Result<()> {
+    match document.get("harkive").and_then(Json::as_i64) {
+        Some(METADATA_VERSION) => Ok(()),
+        _ => Err(Error::FileFormat(
+            "Not harkive preset metadata (version 1)".to_string(),
+        )),
+    }
+}
+
+/// The folder metadata text: the presets saved to one folder, a preset per line.
+pub fn folder_text(presets: &[PresetMetadata]) -> String {
+    let mut text = format!("{{\n  \"harkive\": {METADATA_VERSION},\n  \"presets\": [");
+    for (index, preset) in presets.iter().enumerate() {
+        text += if index == 0 { "\n    " } else { ",\n    " };
+        text += &Json::Object(preset.fields()).to_compact();
+    }
+    text += if presets.is_empty() { "]\n}\n" } else { "\n  ]\n}\n" };
+    text
+}
+
+/// Parse folder metadata.
+pub fn parse_folder(text: &str) -> Result<Vec<PresetMetadata>> {
+    let document = Json::parse(text)?;
+    check_version(&document)?;
+    let presets = document
+        .get("presets")
+        .and_then(Json::as_array)
+        .ok_or_else(|| Error::FileFormat("Missing \"presets\" list".to_string()))?;
+    presets
+        .iter()
+        .enumerate()
+        .map(|(index, preset)| {
+            PresetMetadata::from_json(preset).map_err(|error| error.context(&format!("presets[{index}]")))
+        })
+        .collect()
+}
+
+pub fn write_metadata(path: &Path, text: &str) -> Result<()> {
+    std::fs::write(path, text)
+        .map_err(|error| Error::Io(format!("Couldn't save '{}' : {error}", path.display())))
+}
+
+fn read_text(path: &Path) -> Option<Result<String>> {
+    if !path.is_file() {
+        return None;
+    }
+    Some(
+        std::fs::read_to_string(path)
+            .map_err(|error| Error::Io(format!("{}: {error}", path.display()))),
+    )
+}
+
+/// Saved metadata for archives in one folder, from the sidecars and the folder's metadata.
+///
+/// Metadata is a help, not a requirement: a damaged file is reported and skipped.
+#[derive(Default)]
+pub struct FolderMetadata {
+    folder: PathBuf,
+    presets: Vec<PresetMetadata>,
+}
+
+impl FolderMetadata {
+    pub fn read(folder: &Path, listing_name: &str) -> Self {
+        let path = folder_metadata_path(&folder.join(listing_name));
+        let presets = match read_text(&path).map(|text| parse_folder(&text?)) {
+            None => Vec::new(),
+            Some(Ok(presets)) => presets,
+            Some(Err(error)) => {
+                println!("Ignoring '{}': {}", path.display(), error.message());
+                Vec::new()
+            }
+        };
+        Self {
+            folder: folder.to_path_buf(),
+            presets,
+        }
+    }
+
+    /// Metadata for the archive `file` in the folder, if any was saved.
+    pub fn get(&self, file: &str) -> Option<PresetMetadata> {
+        let path = sidecar_path(&self.folder.join(file));
+        match read_text(&path).map(|text| PresetMetadata::parse(&text?)) {
+            Some(Ok(metadata)) => return Some(metadata),
+            Some(Err(error)) => println!("Ignoring '{}': {}", path.display(), error.message()),
+            None => {}
+        }
+        self.presets.iter().find(|preset| preset.file == file).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(file: &str, slot: Option<u8>) -> PresetMetadata {
+        PresetMetadata {
+            file: file.to_string(),
+            name: file.trim_end_matches(".mid").to_string(),
+            slot,
+            text: "C=OT_SU".to_string(),
+            firmware: Some(1009),
+            device: "Continuum".to_string(),
+            saved: "20261017-093000".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn metadata_round_trips() {
+        let metadata = sample("Sine Pad.mid", Some(3));
+        let text = metadata.to_text();
+        assert!(text.contains("\n  \"categories\": [\"OT\", \"SU\"],\n"));
+        assert_eq!(PresetMetadata::parse(&text).unwrap(), metadata);
+
+        let system = PresetMetadata {
+            bank: Some((1, 0)),
+            program: Some(4),
+            ..sample("Cello.mid", None)
+        };
+        let presets = vec![metadata, system];
+        assert_eq!(parse_folder(&folder_text(&presets)).unwrap(), presets);
+    }
+
+    #[test]
+    fn bad_metadata_is_an_error() {
+        let text = sample("Pad.mid", Some(3)).to_text().replace("\"slot\": 3", "\"slot\": 300");
+        assert!(PresetMetadata::parse(&text).is_err());
+        let error = parse_folder("{\"harkive\": 1, \"presets\": [{\"bank\": [1]}]}").unwrap_err();
+        assert_eq!(error.message(), "presets[0]: \"bank\" must be [hi, lo] from 0 to 127");
+    }
+}
@@ -1,270 +1,434 @@
 use std:: {
     time::Duration,
 };
-use windows::{
-    core::*,
-    Devices::Midi::*,
-    Win32::Foundation::E_FAIL,
-};
 use crate:: {
+     error::{Error, Result},
      midi::*,
+     midi_message::MidiMessage,
      util::*,
-     midi_handler::WinMidi,
 };
 
+// microseconds per quarter note until a tempo meta event says otherwise (120 bpm)
+const DEFAULT_TEMPO: u32 = 500_000;
+
+const META_END_OF_TRACK: u8 = 0x2F;
+const META_TEMPO: u8 = 0x51;
+
+/// Time base from the header's division field.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Division {
+    /// Ticks per quarter note; real time follows the tempo.
+    TicksPerQuarter(u16),
+    /// SMPTE frames per second (29 is 29.97 drop frame) and ticks per frame.
+    Smpte { frames: u8, ticks_per_frame: u8 },
+}
+
+impl Division {
+    fn parse(raw: u16) -> Self {
+        if raw & 0x8000 == 0 {
+            Division::TicksPerQuarter(raw)
+        } else {
+            Division::Smpte {
+                // the high byte is the negative frame rate
+                frames: ((raw >> 8) as u8 as i8).unsigned_abs(),
+                ticks_per_frame: raw as u8,
+            }
+        }
+    }
+}
+
+struct Event {
+    ticks: u64,
+    message: MidiMessage,
+}
+
+/// Bounds-checked reading, with the byte offset in every error.
+struct Cursor<'a> {
+    data: &'a [u8],
+    index: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn error(&self, message: &str, offset: usize) -> Error {
+        Error::FileFormat(format!("{message} at byte {offset}"))
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let byte = *self
+            .data
+            .get(self.index)
+            .ok_or_else(|| self.error("Unexpected end of file", self.index))?;
+        self.index += 1;
+        Ok(byte)
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        let end = self
+            .index
+            .checked_add(length)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| self.error("Unexpected end of file", self.data.len()))?;
+        let bytes = &self.data[self.index..end];
+        self.index = end;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(get_u16(self.take(2)?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(get_u32(self.take(4)?))
+    }
+
+    fn var_len(&mut self) -> Result<u32> {
+        let start = self.index;
+        let mut decoder = VariableLengthValue::new();
+        decoder.start();
+        for _ in 0..4 {
+            if !decoder.add_byte(self.byte()?) {
+                return decoder.finish();
+            }
+        }
+        Err(self.error("Variable-length value longer than 4 bytes", start))
+    }
+}
+
+/// A tempo change, with the time up to it so that lookups don't walk the whole map.
+#[derive(Copy, Clone, Debug)]
+struct TempoChange {
+    ticks: u64,
+    // microseconds per quarter note from here on
+    tempo: u32,
+    // ticks times microseconds per quarter note, summed from the start of the file
+    quarter_micros: u128,
+}
+
+/// Reads the messages of a Standard MIDI File, format 0 or 1.
+///
+/// Tracks are merged in time order. Meta events other than tempo, and SysEx, are skipped.
 #[derive(Default)]
 pub struct ReadMidiFile {
     data: Vec<u8>,
-    decoder: VariableLengthValue,
-    running_status: u8,
+    parsed: bool,
+    events: Vec<Event>,
+    // in tick order once parsed
+    tempos: Vec<TempoChange>,
+    division: Option<Division>,
     index: usize,
-    end: usize,
+    last_ticks: u64,
 }
 
 impl ReadMidiFile {
     pub fn new(data: &[u8]) -> Self {
         Self {
             data: data.to_vec(),
-            decoder: VariableLengthValue::default(),
-            running_status: 0,
-            index: 0,
-            end: data.len(),
+            ..Self::default()
         }
     }
 
-    // pub fn start(&mut self, data: &[u8]) {
-    //     self.data.clear();
-    //     self.data.extend_from_slice(data);
-    //     self.index = 0;
-    //     self.running_status = 0;
-    //     self.decoder.start();
-    // }
-
-    // pub fn reset(&mut self) {
-    //     self.index = 0;
-    //     self.running_status = 0;
-    //     self.decoder.start();
-    // }
-
-    pub fn next(&mut self) -> Result<Option<(Duration, WinMidi)>> {
-        if 0 == self.index {
-            if 24 > self.data.len() {
-                return Err(Error::new(E_FAIL, h!("Not a MIDI file").clone()));
-            }
-            self.read_header()?;
-            self.read_track_header()?;
-        }
-        if self.index >= self.end { return Ok(None); }
-        self.read_event()
+    #[allow(clippy::should_implement_trait)] // fallible, so not an Iterator
+    pub fn next(&mut self) -> Result<Option<(Duration, MidiMessage)>> {
+        let previous = self.last_ticks;
+        Ok(self.next_event()?.map(|(_, message)| {
+            let dt = self.ticks_to_time(self.last_ticks) - self.ticks_to_time(previous);
+            (dt, message)
+        }))
     }
-    
-    fn running_status(&mut self) -> u8 {
-        let status = self.data[self.index];
-        if is_bit8(status) {
-            self.index += 1;
-            self.running_status = status;
-            status
-        } else {
-            self.running_status
-        }
+
+    /// The next message with its delta time in file ticks, as stored.
+    pub fn next_event(&mut self) -> Result<Option<(u32, MidiMessage)>> {
+        self.ensure_parsed()?;
+        let Some(event) = self.events.get(self.index) else {
+            return Ok(None);
+        };
+        self.index += 1;
+        let delta = u32::try_from(event.ticks - self.last_ticks).unwrap_or(u32::MAX);
+        self.last_ticks = event.ticks;
+        Ok(Some((delta, event.message.clone())))
     }
 
-    // fn clear_running_status(&mut self) {
-    //     self.running_status = 0;
-    // }
+    /// Division and tempo at the start of the file, or None when it counts SMPTE time.
+    pub fn time_base(&mut self) -> Result<Option<TimeBase>> {
+        self.ensure_parsed()?;
+        let tempo = match self.tempos.first() {
+            Some(change) if change.ticks == 0 => change.tempo,
+            _ => DEFAULT_TEMPO,
+        };
+        Ok(match self.division {
+            Some(Division::TicksPerQuarter(division)) => Some(TimeBase { division, tempo }),
+            _ => None,
+        })
+    }
 
-    fn read_var_len(&mut self) -> Result<usize> {
-        loop {
-            let byte = self.data[self.index];
-            self.index += 1;
-            if !self.decoder.add_byte(byte) {
-                break;
+    /// Time from the start of the file to `ticks`, following tempo changes.
+    fn ticks_to_time(&self, ticks: u64) -> Duration {
+        let nanos = match self.division {
+            None => 0,
+            Some(Division::Smpte {
+                frames,
+                ticks_per_frame,
+            }) => {
+                // 29 is 29.97 frames per second
+                let per_second = if frames == 29 { 2997 } else { frames as u128 * 100 };
+                let ticks_per_second = per_second * ticks_per_frame.max(1) as u128;
+                ticks as u128 * 100_000_000_000 / ticks_per_second
             }
-        }
-        let length = self.decoder.finish()? as usize;
-        Ok(length)
+            Some(Division::TicksPerQuarter(per_quarter)) => {
+                let per_quarter = per_quarter.max(1) as u128;
+                // the last change before `ticks`
+                let count = self.tempos.partition_point(|change| change.ticks < ticks);
+                let (at, tempo, quarter_micros) = match count {
+                    0 => (0, DEFAULT_TEMPO, 0),
+                    _ => {
+                        let change = self.tempos[count - 1];
+                        (change.ticks, change.tempo, change.quarter_micros)
+                    }
+                };
+                (quarter_micros + (ticks - at) as u128 * tempo as u128) * 1000 / per_quarter
+            }
+        };
+        Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
     }
 
-    fn read_header(&mut self) -> Result<()> {
-        if !is_midi_header(&self.data[0..]) {
-            Err(Error::new(E_FAIL, h!("Not a MIDI file").clone()))
-        } else {
-            // This commented code reads the full header info, but we don't use it.
-            // Left here for future reference.
-
-            //let mut index: usize = 4;
-            //let length = get_u32(&data[index..index + 4]);
-            //index += 4;
-            //let format = get_u16(&data[index..index + 2]);
-            //index += 2;
-            //let track_count = get_u16(&data[index..index + 2]);
-            //index += 2;
-            //let division = get_u16(&data[index..index + 2]);
-            //index += 2;
-            // println!(
-            //     "MThd: length {length}, format {format}, tracks {track_count}, division {division}"
-            // );
-            self.index = 14;
-            Ok(())
+    fn ensure_parsed(&mut self) -> Result<()> {
+        if !self.parsed {
+            self.parse()?;
+            self.parsed = true;
         }
+        Ok(())
     }
 
-    fn read_track_header(&mut self) -> Result<()> {
-        if !is_midi_track_header(&self.data[self.index..]) {
-            Err(Error::new(E_FAIL, h!("Expecting MTrk").clone()))
-        } else {
-            self.index += 4;
-            let length = self.next_u32();
-            //println!("MTrk: len {length}");
-            self.end = self.index + length;
-            debug_assert!(self.end <= self.data.len());
-            Ok(())
+    fn parse(&mut self) -> Result<()> {
+        let data = std::mem::take(&mut self.data);
+        let result = self.parse_chunks(&data);
+        self.data = data;
+        result?;
+        self.events.sort_by_key(|event| event.ticks); // stable: tracks keep their order
+        self.tempos.sort_by_key(|change| change.ticks);
+        let (mut at, mut tempo, mut quarter_micros) = (0, DEFAULT_TEMPO, 0);
+        for change in &mut self.tempos {
+            quarter_micros += (change.ticks - at) as u128 * tempo as u128;
+            change.quarter_micros = quarter_micros;
+            (at, tempo) = (change.ticks, change.tempo);
         }
+        Ok(())
     }
 
-    fn delta_to_ms(delta:usize) -> std::time::Duration {
-        const STD_BEAT_MS:f32 = 500_000.0/96.0/1_000.0;
-        let ms = ((delta as f32) * STD_BEAT_MS) as u64;
-        // #[cfg(debug_assertions)]
-        // {
-        //     println!("Delta={ms}");
-        // }
-        std::time::Duration::from_millis(ms)
-    }
+    fn parse_chunks(&mut self, data: &[u8]) -> Result<()> {
+        let mut cursor = Cursor { data, index: 0 };
+        if !is_midi_header(data) {
+            return Err(Error::FileFormat("Not a MIDI file".to_string()));
+        }
+        cursor.take(4)?;
+        let length = cursor.u32()? as usize;
+        if length < 6 {
+            return Err(cursor.error("MIDI header too short", 4));
+        }
+        let header = cursor.index;
+        let format = cursor.u16()?;
+        let tracks = cursor.u16()?;
+        self.division = Some(Division::parse(cursor.u16()?));
+        if format > 1 {
+            return Err(cursor.error(&format!("Unsupported MIDI file format {format}"), header));
+        }
+        cursor.take(length - 6)?;
 
-    fn next_byte(&mut self) -> u8 {
-        let next = self.data[self.index];
-        self.index += 1;
-        next
-    }
-    fn next_u32(&mut self) -> usize {
-        let result = get_u32(&self.data[self.index..self.index+4]) as usize;
-        self.index += 4;
-        result
+        let mut found = 0;
+        while cursor.index < data.len() && found < tracks {
+            let start = cursor.index;
+            let kind = cursor.take(4)?;
+            let length = cursor.u32()? as usize;
+            let body = cursor.index;
+            cursor
+                .take(length)
+                .map_err(|_| cursor.error("Chunk runs past the end of the file", start))?;
+            if is_midi_track_header(kind) {
+                let mut track = Cursor {
+                    data: &data[..body + length],
+                    index: body,
+                };
+                self.parse_track(&mut track)?;
+                found += 1;
+            }
+            // other chunk types are skipped, as the standard asks
+        }
+        if found < tracks {
+            return Err(cursor.error(
+                &format!("Expecting {tracks} tracks, found {found}"),
+                cursor.index,
+            ));
+        }
+        Ok(())
     }
 
-    fn read_event(&mut self) -> Result<Option<(Duration, WinMidi)>> {
-        let delta = self.read_var_len()?;
-        let dt = if delta == 0 {
-            std::time::Duration::ZERO
-        } else { 
-            //let delta = usize::max(delta, 10);
-            Self::delta_to_ms(delta)
-        };
-        let status = self.running_status();
-        match status {
-            0xF0 //SysEx
-            | 0xF1 // MIDI Time Code Quarter Frame
-            | 0xF2 // Song Position Pointer
-            | 0xF3 // Song Select
-            | 0xF4 // undefined
-            | 0xF5 // undefined
-            | 0xF6 // Tune Request
-            | 0xF7 // End SysEx
-            | 0xF8 // Timing Clock
-            | 0xFA // Start
-            | 0xFB // Continue
-            | 0xFC // Stop
-            | 0xFE // Active Sensing
-                => {
-                    let msg = format!("Unsupported status {status:2X} in preset file");
-                    Err(Error::new(E_FAIL, HSTRING::from(msg)))
-                }
-
-            0xFF => {
-                let code = self.next_byte();
-                if code == 0x2F {
-                    let extra = self.next_byte();
-                    debug_assert!(0 == extra);
-                    //println!("F| End of track");
-                    Ok(None)
-                } else {
-                    let msg = format!("Unsupported status {status:2X}:{code:2X} in preset file");
-                    Err(Error::new(E_FAIL, HSTRING::from(msg)))
+    fn parse_track(&mut self, track: &mut Cursor) -> Result<()> {
+        let mut ticks = 0u64;
+        let mut running_status = 0u8;
+        while track.index < track.data.len() {
+            ticks += track.var_len()? as u64;
+            let at = track.index;
+            let mut status = track.byte()?;
+            if !is_bit8(status) {
+                if running_status == 0 {
+                    return Err(track.error("Data byte without a status", at));
                 }
+                status = running_status;
+                track.index = at;
             }
-
-            _ => {
-                let kind = hi_nybble(status);
-                let channel = lo_nybble(status);
-                match kind {
-                    0x80 => {
-                        //Note off
-                        let note = self.next_byte();
-                        let velocity = self.next_byte();
-                        if let Ok(midi) = MidiNoteOffMessage::CreateMidiNoteOffMessage(channel, note, velocity) {
-                            Ok(Some((dt, WinMidi::NoteOff(midi))))
-                        } else {
-                            Err(Error::new(E_FAIL, h!("Failed to create MidiNoteOffMessage").clone()))
-                        }
-                    },
-                    0x90 => {
-                        //Note on
-                        let note = self.next_byte();
-                        let velocity = self.next_byte();
-                        if let Ok(midi) = MidiNoteOnMessage::CreateMidiNoteOnMessage(channel, note, velocity) {
-                            Ok(Some((dt, WinMidi::NoteOn(midi))))
-                        } else {
-                            Err(Error::new(E_FAIL, h!("Failed to create MidiNoteOnMessage").clone()))
-                        }
-                    },
-                    0xA0 => {
-                        //Poly Key Pressure
-                        let note = self.next_byte();
-                        let pressure = self.next_byte();
-                        if let Ok(midi) = MidiPolyphonicKeyPressureMessage::CreateMidiPolyphonicKeyPressureMessage(channel, note, pressure) {
-                            Ok(Some((dt, WinMidi::PolyphonicKeyPressure(midi))))
-                        } else {
-                            Err(Error::new(E_FAIL, h!("Failed to create MidiPolyphonicKeyPressureMessage").clone()))
-                        }
-                    },
-                    0xB0 => {
-                        // CC
-                        let cc = self.next_byte();
-                        let value = self.next_byte();
-                        if let Ok(midi) = MidiControlChangeMessage::CreateMidiControlChangeMessage(channel, cc, value) {
-                            Ok(Some((dt, WinMidi::ControlChange(midi))))
-                        } else {
-                            Err(Error::new(E_FAIL, h!("Failed to create MidiControlChangeMessage").clone()))
-                        }
-                    },
-                    0xC0 => {
-                        // Program change
-                        let program = self.next_byte();
-                        if let Ok(midi) = MidiProgramChangeMessage::CreateMidiProgramChangeMessage(channel, program) {
-                            Ok(Some((dt, WinMidi::ProgramChange(midi))))
-                        } else {
-                            Err(Error::new(E_FAIL, h!("Failed to create MidiProgramChangeMessage").clone()))
-                        }
-                    }
-                    0xD0 => {
-                        // Channel pressure
-                        let pressure = self.next_byte();
-                        if let Ok(midi) = MidiChannelPressureMessage::CreateMidiChannelPressureMessage(channel, pressure) {
-                            Ok(Some((dt, WinMidi::ChannelPressure(midi))))
-                        } else {
-                            Err(Error::new(E_FAIL, h!("Failed to create MidiChannelPressureMessage").clone()))
+            match status {
+                0xFF => {
+                    running_status = 0;
+                    let kind = track.byte()?;
+                    let length = track.var_len()? as usize;
+                    let data = track.take(length)?;
+                    match kind {
+                        META_END_OF_TRACK => return Ok(()),
+                        META_TEMPO if length == 3 => {
+                            let tempo = u32::from_be_bytes([0, data[0], data[1], data[2]]);
+                            self.tempos.push(TempoChange {
+                                ticks,
+                                tempo,
+                                quarter_micros: 0, // summed once all tracks are read
+                            });
                         }
+                        _ => {} // names, markers, time signatures...
                     }
-                    0xE0 => {
-                        //Pitch bend
-                        let lo = self.next_byte();
-                        let hi = self.next_byte();
-                        let bend = u16_from_midi_bytes(lo, hi);
-                        if let Ok(midi) = MidiPitchBendChangeMessage::CreateMidiPitchBendChangeMessage(channel, bend) {
-                            Ok(Some((dt, WinMidi::PitchBendChange(midi))))
-                        } else {
-                            Err(Error::new(E_FAIL, h!("Failed to create MidiPitchBendChangeMessage").clone()))
+                }
+                0xF0 | 0xF7 => {
+                    running_status = 0;
+                    let length = track.var_len()? as usize;
+                    track.take(length)?;
+                }
+                0xF1..=0xFE => {
+                    return Err(track.error(&format!("Unexpected status {status:02X}"), at));
+                }
+                _ => {
+                    running_status = status;
+                    let length = match hi_nybble(status) {
+                        0xC0 | 0xD0 => 1, // Program change, Channel pressure
+                        _ => 2, // Note off, Note on, Poly Key Pressure, CC, Pitch bend
+                    };
+                    let mut bytes = vec![status];
+                    for _ in 0..length {
+                        let offset = track.index;
+                        let byte = track.byte()?;
+                        if is_bit8(byte) {
+                            return Err(track.error("Expecting a data byte", offset));
                         }
+                        bytes.push(byte);
                     }
-                    _ => {
-                        let msg = format!("Unknown status {status:2X}");
-                        Err(Error::new(E_FAIL, HSTRING::from(msg)))
+                    if let Some(message) = MidiMessage::from_bytes(&bytes) {
+                        self.events.push(Event { ticks, message });
                     }
                 }
             }
         }
+        Ok(()) // no end of track event: the chunk length ends it
+    }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(kind: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut bytes = kind.to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    fn file(format: u16, division: u16, tracks: &[&[u8]]) -> Vec<u8> {
+        let mut header = Vec::new();
+        for word in [format, tracks.len() as u16, division] {
+            header.extend_from_slice(&word.to_be_bytes());
+        }
+        let mut bytes = chunk(b"MThd", &header);
+        for track in tracks {
+            bytes.extend(chunk(b"MTrk", track));
+        }
+        bytes
     }
 
-}
\ No newline at end of file
+    fn read_all(data: &[u8]) -> Result<Vec<(Duration, MidiMessage)>> {
+        let mut reader = ReadMidiFile::new(data);
+        let mut messages = Vec::new();
+        while let Some(message) = reader.next()? {
+            messages.push(message);
+        }
+        Ok(messages)
+    }
+
+    #[test]
+    fn merges_tracks_and_follows_tempo() {
+        // conductor track: tempo 1,000,000us per quarter from tick 96
+        let tempo: &[u8] = &[0x60, 0xFF, 0x51, 3, 0x0F, 0x42, 0x40, 0, 0xFF, 0x2F, 0];
+        // a name meta event and a SysEx are skipped; running status carries over
+        let notes: &[u8] = &[
+            0, 0xFF, 0x03, 2, b'h', b'i', 0, 0xF0, 2, 0x7E, 0xF7, 0x60, 0xB0, 1, 2, 0x60, 3, 4, 0,
+            0xFF, 0x2F, 0,
+        ];
+        let messages = read_all(&file(1, 96, &[tempo, notes])).unwrap();
+        assert_eq!(
+            messages,
+            vec![
+                (Duration::from_millis(500), MidiMessage::cc(0, 1, 2)),
+                (Duration::from_millis(1000), MidiMessage::cc(0, 3, 4)),
+            ]
+        );
+    }
+
+    #[test]
+    fn times_add_up_across_tempo_changes() {
+        // 120 bpm, 60 bpm from tick 100, 240 bpm from tick 200, at 100 ticks per quarter
+        let tempo: &[u8] = &[
+            0, 0xFF, 0x51, 3, 0x07, 0xA1, 0x20, 0x64, 0xFF, 0x51, 3, 0x0F, 0x42, 0x40, 0x64, 0xFF,
+            0x51, 3, 0x03, 0xD0, 0x90, 0, 0xFF, 0x2F, 0,
+        ];
+        let notes: &[u8] = &[
+            0x32, 0xB0, 1, 2, 0x64, 3, 4, 0x64, 5, 6, 0x32, 7, 8, 0, 0xFF, 0x2F, 0,
+        ];
+        let times: Vec<Duration> = read_all(&file(1, 100, &[tempo, notes]))
+            .unwrap()
+            .into_iter()
+            .map(|(dt, _)| dt)
+            .collect();
+        assert_eq!(
+            times,
+            [250, 750, 625, 125].map(Duration::from_millis).to_vec()
+        );
+    }
+
+    #[test]
+    fn smpte_division() {
+        // 25 frames per second, 40 ticks per frame: 1ms per tick
+        let track: &[u8] = &[0x64, 0xD0, 5];
+        let messages = read_all(&file(0, 0xE728, &[track])).unwrap();
+        assert_eq!(messages[0].0, Duration::from_millis(100));
+    }
+
+    #[test]
+    fn damaged_files_give_positioned_errors() {
+        let good = file(0, 96, &[&[0, 0xB0, 1, 2, 0, 0xFF, 0x2F, 0]]);
+        for length in 0..good.len() {
+            // every truncation is an error or a shorter read, never a panic
+            let _ = read_all(&good[..length]);
+        }
+        let truncated = &good[..good.len() - 3];
+        assert_eq!(
+            read_all(truncated),
+            Err(Error::FileFormat("Chunk runs past the end of the file at byte 14".to_string()))
+        );
+        let stray = file(0, 96, &[&[0, 5, 1]]);
+        assert_eq!(
+            read_all(&stray),
+            Err(Error::FileFormat("Data byte without a status at byte 23".to_string()))
+        );
+        let format2 = file(2, 96, &[]);
+        assert_eq!(
+            read_all(&format2),
+            Err(Error::FileFormat("Unsupported MIDI file format 2 at byte 8".to_string()))
+        );
+    }
+}
@@ -0,0 +1,450 @@
+use std::{
+    fs::File,
+    io::{LineWriter, Write},
+    path::Path,
+    sync::{
+        mpsc::{channel, Sender},
+        Arc, Mutex,
+    },
+};
+
+use crate::{
+    error::{Error, Result},
+    midi_message::{MidiMessage, TimedMessage},
+    transport::{Direction, MidiInput, MidiOutput, MidiTransport, PortInfo},
+};
+
+// Session files are text, one message per line:
+//
+//   # harkive session
+//   # device EaganMatrix Module
+//   8123456 out BF 74 55
+//   8123901 in BF 76 55
+//
+// Incoming messages keep the ticks the transport gave them (what `MidiHandler` sees).
+// Outgoing messages carry the ticks of the last message received before them.
+const SESSION_HEADER: &str = "# harkive session";
+const DEVICE_PREFIX: &str = "# device ";
+const REPLAY_PORT_NAME: &str = "Session replay";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionEntry {
+    pub direction: Direction,
+    pub ticks: i64,
+    pub message: MidiMessage,
+}
+
+impl SessionEntry {
+    fn format(&self) -> String {
+        let bytes: Vec<String> = self
+            .message
+            .to_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect();
+        format!(
+            "{} {} {}",
+            self.ticks,
+            match self.direction {
+                Direction::In => "in",
+                Direction::Out => "out",
+            },
+            bytes.join(" ")
+        )
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let ticks = fields.next()?.parse::<i64>().ok()?;
+        let direction = match fields.next()? {
+            "in" => Direction::In,
+            "out" => Direction::Out,
+            _ => return None,
+        };
+        let mut bytes = Vec::new();
+        for field in fields {
+            bytes.push(u8::from_str_radix(field, 16).ok()?);
+        }
+        let message = MidiMessage::from_bytes(&bytes)?;
+        Some(Self {
+            direction,
+            ticks,
+            message,
+        })
+    }
+}
+
+/// A recorded session: the device port name and every message in order.
+#[derive(Default)]
+pub struct Session {
+    pub device: String,
+    pub entries: Vec<SessionEntry>,
+}
+
+impl Session {
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| Error::Io(format!("{}: {error}", path.to_string_lossy())))?;
+        let mut session = Session::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(device) = line.strip_prefix(DEVICE_PREFIX) {
+                session.device = device.to_string();
+            } else if line.starts_with('#') {
+                continue;
+            } else if let Some(entry) = SessionEntry::parse(line) {
+                session.entries.push(entry);
+            } else {
+                return Err(Error::FileFormat(format!(
+                    "{}:{}: not a session entry: {line}",
+                    path.to_string_lossy(),
+                    index + 1
+                )));
+            }
+        }
+        if session.device.is_empty() {
+            return Err(Error::FileFormat(format!(
+                "{} is not a harkive session file",
+                path.to_string_lossy()
+            )));
+        }
+        Ok(session)
+    }
+}
+
+struct SessionLog {
+    file: LineWriter<File>,
+    last_ticks: i64,
+}
+
+impl SessionLog {
+    fn write(&mut self, direction: Direction, ticks: i64, message: &MidiMessage) {
+        if direction == Direction::In {
+            self.last_ticks = ticks;
+        }
+        let entry = SessionEntry {
+            direction,
+            ticks: self.last_ticks,
+            message: message.clone(),
+        };
+        if let Err(error) = writeln!(self.file, "{}", entry.format()) {
+            println!("Error writing session: {error}");
+        }
+    }
+}
+
+/// Transport wrapper that records every message to and from the device.
+pub struct RecordingTransport {
+    inner: Box<dyn MidiTransport>,
+    log: Arc<Mutex<SessionLog>>,
+}
+
+impl RecordingTransport {
+    pub fn new(inner: Box<dyn MidiTransport>, path: &Path) -> Result<Self> {
+        let io_error = |error: std::io::Error| Error::Io(format!("{}: {error}", path.to_string_lossy()));
+        let mut file = LineWriter::new(File::create(path).map_err(io_error)?);
+        writeln!(file, "{SESSION_HEADER}").map_err(io_error)?;
+        Ok(Self {
+            inner,
+            log: Arc::new(Mutex::new(SessionLog {
+                file,
+                last_ticks: 0,
+            })),
+        })
+    }
+}
+
+impl MidiTransport for RecordingTransport {
+    fn ports(&self, direction: Direction) -> Vec<PortInfo> {
+        self.inner.ports(direction)
+    }
+
+    fn open_input(&self, port: &PortInfo) -> Option<Box<dyn MidiInput>> {
+        let inner = self.inner.open_input(port)?;
+        if let Ok(mut log) = self.log.lock() {
+            _ = writeln!(log.file, "{DEVICE_PREFIX}{}", port.name);
+        }
+        Some(Box::new(RecordingInput {
+            inner,
+            log: self.log.clone(),
+        }))
+    }
+
+    fn open_output(&self, port: &PortInfo) -> Option<Box<dyn MidiOutput>> {
+        let inner = self.inner.open_output(port)?;
+        Some(Box::new(RecordingOutput {
+            inner,
+            log: self.log.clone(),
+        }))
+    }
+}
+
+struct RecordingInput {
+    inner: Box<dyn MidiInput>,
+    log: Arc<Mutex<SessionLog>>,
+}
+
+impl MidiInput for RecordingInput {
+    fn listen(&mut self, tx: Sender<TimedMessage>) -> Result<()> {
+        let (inner_tx, inner_rx) = channel::<TimedMessage>();
+        let log = self.log.clone();
+        // ends when the inner port drops its sender
+        std::thread::spawn(move || {
+            while let Ok(msg) = inner_rx.recv() {
+                if let Ok(mut log) = log.lock() {
+                    log.write(Direction::In, msg.ticks, &msg.message);
+                }
+                if tx.send(msg).is_err() {
+                    break;
+                }
+            }
+        });
+        self.inner.listen(inner_tx)
+    }
+
+    fn close(&mut self) {
+        self.inner.close();
+    }
+}
+
+struct RecordingOutput {
+    inner: Box<dyn MidiOutput>,
+    log: Arc<Mutex<SessionLog>>,
+}
+
+impl MidiOutput for RecordingOutput {
+    fn send(&self, message: &MidiMessage) -> Result<()> {
+        if let Ok(mut log) = self.log.lock() {
+            log.write(Direction::Out, 0, message);
+        }
+        self.inner.send(message)
+    }
+
+    fn close(&mut self) {
+        self.inner.close();
+    }
+}
+
+struct ReplayShared {
+    entries: Vec<SessionEntry>,
+    next: usize,
+    tx: Option<Sender<TimedMessage>>,
+}
+
+impl ReplayShared {
+    /// Deliver recorded input up to the next recorded output.
+    ///
+    /// At the end of the session the input is disconnected, which ends the run.
+    fn deliver(&mut self) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        while let Some(entry) = self.entries.get(self.next) {
+            if entry.direction == Direction::Out {
+                return;
+            }
+            _ = tx.send(TimedMessage {
+                ticks: entry.ticks,
+                message: entry.message.clone(),
+            });
+            self.next += 1;
+        }
+        self.tx = None;
+    }
+
+    /// Match `message` with the next recorded output and release the input after it.
+    ///
+    /// Fails on the first output that differs from the recording: the input after it
+    /// would answer a different request, so the replay ends there.
+    fn sent(&mut self, message: &MidiMessage) -> Result<()> {
+        let error = match self.entries.get(self.next) {
+            Some(entry) if entry.direction == Direction::Out && entry.message == *message => {
+                self.next += 1;
+                self.deliver();
+                return Ok(());
+            }
+            Some(entry) if entry.direction == Direction::Out => format!(
+                "Replay: sent {message:?} where the session has {:?} (message {} of the session)",
+                entry.message,
+                self.next + 1
+            ),
+            _ => format!("Replay: sent {message:?} past the end of the session"),
+        };
+        // end the input, so the run stops with the error
+        self.tx = None;
+        Err(Error::Midi(error))
+    }
+}
+
+/// Transport that plays a recorded session back as if it came from the device.
+///
+/// Recorded input is released in step with what is sent, so a stepper sees the
+/// same sequence of messages as in the original run.
+pub struct ReplayTransport {
+    device: String,
+    shared: Arc<Mutex<ReplayShared>>,
+}
+
+impl ReplayTransport {
+    pub fn new(session: Session) -> Self {
+        Self {
+            device: session.device,
+            shared: Arc::new(Mutex::new(ReplayShared {
+                entries: session.entries,
+                next: 0,
+                tx: None,
+            })),
+        }
+    }
+}
+
+impl MidiTransport for ReplayTransport {
+    fn ports(&self, direction: Direction) -> Vec<PortInfo> {
+        vec![PortInfo {
+            direction,
+            name: self.device.clone(),
+            id: REPLAY_PORT_NAME.to_string(),
+        }]
+    }
+
+    fn open_input(&self, _port: &PortInfo) -> Option<Box<dyn MidiInput>> {
+        Some(Box::new(ReplayInput {
+            shared: self.shared.clone(),
+        }))
+    }
+
+    fn open_output(&self, _port: &PortInfo) -> Option<Box<dyn MidiOutput>> {
+        Some(Box::new(ReplayOutput {
+            shared: self.shared.clone(),
+        }))
+    }
+}
+
+struct ReplayInput {
+    shared: Arc<Mutex<ReplayShared>>,
+}
+
+impl MidiInput for ReplayInput {
+    fn listen(&mut self, tx: Sender<TimedMessage>) -> Result<()> {
+        let mut shared = self
+            .shared
+            .lock()
+            .map_err(|_| Error::Midi("Replay lock poisoned".to_string()))?;
+        shared.tx = Some(tx);
+        shared.deliver();
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        if let Ok(mut shared) = self.shared.lock() {
+            shared.tx = None;
+        }
+    }
+}
+
+struct ReplayOutput {
+    shared: Arc<Mutex<ReplayShared>>,
+}
+
+impl MidiOutput for ReplayOutput {
+    fn send(&self, message: &MidiMessage) -> Result<()> {
+        let mut shared = self
+            .shared
+            .lock()
+            .map_err(|_| Error::Midi("Replay lock poisoned".to_string()))?;
+        shared.sent(message)
+    }
+
+    fn close(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        acquire_device::get_haken_io,
+        matrix_handler::MatrixHandler,
+        midi::CHANNEL16,
+        midi_handler::dispatch_midi,
+        options::Action,
+        sim_device::{SimDevice, SimTransport},
+    };
+
+    #[test]
+    fn entry_round_trips_through_text() {
+        let entry = SessionEntry {
+            direction: Direction::Out,
+            ticks: 1234,
+            message: MidiMessage::cc(CHANNEL16, 109, 32),
+        };
+        assert_eq!(entry.format(), "1234 out BF 6D 20");
+        assert_eq!(SessionEntry::parse(&entry.format()), Some(entry));
+        assert_eq!(SessionEntry::parse("12 sideways BF 6D 20"), None);
+    }
+
+    fn list_names(transport: &dyn MidiTransport) -> Vec<String> {
+        let (mut input, output) = get_haken_io(transport, &None).unwrap();
+        let (tx, rx) = channel();
+        input.port.listen(tx).unwrap();
+        let mut handler = MatrixHandler::new(output);
+        handler.start_action(Action::ListNames).unwrap();
+        while let Ok(msg) = rx.recv_timeout(std::time::Duration::from_millis(200)) {
+            dispatch_midi(&mut handler, &msg).unwrap();
+            if handler.is_ready() {
+                break;
+            }
+        }
+        input.port.close();
+        handler.get_presets().iter().map(|p| p.name.clone()).collect()
+    }
+
+    #[test]
+    fn recorded_session_replays() {
+        let path = std::env::temp_dir().join(format!("harkive-session-{}.txt", std::process::id()));
+        let sim = Box::new(SimTransport::new(SimDevice::new()));
+        let recorded = list_names(&RecordingTransport::new(sim, &path).unwrap());
+
+        let session = Session::read(&path).unwrap();
+        _ = std::fs::remove_file(&path);
+        assert!(session.device.contains("EaganMatrix"));
+        assert_eq!(session.entries[0].direction, Direction::Out);
+
+        let replayed = list_names(&ReplayTransport::new(session));
+        assert_eq!(recorded, ["Sine Pad", "Bowed Bass", "Tin Whistle"]);
+        assert_eq!(replayed, recorded);
+    }
+
+    #[test]
+    fn system_messages_in_a_session_are_ignored() {
+        let path = std::env::temp_dir().join(format!("harkive-system-{}.txt", std::process::id()));
+        let sim = Box::new(SimTransport::new(SimDevice::new()));
+        let recorded = list_names(&RecordingTransport::new(sim, &path).unwrap());
+        let mut session = Session::read(&path).unwrap();
+        _ = std::fs::remove_file(&path);
+        let first_in = session.entries.iter().position(|entry| entry.direction == Direction::In).unwrap();
+        let ticks = session.entries[first_in].ticks;
+        for bytes in ["F8", "FE", "FA", "F0 7E 7F 06 01 F7", "F1 12", "F2 01 02"] {
+            let entry = SessionEntry::parse(&format!("{ticks} in {bytes}")).unwrap();
+            session.entries.insert(first_in, entry);
+        }
+        assert_eq!(list_names(&ReplayTransport::new(session)), recorded);
+    }
+
+    #[test]
+    fn replay_stops_at_the_first_different_output() {
+        let session = Session {
+            device: "EaganMatrix Module".to_string(),
+            entries: vec![
+                SessionEntry::parse("0 out BF 6D 20").unwrap(),
+                SessionEntry::parse("10 in BF 6D 21").unwrap(),
+            ],
+        };
+        let transport = ReplayTransport::new(session);
+        let output = transport.open_output(&transport.ports(Direction::Out)[0]).unwrap();
+        let error = output.send(&MidiMessage::cc(CHANNEL16, 109, 39)).unwrap_err();
+        assert!(matches!(&error, Error::Midi(message) if message.contains("where the session has")));
+    }
+}
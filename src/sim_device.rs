@@ -0,0 +1,775 @@
+use std::{
+    sync::{mpsc::Sender, Arc, Mutex},
+    time::Instant,
+};
+
+use crate::{
+    error::{Error, Result},
+    haken_midi::cc16,
+    midi::*,
+    midi_message::{MidiMessage, TimedMessage},
+    transport::{Direction, MidiInput, MidiOutput, MidiTransport, PortInfo},
+};
+
+/// Device name that selects the simulator (`--device sim`).
+pub const SIM_DEVICE_NAME: &str = "sim";
+const SIM_PORT_NAME: &str = "EaganMatrix Simulator";
+const USER_SLOTS: usize = 128;
+// reported as ch16 FirmwareVersionHi/Lo when the editor announces itself
+const SIM_FIRMWARE: u16 = 1009;
+
+/// A preset as held by the simulator.
+///
+/// `matrix` is everything in the archive other than the name, text and category streams,
+/// kept as messages so an archive sent back out matches what was received.
+#[derive(Clone, Default)]
+pub struct SimPreset {
+    pub name: String,
+    pub text: String,
+    pub category: String,
+    pub matrix: Vec<MidiMessage>,
+}
+
+impl SimPreset {
+    fn demo(name: &str, text: &str, seed: u8) -> Self {
+        let mut matrix = Vec::new();
+        for cc in 1..=8u8 {
+            matrix.push(MidiMessage::cc(CHANNEL15, cc, (cc * 13 + seed) & 0x7F));
+        }
+        // a short binary stream, as the Graph data of a real preset
+        matrix.push(MidiMessage::cc(
+            CHANNEL16,
+            cc16::DataStream,
+            cc16::DataStream_Graph,
+        ));
+        for byte in 0..8u8 {
+            matrix.push(MidiMessage::channel_pressure(
+                CHANNEL16,
+                (byte * 7 + seed) & 0x7F,
+            ));
+        }
+        matrix.push(MidiMessage::cc(
+            CHANNEL16,
+            cc16::DataStream,
+            cc16::DataStream_End,
+        ));
+        Self {
+            name: name.to_string(),
+            text: text.to_string(),
+            category: String::new(),
+            matrix,
+        }
+    }
+
+    fn add_stream(messages: &mut Vec<MidiMessage>, kind: u8, text: &str) {
+        messages.push(MidiMessage::cc(CHANNEL16, cc16::DataStream, kind));
+        for ch in text.bytes() {
+            messages.push(MidiMessage::channel_pressure(CHANNEL16, ch & 0x7F));
+        }
+        messages.push(MidiMessage::cc(
+            CHANNEL16,
+            cc16::DataStream,
+            cc16::DataStream_End,
+        ));
+    }
+
+    fn archive(&self) -> Vec<MidiMessage> {
+        let mut messages = Vec::new();
+        Self::add_stream(&mut messages, cc16::DataStream_Name, &self.name);
+        Self::add_stream(&mut messages, cc16::DataStream_Text, &self.text);
+        if !self.category.is_empty() {
+            Self::add_stream(&mut messages, cc16::DataStream_Category, &self.category);
+        }
+        messages.extend(self.matrix.iter().cloned());
+        messages
+    }
+}
+
+#[derive(Copy, Clone, Default, PartialEq)]
+enum Stream {
+    #[default]
+    None,
+    Name,
+    Text,
+    Category,
+    Other,
+}
+
+/// In-memory model of an EaganMatrix device, speaking the ch16 protocol `MatrixHandler` uses.
+///
+/// Messages sent to the device go through `receive`; replies are queued until taken
+/// with `take_replies`.
+pub struct SimDevice {
+    slots: Vec<Option<SimPreset>>,
+    // (cat-code bank, preset)
+    system: Vec<(u8, SimPreset)>,
+    edit: SimPreset,
+    bank_hi: u8,
+    stream: Stream,
+    stream_text: String,
+    receiving: Option<Vec<MidiMessage>>,
+    store_slot: Option<usize>,
+    replies: Vec<MidiMessage>,
+    dropped_acks: usize,
+}
+
+impl Default for SimDevice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimDevice {
+    pub fn new() -> Self {
+        let mut slots = vec![None; USER_SLOTS];
+        slots[0] = Some(SimPreset::demo("Sine Pad", "C=OT_SU", 1));
+        slots[1] = Some(SimPreset::demo("Bowed Bass", "C=ST_BA", 2));
+        slots[2] = Some(SimPreset::demo("Tin Whistle", "C=WI", 3));
+        let system = vec![
+            (1, SimPreset::demo("Cello Section", "C=ST_BO_EN", 4)),
+            (1, SimPreset::demo("Upright Bass", "C=ST_BA_AC", 5)),
+            (2, SimPreset::demo("Breathy Flute", "C=WI_FL_AI", 6)),
+            (13, SimPreset::demo("Tuning Check", "C=UT", 7)),
+        ];
+        Self {
+            edit: slots[0].clone().unwrap_or_default(),
+            slots,
+            system,
+            bank_hi: 0,
+            stream: Stream::None,
+            stream_text: String::new(),
+            receiving: None,
+            store_slot: None,
+            replies: Vec::new(),
+            dropped_acks: 0,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn slot(&self, index: usize) -> Option<&SimPreset> {
+        self.slots.get(index).and_then(|slot| slot.as_ref())
+    }
+
+    /// Lose the next `count` DspDone replies, as a flaky connection would.
+    #[cfg(test)]
+    pub fn drop_acks(&mut self, count: usize) {
+        self.dropped_acks = count;
+    }
+
+    pub fn take_replies(&mut self) -> Vec<MidiMessage> {
+        std::mem::take(&mut self.replies)
+    }
+
+    fn reply_cc(&mut self, cc: u8, value: u8) {
+        self.replies.push(MidiMessage::cc(CHANNEL16, cc, value));
+    }
+
+    fn dsp_done(&mut self) {
+        if self.dropped_acks > 0 {
+            self.dropped_acks -= 1;
+            return;
+        }
+        self.reply_cc(cc16::DownloadControl, cc16::DownloadControl_DspDone);
+    }
+
+    fn send_name_entry(&mut self, bank_hi: u8, number: u8, name: &str, text: &str) {
+        self.reply_cc(cc16::BankSelect, bank_hi);
+        self.reply_cc(cc16::PresetGroup, 0);
+        let mut messages = Vec::new();
+        SimPreset::add_stream(&mut messages, cc16::DataStream_Name, name);
+        SimPreset::add_stream(&mut messages, cc16::DataStream_Text, text);
+        self.replies.extend(messages);
+        self.replies
+            .push(MidiMessage::program_change(CHANNEL16, number));
+    }
+
+    fn send_names(&mut self) {
+        self.reply_cc(cc16::DownloadControl, cc16::DownloadControl_BeginUserNames);
+        for index in 0..USER_SLOTS {
+            let (name, text) = match &self.slots[index] {
+                Some(preset) => (preset.name.clone(), preset.text.clone()),
+                None => ("-".to_string(), String::new()),
+            };
+            self.send_name_entry(0, index as u8, &name, &text);
+        }
+        self.reply_cc(cc16::DownloadControl, cc16::DownloadControl_EndUserNames);
+    }
+
+    /// System presets are numbered from zero within each cat-code bank.
+    fn send_system_names(&mut self) {
+        self.reply_cc(cc16::DownloadControl, cc16::DownloadControl_BeginSystemNames);
+        let mut number = 0;
+        for index in 0..self.system.len() {
+            let (bank, preset) = self.system[index].clone();
+            if index > 0 && self.system[index - 1].0 != bank {
+                number = 0;
+            }
+            self.send_name_entry(bank, number, &preset.name, &preset.text);
+            number += 1;
+        }
+        self.reply_cc(cc16::DownloadControl, cc16::DownloadControl_EndSystemNames);
+    }
+
+    /// Report the edit preset, as the device does when it is assigned a slot.
+    fn send_edit_info(&mut self, number: u8) {
+        self.reply_cc(cc16::BankSelect, self.bank_hi);
+        self.reply_cc(cc16::PresetGroup, 0);
+        let mut messages = Vec::new();
+        SimPreset::add_stream(&mut messages, cc16::DataStream_Name, &self.edit.name);
+        SimPreset::add_stream(&mut messages, cc16::DataStream_Text, &self.edit.text);
+        self.replies.extend(messages);
+        self.replies
+            .push(MidiMessage::program_change(CHANNEL16, number));
+    }
+
+    fn send_archive(&mut self) {
+        self.reply_cc(cc16::DownloadInfo, cc16::DownloadInfo_BeginArchive);
+        let archive = self.edit.archive();
+        self.replies.extend(archive);
+        self.reply_cc(cc16::DownloadInfo, cc16::DownloadInfo_EndArchive);
+    }
+
+    /// Build the edit preset from a received archive.
+    fn finish_archive(&mut self, messages: Vec<MidiMessage>) -> bool {
+        let mut preset = SimPreset::default();
+        let mut stream = Stream::None;
+        for message in messages {
+            match message {
+                MidiMessage::ControlChange {
+                    channel: CHANNEL16,
+                    cc: cc16::DataStream,
+                    value,
+                } => {
+                    let previous = stream;
+                    stream = match value {
+                        cc16::DataStream_Name => Stream::Name,
+                        cc16::DataStream_Text => Stream::Text,
+                        cc16::DataStream_Category => Stream::Category,
+                        cc16::DataStream_End => Stream::None,
+                        _ => Stream::Other,
+                    };
+                    if stream == Stream::Other
+                        || (value == cc16::DataStream_End && previous == Stream::Other)
+                    {
+                        preset.matrix.push(message);
+                    }
+                }
+                MidiMessage::ChannelPressure {
+                    channel: CHANNEL16,
+                    pressure,
+                } if matches!(stream, Stream::Name | Stream::Text | Stream::Category) => {
+                    let text = match stream {
+                        Stream::Name => &mut preset.name,
+                        Stream::Text => &mut preset.text,
+                        _ => &mut preset.category,
+                    };
+                    text.push(pressure as char);
+                }
+                _ => preset.matrix.push(message),
+            }
+        }
+        if preset.matrix.is_empty() && preset.name.is_empty() {
+            return false;
+        }
+        self.edit = preset;
+        true
+    }
+
+    fn on_download_control(&mut self, value: u8) {
+        match value {
+            cc16::DownloadControl_SaveToFlash => {
+                if let Some(slot) = self.store_slot.take() {
+                    self.slots[slot] = Some(self.edit.clone());
+                }
+                self.dsp_done();
+            }
+            cc16::DownloadControl_RequestUserNames => self.send_names(),
+            cc16::DownloadControl_RequestSystemNames => self.send_system_names(),
+            115..=122 => {
+                let bank = (value - cc16::DownloadControl_ClearBank) as usize;
+                for slot in self.slots.iter_mut().skip(bank * 16).take(16) {
+                    *slot = None;
+                }
+                self.dsp_done();
+            }
+            _ => {}
+        }
+    }
+
+    fn on_ch16_cc(&mut self, cc: u8, value: u8) {
+        match cc {
+            cc16::BankSelect => self.bank_hi = value,
+            cc16::DataStream => {
+                if value == cc16::DataStream_End {
+                    if self.stream == Stream::Name {
+                        self.edit.name = std::mem::take(&mut self.stream_text);
+                    }
+                    self.stream = Stream::None;
+                } else {
+                    self.stream = if value == cc16::DataStream_Name {
+                        Stream::Name
+                    } else {
+                        Stream::Other
+                    };
+                    self.stream_text.clear();
+                }
+            }
+            cc16::DownloadControl => self.on_download_control(value),
+            cc16::DownloadInfo => match value {
+                cc16::DownloadInfo_ArchiveCurrent => self.send_archive(),
+                cc16::DownloadInfo_RetrieveArchive => self.receiving = Some(Vec::new()),
+                _ => {}
+            },
+            cc16::EditorPresent => {
+                self.reply_cc(cc16::FirmwareVersionHi, (SIM_FIRMWARE >> 7) as u8);
+                self.reply_cc(cc16::FirmwareVersionLo, (SIM_FIRMWARE & 0x7F) as u8);
+                self.reply_cc(cc16::EditorReply, value);
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle one message sent to the device.
+    pub fn receive(&mut self, message: &MidiMessage) {
+        if let Some(archive) = &mut self.receiving {
+            if *message
+                == MidiMessage::cc(CHANNEL16, cc16::DownloadInfo, cc16::DownloadInfo_EndArchive)
+            {
+                let messages = std::mem::take(archive);
+                self.receiving = None;
+                let value = if self.finish_archive(messages) {
+                    cc16::DownloadControl_ArchiveOk
+                } else {
+                    cc16::DownloadControl_ArchiveFail
+                };
+                self.reply_cc(cc16::DownloadControl, value);
+            } else if let MidiMessage::ControlChange {
+                channel: CHANNEL16,
+                cc: cc16::EditorPresent,
+                value,
+            } = *message
+            {
+                // pacing round trips are answered, not archived
+                self.reply_cc(cc16::EditorReply, value);
+            } else {
+                archive.push(message.clone());
+            }
+            return;
+        }
+        match *message {
+            MidiMessage::ControlChange {
+                channel: CHANNEL16,
+                cc,
+                value,
+            } => self.on_ch16_cc(cc, value),
+            MidiMessage::ChannelPressure {
+                channel: CHANNEL16,
+                pressure,
+            } if self.stream == Stream::Name => {
+                self.stream_text.push(pressure as char);
+            }
+            MidiMessage::ProgramChange {
+                channel: CHANNEL16,
+                program,
+            } if self.bank_hi == 0 => {
+                // select a preset into the edit slot
+                if let Some(Some(preset)) = self.slots.get(program as usize) {
+                    self.edit = preset.clone();
+                }
+            }
+            MidiMessage::ProgramChange {
+                channel: CHANNEL16,
+                program,
+            } if self.bank_hi != 126 => {
+                // select a system preset from its cat-code bank
+                let bank_hi = self.bank_hi;
+                if let Some((_, preset)) = self
+                    .system
+                    .iter()
+                    .filter(|(bank, _)| *bank == bank_hi)
+                    .nth(program as usize)
+                {
+                    self.edit = preset.clone();
+                }
+            }
+            MidiMessage::ProgramChange {
+                channel: CHANNEL15,
+                program,
+            } => {
+                // choose where SaveToFlash stores the edit slot
+                self.store_slot = if self.bank_hi == 0 {
+                    Some(program as usize % USER_SLOTS)
+                } else {
+                    None
+                };
+                self.send_edit_info(program);
+            }
+            _ => {}
+        }
+    }
+}
+
+struct SimShared {
+    device: SimDevice,
+    tx: Option<Sender<TimedMessage>>,
+    pending: Vec<MidiMessage>,
+    start: Instant,
+}
+
+impl SimShared {
+    fn deliver(&mut self) {
+        self.pending.extend(self.device.take_replies());
+        if let Some(tx) = &self.tx {
+            let ticks = (self.start.elapsed().as_nanos() / 100) as i64;
+            for message in self.pending.drain(..) {
+                if tx.send(TimedMessage { ticks, message }).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Transport exposing a single simulated device.
+pub struct SimTransport {
+    shared: Arc<Mutex<SimShared>>,
+}
+
+impl SimTransport {
+    pub fn new(device: SimDevice) -> Self {
+        Self {
+            shared: Arc::new(Mutex::new(SimShared {
+                device,
+                tx: None,
+                pending: Vec::new(),
+                start: Instant::now(),
+            })),
+        }
+    }
+
+    fn port(direction: Direction) -> PortInfo {
+        PortInfo {
+            direction,
+            name: SIM_PORT_NAME.to_string(),
+            id: SIM_DEVICE_NAME.to_string(),
+        }
+    }
+}
+
+impl MidiTransport for SimTransport {
+    fn ports(&self, direction: Direction) -> Vec<PortInfo> {
+        vec![Self::port(direction)]
+    }
+
+    fn open_input(&self, _port: &PortInfo) -> Option<Box<dyn MidiInput>> {
+        Some(Box::new(SimInput {
+            shared: self.shared.clone(),
+        }))
+    }
+
+    fn open_output(&self, _port: &PortInfo) -> Option<Box<dyn MidiOutput>> {
+        Some(Box::new(SimOutput {
+            shared: self.shared.clone(),
+        }))
+    }
+}
+
+struct SimInput {
+    shared: Arc<Mutex<SimShared>>,
+}
+
+impl MidiInput for SimInput {
+    fn listen(&mut self, tx: Sender<TimedMessage>) -> Result<()> {
+        let mut shared = self
+            .shared
+            .lock()
+            .map_err(|_| Error::Midi("Simulator lock poisoned".to_string()))?;
+        shared.tx = Some(tx);
+        shared.deliver();
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        if let Ok(mut shared) = self.shared.lock() {
+            shared.tx = None;
+        }
+    }
+}
+
+struct SimOutput {
+    shared: Arc<Mutex<SimShared>>,
+}
+
+impl MidiOutput for SimOutput {
+    fn send(&self, message: &MidiMessage) -> Result<()> {
+        let mut shared = self
+            .shared
+            .lock()
+            .map_err(|_| Error::Midi("Simulator lock poisoned".to_string()))?;
+        shared.device.receive(message);
+        shared.deliver();
+        Ok(())
+    }
+
+    fn close(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        acquire_device::get_haken_io, continuum_preset::HCCategoryCode,
+        matrix_handler::{ArchiveState, MatrixHandler},
+        midi_handler::dispatch_midi,
+        options::{Action, Options},
+        preset_manager::PresetManager,
+        step_names::group_system_presets,
+        step_backup::BackupFirst,
+        step_clear::Clearer,
+        stepper::{Stepper, WorkingStatus},
+        watchdog::{Phase, RetryPolicy},
+    };
+    use std::{sync::mpsc::channel, time::Duration};
+
+    fn pump(handler: &mut MatrixHandler, rx: &std::sync::mpsc::Receiver<TimedMessage>) {
+        while let Ok(msg) = rx.try_recv() {
+            dispatch_midi(handler, &msg).unwrap();
+        }
+    }
+
+    fn connect(
+        transport: &SimTransport,
+    ) -> (MatrixHandler, std::sync::mpsc::Receiver<TimedMessage>) {
+        let name = Some(SIM_DEVICE_NAME.to_string());
+        let (mut input, output) = get_haken_io(transport, &name).unwrap();
+        let (tx, rx) = channel();
+        input.port.listen(tx).unwrap();
+        (MatrixHandler::new(output), rx)
+    }
+
+    #[test]
+    fn lists_user_names() {
+        let transport = SimTransport::new(SimDevice::new());
+        let (mut handler, rx) = connect(&transport);
+        handler.start_action(Action::ListNames).unwrap();
+        pump(&mut handler, &rx);
+        assert!(handler.is_ready());
+        let names: Vec<&str> = handler
+            .get_presets()
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        assert_eq!(names, ["Sine Pad", "Bowed Bass", "Tin Whistle"]);
+        assert_eq!(handler.get_presets()[1].number, 1);
+    }
+
+    #[test]
+    fn archive_round_trips_through_retrieve() {
+        let mut device = SimDevice::new();
+        let original = device.slot(1).unwrap().clone();
+        device.receive(&MidiMessage::cc(CHANNEL16, cc16::BankSelect, 0));
+        device.receive(&MidiMessage::program_change(CHANNEL16, 1));
+        device.receive(&MidiMessage::cc(
+            CHANNEL16,
+            cc16::DownloadInfo,
+            cc16::DownloadInfo_ArchiveCurrent,
+        ));
+        let archive = device.take_replies();
+        assert_eq!(
+            archive.first(),
+            Some(&MidiMessage::cc(
+                CHANNEL16,
+                cc16::DownloadInfo,
+                cc16::DownloadInfo_BeginArchive
+            ))
+        );
+
+        device.receive(&MidiMessage::cc(
+            CHANNEL16,
+            cc16::DownloadInfo,
+            cc16::DownloadInfo_RetrieveArchive,
+        ));
+        for message in archive.iter().skip(1) {
+            device.receive(message);
+        }
+        assert_eq!(
+            device.take_replies(),
+            [MidiMessage::cc(
+                CHANNEL16,
+                cc16::DownloadControl,
+                cc16::DownloadControl_ArchiveOk
+            )]
+        );
+        device.receive(&MidiMessage::program_change(CHANNEL15, 9));
+        device.receive(&MidiMessage::cc(
+            CHANNEL16,
+            cc16::DownloadControl,
+            cc16::DownloadControl_SaveToFlash,
+        ));
+        let stored = device.slot(9).unwrap();
+        assert_eq!(stored.name, original.name);
+        assert_eq!(stored.text, original.text);
+        assert_eq!(stored.matrix, original.matrix);
+    }
+
+    #[test]
+    fn lists_system_names_by_bank() {
+        let transport = SimTransport::new(SimDevice::new());
+        let (mut handler, rx) = connect(&transport);
+        handler.start_action(Action::ListSystem).unwrap();
+        pump(&mut handler, &rx);
+        assert!(handler.is_ready());
+        let catcode = HCCategoryCode::new();
+        let listed: Vec<(String, u8, String)> = group_system_presets(handler.get_presets())
+            .iter()
+            .map(|p| (catcode.bank_name(p.bank_hi), p.number, p.name.clone()))
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                ("Strings".to_string(), 0, "Cello Section".to_string()),
+                ("Strings".to_string(), 1, "Upright Bass".to_string()),
+                ("Winds".to_string(), 0, "Breathy Flute".to_string()),
+                ("Utility".to_string(), 0, "Tuning Check".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn names_after_an_archive_start_clean() {
+        let transport = SimTransport::new(SimDevice::new());
+        let (mut handler, rx) = connect(&transport);
+        handler.start_action(Action::SaveCurrent).unwrap();
+        pump(&mut handler, &rx);
+        assert!(!handler.get_archive_data().is_empty());
+        handler.clear_presets();
+        handler.transmit_system_names().unwrap();
+        pump(&mut handler, &rx);
+        assert_eq!(handler.get_presets()[0].name, "Cello Section");
+    }
+
+    #[test]
+    fn clear_empties_all_banks() {
+        let transport = SimTransport::new(SimDevice::new());
+        let (mut handler, rx) = connect(&transport);
+        handler.start_action(Action::Clear).unwrap();
+        pump(&mut handler, &rx);
+        assert!(handler.is_ready());
+        handler.start_action(Action::ListNames).unwrap();
+        pump(&mut handler, &rx);
+        assert!(handler.get_presets().is_empty());
+    }
+
+    #[test]
+    fn backup_runs_before_clear() {
+        let folder = std::env::temp_dir().join(format!("harkive-backup-{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        let transport = SimTransport::new(SimDevice::new());
+        let (mut handler, rx) = connect(&transport);
+        let options = Options::default();
+        let mut stepper = BackupFirst::new(folder.clone(), Action::Clear, Box::new(Clearer::new()));
+        handler.start_action(Action::Save).unwrap();
+        // step only when the handler is ready, as PresetManager does
+        loop {
+            pump(&mut handler, &rx);
+            if handler.is_ready()
+                && stepper.next(&options, &mut handler).unwrap() == WorkingStatus::Finished
+            {
+                break;
+            }
+        }
+        let listing = std::fs::read_to_string(folder.join("UserPresets.txt")).unwrap();
+        assert!(folder.join("Sine Pad.mid").is_file());
+        _ = std::fs::remove_dir_all(&folder);
+        assert!(listing.contains("\"Tin Whistle.mid\""));
+
+        handler.start_action(Action::ListNames).unwrap();
+        pump(&mut handler, &rx);
+        assert!(handler.get_presets().is_empty());
+    }
+
+    #[test]
+    fn a_failed_start_stops_the_input_thread() {
+        let file = std::env::temp_dir().join(format!("harkive-not-a-folder-{}", std::process::id()));
+        std::fs::write(&file, b"").unwrap();
+        let transport = SimTransport::new(SimDevice::new());
+        let mut options = Options::with_path(Action::Load, &file);
+        // the backup can't make a folder in a file
+        options.backup = Some(file.clone());
+        let catcode = HCCategoryCode::new();
+        let result = PresetManager::new(&transport, &options, &catcode).unwrap().run();
+        _ = std::fs::remove_file(&file);
+        assert!(matches!(result, Err(Error::Io(_))));
+        // the thread listening to the device has closed its port and ended, dropping it
+        assert!(transport.shared.lock().unwrap().tx.is_none());
+        assert_eq!(Arc::strong_count(&transport.shared), 1);
+    }
+
+    #[test]
+    fn clear_retries_a_lost_dsp_done() {
+        let mut device = SimDevice::new();
+        device.drop_acks(1);
+        let transport = SimTransport::new(device);
+        let (mut handler, rx) = connect(&transport);
+        handler.set_retry_policy(RetryPolicy {
+            timeout: Some(Duration::ZERO),
+            retries: 1,
+        });
+        let options = Options::default();
+        let mut clearer = Clearer::new();
+        handler.start_action(Action::Clear).unwrap();
+        handler.expect(Phase::ClearBank(0), None);
+        pump(&mut handler, &rx);
+        assert!(!handler.dsp_done());
+
+        assert_eq!(handler.check_timeout(), Ok(true));
+        clearer.retry(&options, &mut handler).unwrap();
+        loop {
+            pump(&mut handler, &rx);
+            if clearer.next(&options, &mut handler).unwrap() == WorkingStatus::Finished {
+                break;
+            }
+        }
+        assert_eq!(handler.check_timeout(), Ok(false));
+    }
+
+    #[test]
+    fn acknowledged_upload_outlasts_the_upload_timeout() {
+        // a scaled-down slow link: each burst takes a third of the timeout, and the
+        // whole upload more than the timeout, as a 5 s upload over slow USB would
+        let transport = SimTransport::new(SimDevice::new());
+        let (mut handler, rx) = connect(&transport);
+        handler.set_retry_policy(RetryPolicy {
+            timeout: Some(Duration::from_millis(150)),
+            retries: 0,
+        });
+        handler.expect(Phase::Upload, Some(1));
+        handler
+            .send_cc(CHANNEL16, cc16::DownloadInfo, cc16::DownloadInfo_RetrieveArchive)
+            .unwrap();
+        let mut archive = SimPreset::demo("Long Pad", "C=OT", 9).archive().into_iter();
+        let started = Instant::now();
+        for _ in 0..6 {
+            std::thread::sleep(Duration::from_millis(50));
+            for message in archive.by_ref().take(4) {
+                handler.send(&message).unwrap();
+            }
+            handler.editor_present().unwrap();
+            // as PresetManager does for each message received
+            while let Ok(msg) = rx.try_recv() {
+                handler.note_activity(&msg.message);
+                dispatch_midi(&mut handler, &msg).unwrap();
+            }
+            assert_eq!(handler.check_timeout(), Ok(false));
+        }
+        assert!(started.elapsed() > Duration::from_millis(150));
+        for message in archive {
+            handler.send(&message).unwrap();
+        }
+        handler
+            .send_cc(CHANNEL16, cc16::DownloadInfo, cc16::DownloadInfo_EndArchive)
+            .unwrap();
+        pump(&mut handler, &rx);
+        assert!(handler.archive_state() == ArchiveState::Ok);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{
+    error::{Error, Result},
+    matrix_handler::MatrixHandler,
+    options::{Action, Options},
+    step_save::Saver,
+    stepper::*,
+    util::timestamp_now,
+};
+use std::path::{Path, PathBuf};
+
+const DEFAULT_BACKUP_FOLDER: &str = "harkive-backups";
+
+/// Saves every user preset to a timestamped folder, then runs the action
+/// that would overwrite them. A failed backup ends the run before the
+/// action sends anything.
+pub struct BackupFirst {
+    saver: Saver,
+    action: Action,
+    then: Box<dyn Stepper>,
+    backing_up: bool,
+}
+
+/// Create a new timestamped backup folder, or None when turned off with --no-backup.
+pub fn backup_folder(options: &Options) -> Result<Option<PathBuf>> {
+    if options.no_backup {
+        return Ok(None);
+    }
+    let base = options
+        .backup
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_BACKUP_FOLDER));
+    match new_folder(&base, &timestamp_now()) {
+        Ok(folder) => Ok(Some(folder)),
+        Err(error) => Err(Error::Io(format!(
+            "Backup failed, nothing was changed: couldn't create a folder in '{}' : {error}",
+            base.display()
+        ))),
+    }
+}
+
+/// Create `base/name`, or `base/name-2`, `-3`... when a backup already has the name, so
+/// two runs in the same second never share a folder.
+fn new_folder(base: &Path, name: &str) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(base)?;
+    for count in 1.. {
+        let folder = match count {
+            1 => base.join(name),
+            _ => base.join(format!("{name}-{count}")),
+        };
+        match std::fs::create_dir(&folder) {
+            Ok(()) => return Ok(folder),
+            Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        }
+    }
+    unreachable!()
+}
+
+impl BackupFirst {
+    pub fn new(folder: PathBuf, action: Action, then: Box<dyn Stepper>) -> Self {
+        println!("Backing up user presets to '{}'", folder.display());
+        Self {
+            saver: Saver::in_folder(folder),
+            action,
+            then,
+            backing_up: true,
+        }
+    }
+}
+
+impl Stepper for BackupFirst {
+    fn next(&mut self, options: &Options, handler: &mut MatrixHandler) -> Result<WorkingStatus> {
+        if !self.backing_up {
+            return self.then.next(options, handler);
+        }
+        let status = self
+            .saver
+            .next(options, handler)
+            .map_err(|error| error.context("Backup failed, nothing was changed"))?;
+        if WorkingStatus::Finished == status {
+            println!("Backup complete");
+            self.backing_up = false;
+            handler.start_action(self.action)?;
+        }
+        Ok(WorkingStatus::Working)
+    }
+
+    fn retry(&mut self, options: &Options, handler: &mut MatrixHandler) -> Result<()> {
+        if self.backing_up {
+            self.saver.retry(options, handler)
+        } else {
+            self.then.retry(options, handler)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backups_in_the_same_second_get_their_own_folders() {
+        let base = std::env::temp_dir().join(format!("harkive-backups-{}", std::process::id()));
+        let first = new_folder(&base, "20240102-030405").unwrap();
+        let second = new_folder(&base, "20240102-030405").unwrap();
+        let third = new_folder(&base, "20240102-030405").unwrap();
+        _ = std::fs::remove_dir_all(&base);
+        assert_eq!(first, base.join("20240102-030405"));
+        assert_eq!(second, base.join("20240102-030405-2"));
+        assert_eq!(third, base.join("20240102-030405-3"));
+    }
+}
@@ -0,0 +1,51 @@
+use crate::error::Result;
+use crate::{
+    matrix_handler::MatrixHandler, options::Options, stepper::*, watchdog::Phase,
+};
+
+const USER_BANKS: u8 = 8;
+
+/// Clears the user banks one at a time, each acknowledged by DspDone.
+///
+/// The handler sends the clear for bank 0 when the action starts.
+pub struct Clearer {
+    bank: u8,
+}
+
+impl Clearer {
+    pub fn new() -> Self {
+        Self { bank: 0 }
+    }
+}
+
+/// Print which banks a clear would erase, without a device.
+pub fn print_clear_plan() {
+    println!("Dry run: nothing is sent to the device");
+    for bank in 0..USER_BANKS {
+        let first = 1 + bank as u16 * 16;
+        println!("Would clear user bank {bank}: slots {first}-{}", first + 15);
+    }
+}
+
+impl Stepper for Clearer {
+    fn next(&mut self, _options: &Options, handler: &mut MatrixHandler) -> Result<WorkingStatus> {
+        if !handler.dsp_done() {
+            handler.not_ready();
+            return Ok(WorkingStatus::Working);
+        }
+        self.bank += 1;
+        if self.bank < USER_BANKS {
+            handler.clear_bank(self.bank)?;
+            handler.expect(Phase::ClearBank(self.bank), None);
+            handler.not_ready();
+            Ok(WorkingStatus::Working)
+        } else {
+            handler.satisfied();
+            Ok(WorkingStatus::Finished)
+        }
+    }
+
+    fn retry(&mut self, _options: &Options, handler: &mut MatrixHandler) -> Result<()> {
+        handler.clear_bank(self.bank)
+    }
+}
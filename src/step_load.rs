@@ -1,17 +1,25 @@
 use crate::{
     continuum_preset::{ContinuumPreset, PresetBuilder},
+    error::{Error, Result},
     haken_midi::cc16,
     matrix_handler::{ArchiveState, MatrixHandler},
     midi::CHANNEL16,
+    midi_message::MidiMessage,
     options::Options,
+    pacing::Pacer,
     preset_listing::*,
+    preset_metadata::FolderMetadata,
     read_midi_file::ReadMidiFile,
     stepper::*,
     util::is_extension,
+    watchdog::Phase,
 };
 use std::path::PathBuf;
 use std::io::Write;
-use windows::{core::*, Win32::Foundation::E_FAIL};
+use std::time::{Duration, Instant};
+
+// longest to wait for DspDone once the device has answered after SaveToFlash
+const FLASH_SETTLE: Duration = Duration::from_secs(1);
 
 #[derive(Copy, Clone, Default, PartialEq)]
 enum SendState {
@@ -22,26 +30,84 @@ enum SendState {
     Name,
     Save,
     Finish,
-}
-
-fn busy_wait(dt: &std::time::Duration) {
-    if dt.is_zero() { return; }
-    let start = std::time::Instant::now();
-    loop {
-        let elapsed = start.elapsed();
-        if elapsed.is_zero() { break; }
-        if elapsed > *dt { break; }
-        std::thread::yield_now();
-    }
+    Verify,
 }
 
 pub struct PresetLoader {
     initialized: bool,
     index: usize,
     state: SendState,
-    is_data_sent: bool,
     presets: Vec<ContinuumPreset>,
     folder: PathBuf,
+    pacer: Pacer,
+    upload: Vec<MidiMessage>,
+    sent: usize,
+    burst_started: Option<Instant>,
+    upload_started: Instant,
+    saved: Instant,
+    load_started: Instant,
+    // (slot, difference) for each preset that failed --verify
+    mismatches: Vec<(u8, String)>,
+    // what was saved with the presets in the folder, when harkive saved them
+    metadata: FolderMetadata,
+    // presets saved with firmware other than the device's
+    firmware_mismatches: usize,
+}
+
+fn listing_mode(options: &Options) -> ListingMode {
+    if options.lenient {
+        ListingMode::Lenient
+    } else {
+        ListingMode::Strict
+    }
+}
+
+/// Archive messages without the name stream, which a load sets from the listing.
+fn comparable(messages: &[MidiMessage]) -> Vec<MidiMessage> {
+    let mut result = Vec::new();
+    let mut in_name = false;
+    for message in messages {
+        if let MidiMessage::ControlChange {
+            channel: CHANNEL16,
+            cc: cc16::DataStream,
+            value,
+        } = *message
+        {
+            if value == cc16::DataStream_Name {
+                in_name = true;
+                continue;
+            }
+            if in_name {
+                in_name = false;
+                if value == cc16::DataStream_End {
+                    continue;
+                }
+            }
+        }
+        if !in_name {
+            result.push(message.clone());
+        }
+    }
+    result
+}
+
+/// Describe the first difference between the archive sent and the one read back.
+fn compare_read_back(sent: &[MidiMessage], received: &[MidiMessage]) -> Option<String> {
+    let sent = comparable(sent);
+    let received = comparable(received);
+    for (index, (a, b)) in sent.iter().zip(received.iter()).enumerate() {
+        if a != b {
+            return Some(format!("message {}: sent {a:?}, read back {b:?}", index + 1));
+        }
+    }
+    if sent.len() != received.len() {
+        return Some(format!(
+            "{} messages sent, {} read back",
+            sent.len(),
+            received.len()
+        ));
+    }
+    None
 }
 
 impl PresetLoader {
@@ -50,18 +116,24 @@ impl PresetLoader {
             initialized: false,
             index: usize::MAX,
             state: SendState::default(),
-            is_data_sent: false,
             presets: Vec::new(),
             folder: PathBuf::default(),
+            pacer: Pacer::default(),
+            upload: Vec::new(),
+            sent: 0,
+            burst_started: None,
+            upload_started: Instant::now(),
+            saved: Instant::now(),
+            load_started: Instant::now(),
+            mismatches: Vec::new(),
+            metadata: FolderMetadata::default(),
+            firmware_mismatches: 0,
         }
     }
 
-    fn fail(message: &str) -> Result<WorkingStatus> {
-        Err(Error::new(E_FAIL, HSTRING::from(message)))
-    }
-
     fn read_preset_folder(
         path: &PathBuf,
+        metadata: &FolderMetadata,
         presets: &mut Vec<ContinuumPreset>,
     ) -> Result<WorkingStatus> {
         let mut builder = PresetBuilder::default();
@@ -83,28 +155,236 @@ impl PresetLoader {
                 }
             }
             Err(error) => {
-                return Self::fail(&error.to_string());
+                return Err(Error::Io(format!("{}: {error}", path.to_string_lossy())));
             }
         }
 
         presets.sort_unstable_by_key(|preset| preset.name.clone()); // how to avoid clone?
-        for (index, preset) in presets.iter_mut().enumerate() {
-            preset.number = (index + 1) as u8;
+
+        // presets go back to the slots they were saved from; the rest fill the free
+        // slots in name order
+        let mut taken = [false; 129];
+        let saved: Vec<Option<u8>> = presets
+            .iter()
+            .map(|preset| {
+                let slot = metadata.get(&(preset.name.clone() + ".mid"))?.slot?;
+                let free = (1..=128).contains(&slot) && !taken[slot as usize];
+                taken[slot as usize] = true;
+                free.then_some(slot)
+            })
+            .collect();
+        let restored = saved.iter().flatten().count();
+        if restored > 0 {
+            println!("Restoring {restored} preset(s) to the slots they were saved from");
+        }
+        let mut free = (1..=128u8).filter(|slot| !taken[*slot as usize]);
+        for (preset, slot) in presets.iter_mut().zip(saved) {
+            if let Some(slot) = slot.or_else(|| free.next()) {
+                preset.number = slot;
+            }
         }
 
         Ok(WorkingStatus::Working)
     }
 
+    /// Work out the presets to load and their slots from the path given.
+    fn resolve_presets(&mut self, options: &Options) -> Result<()> {
+        self.presets.clear();
+        if let Some(path) = options.get_path() {
+            if path.is_file() {
+                // set base folder
+                if let Some(parent) = path.parent() {
+                    self.folder = parent.into();
+                } else {
+                    return Err(Error::Usage(format!(
+                        "Missing required path in {}",
+                        path.to_string_lossy()
+                    )));
+                }
+
+                let listing = path.file_name().map(|name| name.to_string_lossy().to_string());
+                let listing = match is_extension(&path, "txt") {
+                    true => listing.unwrap_or_default(),
+                    false => "UserPresets.txt".to_string(),
+                };
+                self.metadata = FolderMetadata::read(&self.folder, &listing);
+
+                // either listing (.txt) file or (.mid) file
+                if is_extension(&path, "txt") {
+                    read_preset_listing(&path, listing_mode(options), &mut self.presets)?;
+                } else if is_extension(&path, "mid") {
+                    if let Some(name) = path.file_stem() {
+                        let mut builder = PresetBuilder::default();
+                        builder.add_name_chars(&name.to_string_lossy());
+                        builder.set_number(0);
+                        if let Some(preset) = builder.finish() {
+                            self.presets.push(preset);
+                        }
+                    }
+                } else {
+                    return Err(Error::Usage(format!(
+                        "Path is not a folder, preset listing (.txt), or preset (.mid) file: '{}'",
+                        path.to_string_lossy())));
+                }
+            } else {
+                self.folder = path.clone();
+                self.metadata = FolderMetadata::read(&self.folder, "UserPresets.txt");
+                let listing = self.folder.join("UserPresets.txt");
+                if listing.exists() {
+                    println!("Using preset listing '{}'", listing.to_string_lossy());
+                    read_preset_listing(&listing, listing_mode(options), &mut self.presets)?;
+                } else {
+                    Self::read_preset_folder(&path, &self.metadata, &mut self.presets)?;
+                }
+            }
+        } else {
+            unreachable!();
+        }
+        if self.presets.is_empty() {
+            return Err(Error::Listing(format!(
+                "No presets to load from '{}'",
+                options.get_path_display_name().unwrap_or_default()
+            )));
+        }
+        Ok(())
+    }
+
     fn choose_current_slot(&mut self, handler: &mut MatrixHandler) -> Result<()> {
         let slot = self.presets[self.index].number;
         if 0 == slot {
             handler.choose_edit_slot()?;
         } else {
-            handler.choose_preset(slot)?;
+            // listing slots are one-based, selection is zero-based
+            handler.choose_preset(slot - 1)?;
+        }
+        Ok(())
+    }
+
+    /// One-based slot being loaded, None for the edit slot.
+    fn current_slot(&self) -> Option<u8> {
+        match self.presets[self.index].number {
+            0 => None,
+            slot => Some(slot),
+        }
+    }
+
+    /// Report a preset saved with other firmware than the device has.
+    fn check_firmware(&mut self, mid_name: &str, handler: &MatrixHandler) {
+        let saved = self.metadata.get(mid_name).and_then(|metadata| metadata.firmware);
+        if let (Some(saved), Some(device)) = (saved, handler.firmware_version()) {
+            if saved != device {
+                println!(">'{mid_name}' was saved with firmware {saved}, the device has {device}");
+                self.firmware_mismatches += 1;
+            }
+        }
+    }
+
+    /// Read the current preset's .mid data for upload.
+    fn read_preset_data(&mut self, handler: &MatrixHandler) -> Result<()> {
+        let mut path = self.folder.clone();
+        let mid_name = self.presets[self.index].name.clone() + ".mid";
+        path.push(&mid_name);
+        println!(">Sending preset data '{}'", path.to_string_lossy());
+        self.check_firmware(&mid_name, handler);
+        let data = std::fs::read(&path)
+            .map_err(|error| Error::Io(format!("{}: {error}", path.to_string_lossy())))?;
+        // file timing is ignored: the device paces the upload
+        let mut file = ReadMidiFile::new(&data);
+        self.upload.clear();
+        while let Some((_, message)) = file.next()? {
+            self.upload.push(message);
         }
         Ok(())
     }
 
+    /// Ask the device to receive an archive, then send the first burst of it.
+    fn start_upload(&mut self, handler: &mut MatrixHandler) -> Result<()> {
+        handler.clear_archive_state();
+        handler.send_cc(
+            CHANNEL16,
+            cc16::DownloadInfo,
+            cc16::DownloadInfo_RetrieveArchive,
+        )?;
+        self.sent = 0;
+        self.upload_started = Instant::now();
+        self.send_burst(handler)
+    }
+
+    /// Send the next burst, then ask for an EditorReply unless the archive is all sent.
+    fn send_burst(&mut self, handler: &mut MatrixHandler) -> Result<()> {
+        let end = self.upload.len().min(self.sent + self.pacer.burst());
+        for message in &self.upload[self.sent..end] {
+            handler.send(message)?;
+        }
+        self.sent = end;
+        if self.sent < self.upload.len() {
+            handler.editor_present()?;
+            self.burst_started = Some(Instant::now());
+        } else {
+            self.burst_started = None;
+        }
+        Ok(())
+    }
+
+    /// Compare the archive read back with what was sent.
+    fn check_read_back(&mut self, handler: &mut MatrixHandler) -> Result<()> {
+        let data = handler.get_archive_data();
+        let mut file = ReadMidiFile::new(&data);
+        let mut received = Vec::new();
+        while let Some((_, message)) = file.next()? {
+            received.push(message);
+        }
+        let slot = self.presets[self.index].number;
+        let mut target = slot_target(slot);
+        match compare_read_back(&self.upload, &received) {
+            Some(difference) => {
+                target[..1].make_ascii_uppercase();
+                println!(">{target} differs: {difference}");
+                self.mismatches.push((slot, difference));
+            }
+            None => println!(">Verified {target}"),
+        }
+        Ok(())
+    }
+
+    /// Move on to the next preset, or finish the load.
+    fn next_preset(&mut self, options: &Options, handler: &mut MatrixHandler) -> Result<WorkingStatus> {
+        if 0 == self.index {
+            println!(
+                ">Loaded {} presets in {:.2}s",
+                self.presets.len(),
+                self.load_started.elapsed().as_secs_f64()
+            );
+            if self.firmware_mismatches > 0 {
+                println!(
+                    ">{} preset(s) were saved with different firmware",
+                    self.firmware_mismatches
+                );
+            }
+            if self.mismatches.is_empty() {
+                return Ok(WorkingStatus::Finished);
+            }
+            let slots: Vec<String> = self
+                .mismatches
+                .iter()
+                .rev()
+                .map(|(slot, _)| match slot {
+                    0 => "editing slot".to_string(),
+                    slot => slot.to_string(),
+                })
+                .collect();
+            return Err(Error::VerifyFailed(format!(
+                "{} preset(s) read back differently, slots: {}",
+                self.mismatches.len(),
+                slots.join(", ")
+            )));
+        }
+        self.index -= 1;
+        self.state = SendState::Start;
+        // start the next preset now rather than waiting on another device message
+        self.next(options, handler)
+    }
+
     fn set_current_slot(&mut self, handler: &mut MatrixHandler) -> Result<()> {
         let slot = self.presets[self.index].number;
         if 0 == slot {
@@ -116,68 +396,46 @@ impl PresetLoader {
     }
 }
 
+/// Print which slots a load would write from which files, without a device.
+pub fn print_load_plan(options: &Options) -> Result<()> {
+    let mut loader = PresetLoader::new();
+    loader.resolve_presets(options)?;
+    println!("Dry run: nothing is sent to the device");
+    // loaded last to first
+    for preset in loader.presets.iter().rev() {
+        let path = loader.folder.join(preset.name.clone() + ".mid");
+        let target = slot_target(preset.number);
+        let missing = if path.is_file() { "" } else { " (file missing)" };
+        println!("Would load {target} from '{}'{missing}", path.display());
+    }
+    Ok(())
+}
+
+/// Slot 0 is the editing slot, not a user preset slot.
+fn slot_target(slot: u8) -> String {
+    match slot {
+        0 => "editing slot".to_string(),
+        slot => format!("slot {slot}"),
+    }
+}
+
 impl Stepper for PresetLoader {
     fn next(&mut self, options: &Options, handler: &mut MatrixHandler) -> Result<WorkingStatus> {
         match self.state {
             SendState::Start => {
                 if !self.initialized {
-                    self.presets.clear();
-                    if let Some(path) = options.get_path() {
-                        if path.is_file() {
-                            // set base folder
-                            if let Some(parent) = path.parent() {
-                                self.folder = parent.into();
-                            } else {
-                                return Self::fail(&format!(
-                                    "Missing required path in {}",
-                                    path.to_string_lossy()
-                                ));
-                            }
-
-                            // either listing (.txt) file or (.mid) file
-                            if is_extension(&path, "txt") {
-                                read_preset_listing(
-                                    &path,
-                                    &mut self.presets,
-                                )?;
-                            } else if is_extension(&path, "mid") {
-                                if let Some(name) = path.file_stem() {
-                                    let mut builder = PresetBuilder::default();
-                                    builder.add_name_chars(&name.to_string_lossy());
-                                    builder.set_number(0);
-                                    if let Some(preset) = builder.finish() {
-                                        self.presets.push(preset);
-                                    }
-                                }
-                            } else {
-                                return Self::fail(&format!(
-                                    "Path is not a folder, preset listing (.txt), or preset (.mid) file: '{}'",
-                                    path.to_string_lossy()));
-                            }
-                        } else {
-                            self.folder = path.clone();
-                            let listing = self.folder.join("UserPresets.txt");
-                            if listing.exists() {
-                                println!("Using preset listing '{}'", listing.to_string_lossy());
-                                read_preset_listing(
-                                    &listing,
-                                    &mut self.presets,
-                                )?;
-                            } else {
-                                Self::read_preset_folder(&path, &mut self.presets)?;
-                            }
-                        }
-                    } else {
-                        unreachable!();
-                    }
-                    debug_assert!(!self.presets.is_empty());
+                    self.resolve_presets(options)?;
                     self.index = self.presets.len() -1;
                     self.initialized = true;
                 }
 
+                if self.index == self.presets.len() - 1 {
+                    self.load_started = Instant::now();
+                }
                 println!(">Starting preset load");
                 self.choose_current_slot(handler)?;
                 handler.editor_present()?; // editor present
+                handler.expect(Phase::EditorReply, self.current_slot());
                 self.state = SendState::Prologue;
                 Ok(WorkingStatus::Working)
             }
@@ -185,13 +443,11 @@ impl Stepper for PresetLoader {
 
             SendState::Prologue => {
                 if handler.editor_reply() {
+                    handler.satisfied();
                     println!(">Preparing device to receive");
-                    handler.clear_archive_state();
-                    handler.send_cc(
-                        CHANNEL16,
-                        cc16::DownloadInfo,
-                        cc16::DownloadInfo_RetrieveArchive,
-                    )?;
+                    self.read_preset_data(handler)?;
+                    self.start_upload(handler)?;
+                    handler.expect(Phase::Upload, self.current_slot());
                     self.state = SendState::Matrix;
                 }
                 else {
@@ -201,29 +457,14 @@ impl Stepper for PresetLoader {
             }
 
             SendState::Matrix => {
-                if !self.is_data_sent {
-                    let mut path = self.folder.clone();
-                    let mid_name = self.presets[self.index].name.clone() + ".mid";
-                    path.push(&mid_name);
-                    println!(">Sending preset data '{}'", path.to_string_lossy());
-                    match std::fs::read(&path) {
-                        Ok(data) => {
-                            let mut file = ReadMidiFile::new(&data);
-                            while let Some((dt, midi)) = file.next()? {
-                                // if dt.as_millis() < 18 {
-                                //     dt = std::time::Duration::from_millis(18);
-                                // }
-                                busy_wait(&dt); // sleep apparently causes mpsc::channel to deadlock, so busy-wait
-                                midi.send(handler.output_port())?;
-                            }
-                            self.is_data_sent = true;
-                        }
-                        Err(error) => {
-                            return Self::fail(&error.to_string());
-                        }
+                if let Some(started) = self.burst_started {
+                    if handler.editor_reply() {
+                        self.pacer.acknowledged(started.elapsed());
+                        self.send_burst(handler)?;
                     }
+                    handler.not_ready();
+                    return Ok(WorkingStatus::Working);
                 }
-
                 // receive archiveOk 109:5 or archiveFail 109:6
                 match handler.archive_state() {
                     ArchiveState::Unknown => {
@@ -231,18 +472,21 @@ impl Stepper for PresetLoader {
                     }
 
                     ArchiveState::Ok => {
+                        handler.satisfied();
+                        println!(
+                            ">Sent {} messages in {:.2}s",
+                            self.upload.len(),
+                            self.upload_started.elapsed().as_secs_f64()
+                        );
                         self.state = SendState::Name;
-                        self.is_data_sent = false;
                         handler.not_ready();
                     }
 
                     ArchiveState::Fail => {
-                        //return Self::fail("Preset loading failed");
-                        println!("Preset loading failed");
-                        self.is_data_sent = false;
-                        self.state = SendState::Finish;
-                        handler.editor_present()?;
-                        handler.not_ready();
+                        return Err(Error::DeviceRejected(format!(
+                            "Device rejected preset '{}' (ArchiveFail)",
+                            self.presets[self.index].name
+                        )));
                     }
                 }
                 Ok(WorkingStatus::Working)
@@ -250,7 +494,7 @@ impl Stepper for PresetLoader {
 
             SendState::Name => {
                 let name = &self.presets[self.index].name;
-                println!(">Sending \"{}\" to slot {}", name, self.presets[self.index].number);
+                println!(">Sending \"{}\" to {}", name, slot_target(self.presets[self.index].number));
                 handler.clear_presets();
                 handler.send_string(0, name)?;
                 self.set_current_slot(handler)?;
@@ -261,6 +505,7 @@ impl Stepper for PresetLoader {
 
             SendState::Save => {
                 println!(">Save to flash");
+                handler.clear_dsp_done();
                 handler.send_cc(
                     CHANNEL16,
                     cc16::DownloadControl,
@@ -268,30 +513,149 @@ impl Stepper for PresetLoader {
                 )?;
 
                 self.state = SendState::Finish;
+                self.saved = Instant::now();
                 handler.editor_present()?;
+                handler.expect(Phase::SaveToFlash, self.current_slot());
                 handler.not_ready();
                 Ok(WorkingStatus::Working)
             }
 
             SendState::Finish => {
                 if handler.editor_reply() {
+                    handler.satisfied();
+                    // the reply can beat the end of the flash write
+                    if !handler.dsp_done() && self.saved.elapsed() < FLASH_SETTLE {
+                        handler.not_ready();
+                        return Ok(WorkingStatus::Working);
+                    }
                     println!();
-                    if 0 ==  self.index {
-                        Ok(WorkingStatus::Finished)
-                    } else {
-                        busy_wait(& std::time::Duration::new(1,0));
-                        self.index -= 1;
-                        self.state = SendState::Start;
-                        Ok(WorkingStatus::Working)
+                    if options.verify {
+                        println!(">Reading back");
+                        self.choose_current_slot(handler)?;
+                        handler.read_back_current()?;
+                        handler.expect(Phase::Archive, self.current_slot());
+                        self.state = SendState::Verify;
+                        handler.not_ready();
+                        return Ok(WorkingStatus::Working);
                     }
+                    self.next_preset(options, handler)
                 } else {
                     // wait for editor reply
-                    _ = std::io::stdout().write(&[b'.']);
+                    _ = std::io::stdout().write(b".");
                     _ = std::io::stdout().flush();
                     handler.not_ready();
                     Ok(WorkingStatus::Working)
                 }
             }
+
+            SendState::Verify => {
+                if handler.archive_received() {
+                    handler.satisfied();
+                    self.check_read_back(handler)?;
+                    println!();
+                    self.next_preset(options, handler)
+                } else {
+                    handler.not_ready();
+                    Ok(WorkingStatus::Working)
+                }
+            }
+        }
+    }
+
+    fn retry(&mut self, _options: &Options, handler: &mut MatrixHandler) -> Result<()> {
+        match self.state {
+            SendState::Verify => {
+                self.choose_current_slot(handler)?;
+                handler.read_back_current()
+            }
+            SendState::Prologue | SendState::Finish => handler.editor_present(),
+            SendState::Matrix => {
+                self.pacer.back_off();
+                self.start_upload(handler)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        acquire_device::get_haken_io,
+        midi_handler::dispatch_midi,
+        options::Action,
+        preset_archive::PresetArchive,
+        sim_device::{SimDevice, SimTransport, SIM_DEVICE_NAME},
+        stream_decode::stream_messages,
+    };
+    use std::sync::mpsc::channel;
+
+    fn archive(name: &str, value: u8) -> Vec<MidiMessage> {
+        let mut messages = stream_messages(cc16::DataStream_Name, name.as_bytes());
+        messages.push(MidiMessage::cc(CHANNEL16, 1, value));
+        messages
+    }
+
+    #[test]
+    fn read_back_ignores_the_name() {
+        assert_eq!(compare_read_back(&archive("Pad", 3), &archive("Renamed", 3)), None);
+    }
+
+    #[test]
+    fn read_back_reports_the_first_difference() {
+        assert_eq!(
+            compare_read_back(&archive("Pad", 3), &archive("Pad", 4)),
+            Some(
+                "message 1: sent ControlChange { channel: 15, cc: 1, value: 3 }, \
+                 read back ControlChange { channel: 15, cc: 1, value: 4 }"
+                    .to_string()
+            )
+        );
+        let mut longer = archive("Pad", 3);
+        longer.push(MidiMessage::cc(CHANNEL16, 2, 0));
+        assert_eq!(
+            compare_read_back(&archive("Pad", 3), &longer),
+            Some("1 messages sent, 2 read back".to_string())
+        );
+    }
+
+    #[test]
+    fn empty_listings_and_folders_load_nothing() {
+        let folder = std::env::temp_dir().join(format!("harkive-load-{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        let listing = folder.join("Empty.txt");
+        std::fs::write(&listing, "\n").unwrap();
+        let results: Vec<Result<()>> = [&folder, &listing]
+            .into_iter()
+            .map(|path| PresetLoader::new().resolve_presets(&Options::with_path(Action::Load, path)))
+            .collect();
+        std::fs::remove_dir_all(&folder).unwrap();
+        for result in results {
+            assert!(matches!(result, Err(Error::Listing(message)) if message.starts_with("No presets to load")));
+        }
+    }
+
+    #[test]
+    fn listing_slot_selects_its_own_preset() {
+        let transport = SimTransport::new(SimDevice::new());
+        let (mut input, output) = get_haken_io(&transport, &Some(SIM_DEVICE_NAME.to_string())).unwrap();
+        let (tx, rx) = channel();
+        input.port.listen(tx).unwrap();
+        let mut handler = MatrixHandler::new(output);
+        let mut loader = PresetLoader::new();
+        let mut builder = PresetBuilder::default();
+        builder.add_name_chars("Bass");
+        // "Bowed Bass" is in the second slot of the simulator
+        builder.set_number(2);
+        loader.presets.extend(builder.finish());
+        loader.index = 0;
+        loader.choose_current_slot(&mut handler).unwrap();
+        handler.read_back_current().unwrap();
+        while let Ok(msg) = rx.try_recv() {
+            dispatch_midi(&mut handler, &msg).unwrap();
         }
+        let archive = PresetArchive::parse(&handler.get_archive_data()).unwrap();
+        assert_eq!(archive.name(), "Bowed Bass");
     }
 }
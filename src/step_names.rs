@@ -1,21 +1,79 @@
-use windows::core::Result;
-use crate::{matrix_handler::MatrixHandler, options::Options, stepper::*};
+use crate::error::Result;
+use crate::{
+    continuum_preset::{ContinuumPreset, HCCategoryCode},
+    matrix_handler::MatrixHandler,
+    options::Options,
+    stepper::*,
+};
 
-pub struct NameList {}
+pub struct NameList {
+    catcode: HCCategoryCode,
+}
+impl NameList {
+    pub fn new(catcode: HCCategoryCode) -> Self {
+        Self { catcode }
+    }
+}
 impl Stepper for NameList {
     fn next(&mut self, options: &Options, handler: &mut MatrixHandler) -> Result<WorkingStatus> {
-        let catcode = crate::continuum_preset::HCCategoryCode::new();
+        handler.satisfied();
         let presets = handler.get_presets();
         if presets.is_empty() {
             println!("No user presets found");
         } else {
             for preset in presets.iter() {
                 preset.print();
-                preset.print_friendly_categories(&catcode);
+                preset.print_friendly_categories(&self.catcode);
             }
-            crate::preset_listing::save_preset_listing(&presets[0..], options.get_path());
+            crate::preset_listing::save_preset_listing(&presets[0..], options.get_path())?;
         }
         Ok(WorkingStatus::Finished)
     }
 
+    fn retry(&mut self, _options: &Options, handler: &mut MatrixHandler) -> Result<()> {
+        handler.transmit_names()
+    }
+
+}
+
+/// Order system presets by cat-code bank, keeping device order within each bank.
+pub fn group_system_presets(presets: &[ContinuumPreset]) -> Vec<ContinuumPreset> {
+    let mut presets = presets.to_vec();
+    presets.sort_by_key(|preset| preset.bank_hi);
+    presets
+}
+
+pub struct SystemList {
+    catcode: HCCategoryCode,
+}
+impl SystemList {
+    pub fn new(catcode: HCCategoryCode) -> Self {
+        Self { catcode }
+    }
+}
+impl Stepper for SystemList {
+    fn next(&mut self, options: &Options, handler: &mut MatrixHandler) -> Result<WorkingStatus> {
+        handler.satisfied();
+        let catcode = &self.catcode;
+        let presets = group_system_presets(handler.get_presets());
+        if presets.is_empty() {
+            println!("No system presets found");
+        } else {
+            let mut bank = None;
+            for preset in presets.iter() {
+                if bank != Some(preset.bank_hi) {
+                    bank = Some(preset.bank_hi);
+                    println!("---- {} ----", catcode.bank_name(preset.bank_hi));
+                }
+                preset.print();
+                preset.print_friendly_categories(catcode);
+            }
+            crate::preset_listing::save_system_listing(&presets, options.get_path())?;
+        }
+        Ok(WorkingStatus::Finished)
+    }
+
+    fn retry(&mut self, _options: &Options, handler: &mut MatrixHandler) -> Result<()> {
+        handler.transmit_system_names()
+    }
 }
\ No newline at end of file
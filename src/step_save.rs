@@ -1,17 +1,58 @@
-use windows::{core::*, Win32::Foundation::E_FAIL};
 use crate::{
     continuum_preset::*,
+    error::{Error, Result},
     preset_listing::*,
     stepper::*,
     matrix_handler::MatrixHandler,
     options::*,
+    preset_archive::PresetArchive,
+    preset_metadata::*,
+    step_names::group_system_presets,
+    util::timestamp_now,
+    watchdog::Phase,
 };
+use std::path::{Path, PathBuf};
 
-fn fail(message: &str) -> windows::core::Result<()> {
-    Err(Error::new(E_FAIL, HSTRING::from(message)))
+/// Where a saved preset came from on the device.
+#[derive(Copy, Clone, PartialEq)]
+enum Place {
+    EditSlot,
+    User,
+    System,
 }
 
-pub fn save_preset(options: &Options, handler: &mut MatrixHandler, preset: &ContinuumPreset) ->  windows::core::Result<()> {
+/// What the handler knows about the archive just collected.
+fn preset_metadata(
+    handler: &MatrixHandler,
+    preset: &ContinuumPreset,
+    data: &[u8],
+    place: Place,
+) -> PresetMetadata {
+    // the archive's own streams, rather than the names list
+    let (text, category) = match PresetArchive::parse(data) {
+        Ok(archive) => (archive.text(), archive.category()),
+        Err(_) => (preset.text.clone(), preset.category.clone()),
+    };
+    let system = place == Place::System;
+    PresetMetadata {
+        name: preset.name.clone(),
+        slot: (place == Place::User).then_some(preset.number + 1),
+        bank: system.then_some((preset.bank_hi, preset.bank_lo)),
+        program: system.then_some(preset.number),
+        text,
+        category,
+        firmware: handler.firmware_version(),
+        device: handler.device_kind().to_string(),
+        saved: timestamp_now(),
+        ..Default::default()
+    }
+}
+
+pub fn save_preset(
+    options: &Options,
+    handler: &mut MatrixHandler,
+    preset: &ContinuumPreset,
+) -> Result<PresetMetadata> {
     let data = handler.get_archive_data();
     if let Some(mut path) = options.get_path() {
         // if action is:
@@ -26,30 +67,66 @@ pub fn save_preset(options: &Options, handler: &mut MatrixHandler, preset: &Cont
                     path.push(make_preset_filename(&preset.name, &data));
                 }
                 None => {
-                    let message = format!(
+                    return Err(Error::Usage(format!(
                         "Couldn't save '{}' : Unable to get target folder",
                         path.display()
-                    );
-                    return fail(&message);
+                    )));
                 }
             }
         }
-        let pathname = path.display().to_string();
-        match std::fs::write(path, &data) {
-            Ok(_) => {
-                println!("Saved preset '{pathname}'");
-                Ok(())
-            }
-            Err(error) => {
-                println!("Couldn't save '{pathname}' : {error}");
-                fail(&error.to_string())
-            }
-        }
+        let place = if options.action == Action::Save { Place::User } else { Place::EditSlot };
+        let metadata = preset_metadata(handler, preset, &data, place);
+        write_preset(path, &data, metadata)
     } else {
-        fail("Missing path to save to")
+        Err(Error::Usage("Missing path to save to".to_string()))
     }
 }
 
+fn save_preset_in(
+    folder: &Path,
+    handler: &mut MatrixHandler,
+    preset: &ContinuumPreset,
+    place: Place,
+) -> Result<PresetMetadata> {
+    let data = handler.get_archive_data();
+    let metadata = preset_metadata(handler, preset, &data, place);
+    write_preset(folder.join(make_preset_filename(&preset.name, &data)), &data, metadata)
+}
+
+/// Write the archive and its metadata sidecar.
+fn write_preset(path: PathBuf, data: &[u8], mut metadata: PresetMetadata) -> Result<PresetMetadata> {
+    let pathname = path.display().to_string();
+    match std::fs::write(&path, data) {
+        Ok(_) => println!("Saved preset '{pathname}'"),
+        Err(error) => return Err(Error::Io(format!("Couldn't save '{pathname}' : {error}"))),
+    }
+    metadata.file = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    write_metadata(&sidecar_path(&path), &metadata.to_text())?;
+    Ok(metadata)
+}
+
+/// Write the metadata of every preset saved to `folder`, beside its listing.
+fn save_folder_metadata(presets: &[PresetMetadata], folder: Option<PathBuf>, listing: &str) -> Result<()> {
+    if let Some(folder) = folder {
+        write_metadata(&folder_metadata_path(&folder.join(listing)), &folder_text(presets))?;
+    }
+    Ok(())
+}
+
+/// Folder for system presets: "System" beside the user presets.
+fn system_folder(options: &Options) -> Option<PathBuf> {
+    let path = options.get_path()?;
+    let base = if path.is_dir() {
+        path
+    } else {
+        path.parent()?.to_path_buf()
+    };
+    Some(base.join("System"))
+}
+
 pub struct SingleSaver { }
 impl SingleSaver {
     fn first_handler_preset(handler: &MatrixHandler) -> Option<ContinuumPreset>
@@ -59,11 +136,16 @@ impl SingleSaver {
 }
 impl Stepper for SingleSaver {
     fn next(&mut self, options: &Options, handler: &mut MatrixHandler) -> Result<WorkingStatus> {
+        handler.satisfied();
         if let Some(preset) = Self::first_handler_preset(handler) {
-            _ = save_preset(options, handler, &preset);
+            save_preset(options, handler, &preset)?;
         }
         Ok(WorkingStatus::Finished)
     }
+
+    fn retry(&mut self, _options: &Options, handler: &mut MatrixHandler) -> Result<()> {
+        handler.transmit_archive_current()
+    }
 }
 
 #[derive(Copy, Clone, Default, PartialEq)]
@@ -76,73 +158,126 @@ enum SaveState {
     Finish,
 }
 
+/// Saves every user preset, then the system presets when asked for.
 pub struct Saver {
     save_state: SaveState,
-    working_preset: u8,
+    working_preset: usize,
     presets: Vec<ContinuumPreset>,
+    // set while walking system presets, to the folder they are saved in
+    system: Option<PathBuf>,
+    // user presets go here rather than to the path given, for backups
+    folder: Option<PathBuf>,
+    // metadata of the presets saved to the current folder
+    saved: Vec<PresetMetadata>,
 }
 
 impl Saver {
     pub fn new() -> Self {
         Self {
             save_state: SaveState::default(),
-            working_preset: u8::MAX,
+            working_preset: usize::MAX,
             presets: Vec::new(),
+            system: None,
+            folder: None,
+            saved: Vec::new(),
         }
     }
 
-    fn handle_error(error: Error) -> Result<WorkingStatus> {
-        println!("Unable to continue due to error: {}", error.message());
-        Ok(WorkingStatus::Finished)
+    /// Save the user presets and their listing to `folder`.
+    pub fn in_folder(folder: PathBuf) -> Self {
+        Self {
+            folder: Some(folder),
+            ..Self::new()
+        }
+    }
+
+    fn choose_working_preset(&self, handler: &mut MatrixHandler) -> Result<()> {
+        let preset = &self.presets[self.working_preset];
+        if self.system.is_some() {
+            handler.choose_system_preset(preset)
+        } else {
+            handler.choose_preset(preset.number)
+        }
     }
 
+    fn start_system(&mut self, options: &Options, handler: &mut MatrixHandler) -> Result<()> {
+        let Some(folder) = system_folder(options) else {
+            return Err(Error::Usage("Missing path to save to".to_string()));
+        };
+        if let Err(error) = std::fs::create_dir_all(&folder) {
+            return Err(Error::Io(format!("Couldn't create '{}' : {error}", folder.display())));
+        }
+        self.system = Some(folder);
+        self.presets.clear();
+        self.saved.clear();
+        self.save_state = SaveState::Start;
+        handler.clear_presets();
+        handler.transmit_system_names()?;
+        handler.expect(Phase::PresetNames, None);
+        handler.not_ready();
+        Ok(())
+    }
 }
 
 impl Stepper for Saver {
     fn next(&mut self, options: &Options, handler: &mut MatrixHandler) -> Result<WorkingStatus> {
         match self.save_state {
             SaveState::Start => {
-                println!("Gathering user presets...");
+                handler.satisfied();
+                if self.system.is_some() {
+                    println!("Gathering system presets...");
+                } else {
+                    println!("Gathering user presets...");
+                }
                 self.save_state = SaveState::GatherList;
                 self.working_preset = 0;
                 Ok(WorkingStatus::Working)
             }
             SaveState::GatherList => {
                 debug_assert!(self.presets.is_empty());
-                self.presets = handler.get_presets().clone();
+                self.presets = if self.system.is_some() {
+                    group_system_presets(handler.get_presets())
+                } else {
+                    handler.get_presets().clone()
+                };
                 for preset in self.presets.iter() {
                     println!("{}", preset.name);
                 }
-                self.save_state = SaveState::CollectPreset;
-                Ok(if self.presets.is_empty() {
-                    WorkingStatus::Finished
+                self.save_state = if self.presets.is_empty() {
+                    SaveState::Finish
                 } else {
-                    WorkingStatus::Working
-                })
+                    SaveState::CollectPreset
+                };
+                Ok(WorkingStatus::Working)
             }
             SaveState::CollectPreset => {
-                debug_assert!((self.working_preset as usize) < self.presets.len());
+                debug_assert!(self.working_preset < self.presets.len());
                 println!(
                     "Collecting {}...",
-                    self.presets[self.working_preset as usize].name
+                    self.presets[self.working_preset].name
                 );
-                let index = self.presets[self.working_preset as usize].number;
-                //$review:error handling
-                if let Err(error) = handler.choose_preset(index) {
-                    return Self::handle_error(error);
-                }
-                if let Err(error) = handler.start_action(Action::SaveCurrent) {
-                    return Self::handle_error(error);
-                }
+                self.choose_working_preset(handler)?;
+                handler.start_action(Action::SaveCurrent)?;
+                let slot = match self.system {
+                    Some(_) => None,
+                    None => Some(self.presets[self.working_preset].number + 1),
+                };
+                handler.expect(Phase::Archive, slot);
                 self.save_state = SaveState::SavePreset;
                 Ok(WorkingStatus::Working)
             }
             SaveState::SavePreset => {
-                let preset = &self.presets[self.working_preset as usize];
-                _ = save_preset(options, handler, preset); // $review: error handling
+                handler.satisfied();
+                let preset = &self.presets[self.working_preset];
+                let metadata = match (&self.system, &self.folder) {
+                    (Some(folder), _) => save_preset_in(folder, handler, preset, Place::System)?,
+                    (None, Some(folder)) => save_preset_in(folder, handler, preset, Place::User)?,
+                    (None, None) => save_preset(options, handler, preset)?,
+                };
+                self.saved.push(metadata);
                 self.working_preset += 1;
-                if (self.working_preset as usize) >= self.presets.len() {
-                    self.working_preset = u8::MAX;
+                if self.working_preset >= self.presets.len() {
+                    self.working_preset = usize::MAX;
                     self.save_state = SaveState::Finish;
                 } else {
                     self.save_state = SaveState::CollectPreset;
@@ -150,10 +285,36 @@ impl Stepper for Saver {
                 Ok(WorkingStatus::Working)
             }
             SaveState::Finish => {
-                save_preset_listing(&self.presets[0..], options.get_path());
+                if self.system.is_some() {
+                    if !self.presets.is_empty() {
+                        save_system_listing(&self.presets[0..], self.system.clone())?;
+                        save_folder_metadata(&self.saved, self.system.clone(), "SystemPresets.txt")?;
+                    }
+                } else {
+                    if !self.presets.is_empty() {
+                        let folder = self.folder.clone().or_else(|| options.get_path());
+                        save_preset_listing(&self.presets[0..], folder.clone())?;
+                        save_folder_metadata(&self.saved, folder, "UserPresets.txt")?;
+                    }
+                    if options.with_system && self.folder.is_none() {
+                        self.start_system(options, handler)?;
+                        return Ok(WorkingStatus::Working);
+                    }
+                }
                 self.save_state = SaveState::Start;
                 Ok(WorkingStatus::Finished)
             }
         }
     }
+
+    fn retry(&mut self, _options: &Options, handler: &mut MatrixHandler) -> Result<()> {
+        match self.save_state {
+            SaveState::SavePreset => {
+                self.choose_working_preset(handler)?;
+                handler.transmit_archive_current()
+            }
+            _ if self.system.is_some() => handler.transmit_system_names(),
+            _ => handler.transmit_names(),
+        }
+    }
 }
\ No newline at end of file
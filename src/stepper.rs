@@ -1,4 +1,4 @@
-use windows::core::Result;
+use crate::error::Result;
 use crate::{matrix_handler::MatrixHandler, options::Options};
 
 #[derive(Copy, Clone, PartialEq)]
@@ -9,6 +9,11 @@ pub enum WorkingStatus {
 
 pub trait Stepper {
     fn next(&mut self, options: &Options, handler: &mut MatrixHandler) -> Result<WorkingStatus>;
+
+    /// The reply the handler was told to expect timed out: send the request again.
+    fn retry(&mut self, _options: &Options, _handler: &mut MatrixHandler) -> Result<()> {
+        Ok(())
+    }
 }
 
 pub struct NilStepper { }
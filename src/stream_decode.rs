@@ -0,0 +1,215 @@
+use crate::{
+    data_kind::DataKind,
+    haken_midi::cc16,
+    midi::CHANNEL16,
+    midi_message::MidiMessage,
+    midi_traits::*,
+    util::make_hex_string,
+};
+
+/// Contents of a cc56 data stream, by kind.
+///
+/// Stream bytes are 7-bit. Text streams are ASCII. Haken doesn't publish the layout of the
+/// other kinds, and no reading of them has been checked against a device, so their bytes
+/// are kept as they are.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StreamData {
+    Name(String),
+    ControlText(String),
+    Log(String),
+    Category(String),
+    DemoAssort(String),
+    /// Graphs, parameter values, system data and unknown kinds.
+    Bytes(Vec<u8>),
+}
+
+/// One complete cc56 stream: its kind, the number of stream bytes, and what they decode to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DataStream {
+    pub kind: DataKind,
+    pub size: usize,
+    pub data: StreamData,
+}
+
+impl StreamData {
+    /// Decode the channel pressure bytes of a stream of `kind`.
+    pub fn decode(kind: DataKind, bytes: &[u8]) -> Self {
+        let text = || bytes.iter().map(|byte| (*byte & 0x7F) as char).collect();
+        match kind {
+            DataKind::Name => Self::Name(text()),
+            DataKind::ControlText => Self::ControlText(text()),
+            DataKind::Log => Self::Log(text()),
+            DataKind::Category => Self::Category(text()),
+            DataKind::DemoAssort => Self::DemoAssort(text()),
+            _ => Self::Bytes(bytes.to_vec()),
+        }
+    }
+
+    /// The stream bytes this data decodes from, the inverse of `decode`.
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Self::Bytes(bytes) => bytes.clone(),
+            _ => self.text().unwrap_or_default().bytes().collect(),
+        }
+    }
+
+    /// The text of a text stream.
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            Self::Name(text)
+            | Self::ControlText(text)
+            | Self::Log(text)
+            | Self::Category(text)
+            | Self::DemoAssort(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+impl DataStream {
+    /// Decode the channel pressure bytes of a stream of `kind`.
+    pub fn decode(kind: DataKind, bytes: &[u8]) -> Self {
+        Self {
+            kind,
+            size: bytes.len(),
+            data: StreamData::decode(kind, bytes),
+        }
+    }
+}
+
+impl Described for DataStream {
+    fn describe(&self) -> String {
+        let data = &self.data;
+        let detail = match data {
+            StreamData::Bytes(bytes) => format!("[{}]", make_hex_string(bytes)),
+            _ => format!("\"{}\"", data.text().unwrap_or_default()),
+        };
+        format!("{} ({} bytes): {}", self.kind.name(), self.size, detail)
+    }
+}
+
+/// Collects cc56 streams from messages, as received live or read from a preset file.
+#[derive(Default)]
+pub struct StreamCollector {
+    kind: Option<DataKind>,
+    bytes: Vec<u8>,
+}
+
+impl StreamCollector {
+    /// Start a stream of `value` (a DataStream value), or end the open one on `DataStream_End`.
+    ///
+    /// Returns the stream just ended; the collector keeps nothing of it.
+    pub fn stream_control(&mut self, value: u8) -> Option<DataStream> {
+        if value == cc16::DataStream_End {
+            let kind = self.kind.take()?;
+            let stream = DataStream::decode(kind, &self.bytes);
+            self.bytes.clear();
+            Some(stream)
+        } else {
+            self.kind = Some(DataKind::new(value));
+            self.bytes.clear();
+            None
+        }
+    }
+
+    /// Add a stream byte. Returns false when no stream is open.
+    pub fn add(&mut self, byte: u8) -> bool {
+        if self.kind.is_some() {
+            self.bytes.push(byte);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether `message` belongs to the open stream: one of its bytes, or its end.
+    pub fn continues(&self, message: &MidiMessage) -> bool {
+        self.kind.is_some()
+            && match *message {
+                MidiMessage::ControlChange { channel, cc, value } => {
+                    channel == CHANNEL16 && cc == cc16::DataStream && value == cc16::DataStream_End
+                }
+                MidiMessage::ChannelPressure { channel, .. } => channel == CHANNEL16,
+                _ => false,
+            }
+    }
+
+    /// Follow one message; returns the stream it ends, if any.
+    pub fn add_message(&mut self, message: &MidiMessage) -> Option<DataStream> {
+        match *message {
+            MidiMessage::ControlChange { channel, cc, value }
+                if channel == CHANNEL16 && cc == cc16::DataStream =>
+            {
+                self.stream_control(value)
+            }
+            MidiMessage::ChannelPressure { channel, pressure } if channel == CHANNEL16 => {
+                self.add(pressure);
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Decode every complete cc56 stream in `messages`, in order.
+pub fn decode_streams(messages: &[MidiMessage]) -> Vec<DataStream> {
+    let mut collector = StreamCollector::default();
+    messages
+        .iter()
+        .filter_map(|message| collector.add_message(message))
+        .collect()
+}
+
+/// The cc56 messages of a complete stream of `kind` holding `bytes`, for tests.
+#[cfg(test)]
+pub fn stream_messages(kind: u8, bytes: &[u8]) -> Vec<MidiMessage> {
+    let mut messages = vec![MidiMessage::cc(CHANNEL16, cc16::DataStream, kind)];
+    for byte in bytes {
+        messages.push(MidiMessage::channel_pressure(CHANNEL16, *byte));
+    }
+    messages.push(MidiMessage::cc(CHANNEL16, cc16::DataStream, cc16::DataStream_End));
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::CHANNEL15;
+
+    #[test]
+    fn decodes_text_streams_and_keeps_other_bytes() {
+        let mut messages = stream_messages(cc16::DataStream_Name, b"Cello");
+        messages.push(MidiMessage::cc(CHANNEL15, 1, 2));
+        messages.extend(stream_messages(cc16::DataStream_Graph, &[0, 5, 1, 0, 127, 127, 0, 3]));
+        messages.extend(stream_messages(cc16::DataStream_Kinetic, &[0x03, 0x7E, 0, 0, 0]));
+        let streams = decode_streams(&messages);
+        assert_eq!(streams.len(), 3);
+        assert_eq!(streams[0].data, StreamData::Name("Cello".to_string()));
+        assert_eq!(streams[1].kind, DataKind::Graph);
+        assert_eq!(streams[1].size, 8);
+        assert_eq!(streams[1].data, StreamData::Bytes(vec![0, 5, 1, 0, 127, 127, 0, 3]));
+        assert_eq!(streams[2].data, StreamData::Bytes(vec![0x03, 0x7E, 0, 0, 0]));
+        // an unterminated stream is left out
+        let mut messages = stream_messages(cc16::DataStream_Float, &[1]);
+        messages.pop();
+        assert!(decode_streams(&messages).is_empty());
+    }
+
+    #[test]
+    fn encoding_is_the_inverse_of_decoding() {
+        for (kind, bytes) in [(DataKind::Name, &b"Pad"[..]), (DataKind::Graph, &[1, 2, 3][..])] {
+            assert_eq!(StreamData::decode(kind, bytes).encode(), bytes);
+        }
+    }
+
+    #[test]
+    fn collectors_keep_nothing_once_a_stream_ends() {
+        let mut collector = StreamCollector::default();
+        for message in stream_messages(cc16::DataStream_Log, b"ok") {
+            if let Some(stream) = collector.add_message(&message) {
+                assert_eq!(stream.data, StreamData::Log("ok".to_string()));
+            }
+        }
+        assert!(collector.kind.is_none() && collector.bytes.is_empty());
+    }
+}
@@ -0,0 +1,99 @@
+use std::sync::mpsc::Sender;
+
+use crate::{
+    error::{Error, Result},
+    midi_message::{MidiMessage, TimedMessage},
+    midi_traits::Named,
+    options::Options,
+    session::{RecordingTransport, ReplayTransport, Session},
+    sim_device::{SimDevice, SimTransport, SIM_DEVICE_NAME},
+};
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    In,
+    Out,
+}
+impl Named for Direction {
+    fn name(&self) -> &'static str {
+        match *self {
+            Direction::In => "in",
+            Direction::Out => "out",
+        }
+    }
+}
+
+/// A MIDI port as reported by a transport.
+///
+/// `id` is whatever the transport needs to open the port again
+/// (a WinRT device id, an ALSA "client:port" address).
+#[derive(Clone)]
+#[allow(dead_code)] // not all fields are needed by every transport
+pub struct PortInfo {
+    pub direction: Direction,
+    pub name: String,
+    pub id: String,
+}
+
+/// Receiving side of an open device.
+pub trait MidiInput: Send {
+    /// Start delivering received messages to `tx` until `close` is called.
+    fn listen(&mut self, tx: Sender<TimedMessage>) -> Result<()>;
+    fn close(&mut self);
+}
+
+/// Sending side of an open device.
+pub trait MidiOutput {
+    fn send(&self, message: &MidiMessage) -> Result<()>;
+    fn close(&mut self);
+}
+
+/// A platform MIDI API.
+pub trait MidiTransport {
+    fn ports(&self, direction: Direction) -> Vec<PortInfo>;
+    fn open_input(&self, port: &PortInfo) -> Option<Box<dyn MidiInput>>;
+    fn open_output(&self, port: &PortInfo) -> Option<Box<dyn MidiOutput>>;
+}
+
+/// The transport selected by the options: a replayed session, the simulator for
+/// `--device sim`, or the platform transport; recorded when `--record` is given.
+pub fn open_transport(options: &Options) -> Result<Box<dyn MidiTransport>> {
+    let transport: Box<dyn MidiTransport> = if let Some(path) = &options.replay {
+        Box::new(ReplayTransport::new(Session::read(path)?))
+    } else if options.device.as_deref() == Some(SIM_DEVICE_NAME) {
+        Box::new(SimTransport::new(SimDevice::new()))
+    } else {
+        platform_transport()?
+    };
+    if let Some(path) = &options.record {
+        let recorder = RecordingTransport::new(transport, path).map_err(|error| {
+            Error::Io(format!(
+                "Unable to record session to '{}': {error}",
+                path.to_string_lossy()
+            ))
+        })?;
+        println!("Recording session to '{}'", path.to_string_lossy());
+        Ok(Box::new(recorder))
+    } else {
+        Ok(transport)
+    }
+}
+
+/// The transport for the platform harkive was built for.
+pub fn platform_transport() -> Result<Box<dyn MidiTransport>> {
+    #[cfg(windows)]
+    {
+        Ok(Box::new(crate::winrt_transport::WinRtTransport {}))
+    }
+    #[cfg(all(target_os = "linux", feature = "alsa"))]
+    {
+        Ok(Box::new(crate::alsa_transport::AlsaTransport::new()?))
+    }
+    #[cfg(not(any(windows, all(target_os = "linux", feature = "alsa"))))]
+    {
+        Err(Error::Midi(
+            "No MIDI transport in this build (on Linux, build with --features alsa).".to_string(),
+        ))
+    }
+}
@@ -1,8 +1,8 @@
 use std::collections::hash_map::DefaultHasher;
 use std::hash::Hasher;
-use std::path::PathBuf;
+use std::path::Path;
 
-pub fn is_extension(path: &PathBuf, extension: &str) -> bool {
+pub fn is_extension(path: &Path, extension: &str) -> bool {
     if let Some(ext) = path.extension() {
         if let Some(pext) = ext.to_str() {
             return pext == extension
@@ -38,7 +38,7 @@ pub fn make_hex_string(data: &[u8]) -> String {
 
 pub fn display_f64(f: f64) -> String {
     let raw = format!("{f:.3}");
-    let result = raw.trim_end_matches(&['0', '.']);
+    let result = raw.trim_end_matches(['0', '.']);
     if result.is_empty() {
         return "0".to_string();
     } else if result.len() == 1 {
@@ -219,3 +219,46 @@ pub fn edit_distance(str_a: &str, str_b: &str) -> u32 {
 
     distances[n]
 }
+
+/// UTC "YYYYMMDD-HHMMSS" for seconds since the Unix epoch, for folder names.
+pub fn timestamp_name(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let time = secs % 86_400;
+    // civil date from day count (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}{month:02}{day:02}-{:02}{:02}{:02}",
+        time / 3_600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// `timestamp_name` for now.
+pub fn timestamp_now() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    timestamp_name(secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps_are_utc_calendar_time() {
+        assert_eq!(timestamp_name(0), "19700101-000000");
+        assert_eq!(timestamp_name(951_782_400), "20000229-000000");
+        assert_eq!(timestamp_name(1_790_000_000), "20260921-141320");
+    }
+}
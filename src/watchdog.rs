@@ -0,0 +1,248 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::{Error, Result},
+    haken_midi::cc16,
+    midi::CHANNEL16,
+    midi_message::MidiMessage,
+};
+
+/// A step of a device exchange that waits on a reply.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// EditorPresent sent, waiting for EditorReply.
+    EditorReply,
+    /// User preset names requested, waiting for EndUserNames.
+    PresetNames,
+    /// Archive of the current preset requested, waiting for EndArchive.
+    Archive,
+    /// Preset archive sent, waiting for ArchiveOk/ArchiveFail.
+    Upload,
+    /// SaveToFlash sent, waiting for EditorReply.
+    SaveToFlash,
+    /// Clear of a user bank (0-based) sent, waiting for DspDone.
+    ClearBank(u8),
+}
+
+impl Phase {
+    /// Whether `message` is part of the channel 16 reply this phase waits on. Only these
+    /// restart the timeout: notes played or a pedal moved while waiting don't.
+    pub fn is_reply(&self, message: &MidiMessage) -> bool {
+        match (self, message) {
+            // everything the device sends between requesting and ending an archive is
+            // on channel 16 apart from the matrix settings, which come in one burst
+            (Phase::Archive, message) => message.channel() == Some(CHANNEL16),
+            (
+                Phase::PresetNames,
+                MidiMessage::ProgramChange { channel: CHANNEL16, .. }
+                | MidiMessage::ChannelPressure { channel: CHANNEL16, .. },
+            ) => true,
+            (
+                Phase::PresetNames,
+                MidiMessage::ControlChange { channel: CHANNEL16, cc, .. },
+            ) => matches!(
+                *cc,
+                cc16::BankSelect | cc16::PresetGroup | cc16::DataStream | cc16::DownloadControl
+            ),
+            (
+                Phase::EditorReply | Phase::SaveToFlash,
+                MidiMessage::ControlChange { channel: CHANNEL16, cc, .. },
+            ) => matches!(
+                *cc,
+                cc16::EditorReply | cc16::FirmwareVersionHi | cc16::FirmwareVersionLo
+            ),
+            // each burst of an upload is acknowledged with an EditorReply
+            (
+                Phase::Upload,
+                MidiMessage::ControlChange { channel: CHANNEL16, cc, .. },
+            ) => matches!(*cc, cc16::DownloadControl | cc16::EditorReply),
+            (
+                Phase::ClearBank(_),
+                MidiMessage::ControlChange { channel: CHANNEL16, cc, .. },
+            ) => *cc == cc16::DownloadControl,
+            _ => false,
+        }
+    }
+
+    fn default_timeout(&self) -> Duration {
+        match self {
+            Phase::EditorReply => Duration::from_secs(2),
+            Phase::PresetNames | Phase::Archive | Phase::Upload => Duration::from_secs(5),
+            // flash writes are slow
+            Phase::SaveToFlash | Phase::ClearBank(_) => Duration::from_secs(10),
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Phase::EditorReply => f.write_str("editor reply"),
+            Phase::PresetNames => f.write_str("preset names"),
+            Phase::Archive => f.write_str("archive"),
+            Phase::Upload => f.write_str("archive upload"),
+            Phase::SaveToFlash => f.write_str("save to flash"),
+            Phase::ClearBank(bank) => write!(f, "clear bank {bank}"),
+        }
+    }
+}
+
+/// How long to wait on each phase, and how often to re-send before giving up.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Overrides the per-phase timeouts when set.
+    pub timeout: Option<Duration>,
+    pub retries: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            retries: 2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn timeout(&self, phase: Phase) -> Duration {
+        self.timeout.unwrap_or_else(|| phase.default_timeout())
+    }
+}
+
+/// Tracks the phase currently waiting on the device.
+///
+/// The timer restarts on each reply to the phase (see `Phase::is_reply`), so long
+/// transfers only time out when the device stops answering.
+#[derive(Default)]
+pub struct Watchdog {
+    waiting: Option<(Phase, Option<u8>)>,
+    since: Option<Instant>,
+    attempts: u32,
+}
+
+impl Watchdog {
+    /// Start waiting on `phase`. `slot` is the one-based preset slot involved, if any.
+    pub fn arm(&mut self, phase: Phase, slot: Option<u8>) {
+        self.waiting = Some((phase, slot));
+        self.since = Some(Instant::now());
+        self.attempts = 0;
+    }
+
+    pub fn disarm(&mut self) {
+        self.waiting = None;
+        self.since = None;
+    }
+
+    /// The phase being waited on, if any.
+    pub fn phase(&self) -> Option<Phase> {
+        self.waiting.map(|(phase, _)| phase)
+    }
+
+    pub fn activity(&mut self) {
+        if self.since.is_some() {
+            self.since = Some(Instant::now());
+        }
+    }
+
+    /// Ok(true) when the phase timed out and the request should be sent again.
+    /// Fails with `Error::Timeout` once the retries are used up.
+    pub fn check(&mut self, policy: &RetryPolicy) -> Result<bool> {
+        let (Some((phase, slot)), Some(since)) = (self.waiting, self.since) else {
+            return Ok(false);
+        };
+        if since.elapsed() < policy.timeout(phase) {
+            return Ok(false);
+        }
+        let target = match slot {
+            Some(slot) => format!(" for slot {slot}"),
+            None => String::new(),
+        };
+        if self.attempts >= policy.retries {
+            self.disarm();
+            return Err(Error::Timeout(format!(
+                "Timed out waiting on {phase}{target} after {} attempts",
+                1 + policy.retries
+            )));
+        }
+        self.attempts += 1;
+        self.since = Some(Instant::now());
+        println!(
+            "[Retrying {phase}{target} ({} of {})]",
+            self.attempts, policy.retries
+        );
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn immediate(retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            timeout: Some(Duration::ZERO),
+            retries,
+        }
+    }
+
+    #[test]
+    fn idle_watchdog_never_expires() {
+        let mut watchdog = Watchdog::default();
+        assert_eq!(watchdog.check(&immediate(0)), Ok(false));
+    }
+
+    #[test]
+    fn retries_then_reports_phase_and_slot() {
+        let mut watchdog = Watchdog::default();
+        watchdog.arm(Phase::Upload, Some(12));
+        let policy = immediate(2);
+        assert_eq!(watchdog.check(&policy), Ok(true));
+        assert_eq!(watchdog.check(&policy), Ok(true));
+        let error = watchdog.check(&policy).unwrap_err();
+        assert_eq!(
+            error,
+            Error::Timeout("Timed out waiting on archive upload for slot 12 after 3 attempts".into())
+        );
+        // disarmed after giving up
+        assert_eq!(watchdog.check(&policy), Ok(false));
+    }
+
+    #[test]
+    fn only_replies_to_the_phase_count_as_activity() {
+        let name_char = MidiMessage::channel_pressure(CHANNEL16, b'A');
+        assert!(Phase::PresetNames.is_reply(&name_char));
+        assert!(Phase::Archive.is_reply(&name_char));
+        assert!(!Phase::Upload.is_reply(&name_char));
+        let dsp_done = MidiMessage::cc(
+            CHANNEL16,
+            cc16::DownloadControl,
+            cc16::DownloadControl_DspDone,
+        );
+        assert!(Phase::ClearBank(2).is_reply(&dsp_done));
+        let burst_ack = MidiMessage::cc(CHANNEL16, cc16::EditorReply, 85);
+        assert!(Phase::Upload.is_reply(&burst_ack));
+        assert!(!Phase::ClearBank(2).is_reply(&burst_ack));
+        assert!(!Phase::EditorReply.is_reply(&dsp_done));
+        // a note played on the surface, or a pedal, is not a reply
+        let note = MidiMessage::NoteOn {
+            channel: 0,
+            note: 60,
+            velocity: 90,
+        };
+        assert!(!Phase::Archive.is_reply(&note));
+        assert!(!Phase::PresetNames.is_reply(&MidiMessage::cc(0, 64, 127)));
+    }
+
+    #[test]
+    fn waits_out_the_phase_timeout() {
+        let mut watchdog = Watchdog::default();
+        watchdog.arm(Phase::ClearBank(3), None);
+        assert_eq!(watchdog.check(&RetryPolicy::default()), Ok(false));
+        watchdog.disarm();
+        assert_eq!(watchdog.check(&immediate(0)), Ok(false));
+    }
+}
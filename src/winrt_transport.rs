@@ -0,0 +1,164 @@
+use std::sync::mpsc::Sender;
+
+use crate::{
+    error::Result,
+    midi_message::{MidiMessage, TimedMessage},
+    transport::{Direction, MidiInput, MidiOutput, MidiTransport, PortInfo},
+};
+use windows::{
+    core::*, Devices::Enumeration::*, Devices::Midi::*, Foundation::*, Storage::Streams::*,
+    Win32::Foundation::E_FAIL,
+};
+
+pub struct WinRtTransport {}
+
+fn selector(direction: Direction) -> Option<HSTRING> {
+    match direction {
+        Direction::In => MidiInPort::GetDeviceSelector(),
+        Direction::Out => MidiOutPort::GetDeviceSelector(),
+    }
+    .ok()
+}
+
+fn get_info_collection(direction: Direction) -> Option<DeviceInformationCollection> {
+    if let Some(selector) = selector(direction) {
+        if let Ok(filter) = DeviceInformation::FindAllAsyncAqsFilter(&selector) {
+            if let Ok(devices) = futures::executor::block_on(filter) {
+                return Some(devices);
+            }
+        }
+    }
+    None
+}
+
+fn buffer_bytes(buffer: &IBuffer) -> Result<Vec<u8>> {
+    let reader = DataReader::FromBuffer(buffer)?;
+    let mut bytes = vec![0u8; buffer.Length()? as usize];
+    reader.ReadBytes(bytes.as_mut_slice())?;
+    Ok(bytes)
+}
+
+impl MidiTransport for WinRtTransport {
+    fn ports(&self, direction: Direction) -> Vec<PortInfo> {
+        let mut result = Vec::new();
+        if let Some(devices) = get_info_collection(direction) {
+            for item in devices.into_iter() {
+                if let (Ok(name), Ok(id)) = (item.Name(), item.Id()) {
+                    result.push(PortInfo {
+                        direction,
+                        name: name.to_string_lossy(),
+                        id: id.to_string_lossy(),
+                    });
+                }
+            }
+        }
+        result
+    }
+
+    fn open_input(&self, info: &PortInfo) -> Option<Box<dyn MidiInput>> {
+        debug_assert!(info.direction == Direction::In);
+        if let Ok(future) = MidiInPort::FromIdAsync(&HSTRING::from(&info.id)) {
+            match futures::executor::block_on(future) {
+                Ok(port) => {
+                    return Some(Box::new(WinRtInput { port }));
+                }
+                Err(ref error) => {
+                    if HRESULT(0) == error.code() {
+                        println!(
+                            "Midi in port '{}' is in use ({})",
+                            info.name,
+                            error.message()
+                        );
+                    } else {
+                        println!(
+                            "Error opening Midi in port '{}': {}",
+                            info.name,
+                            error.message()
+                        );
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn open_output(&self, info: &PortInfo) -> Option<Box<dyn MidiOutput>> {
+        debug_assert!(info.direction == Direction::Out);
+        if let Ok(future) = MidiOutPort::FromIdAsync(&HSTRING::from(&info.id)) {
+            match futures::executor::block_on(future) {
+                Ok(iport) => {
+                    if let Ok(port) = iport.cast() {
+                        return Some(Box::new(WinRtOutput { port }));
+                    }
+                }
+                Err(ref error) => {
+                    if HRESULT(0) == error.code() {
+                        println!("Midi out port '{}' is in use", info.name);
+                    } else {
+                        println!(
+                            "Error opening Midi out port '{}': {}",
+                            info.name,
+                            error.message()
+                        );
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+pub struct WinRtInput {
+    port: MidiInPort,
+}
+
+impl MidiInput for WinRtInput {
+    fn listen(&mut self, tx: Sender<TimedMessage>) -> Result<()> {
+        self.port.MessageReceived(&TypedEventHandler::new(
+            move |_, arg: &Option<MidiMessageReceivedEventArgs>| {
+                if let Some(arg) = arg {
+                    let imsg = arg.Message()?;
+                    let data = buffer_bytes(&imsg.RawData()?)
+                        .map_err(|e| Error::new(E_FAIL, HSTRING::from(e.message())))?;
+                    let Some(message) = MidiMessage::from_bytes(&data) else {
+                        return Ok(());
+                    };
+                    let timed = TimedMessage {
+                        ticks: imsg.Timestamp()?.Duration,
+                        message,
+                    };
+                    if let Err(e) = tx.send(timed) {
+                        let message = e.to_string();
+                        println!("Channel tx failed: {message}");
+                        return Err(Error::new(E_FAIL, HSTRING::from(message)));
+                    }
+                };
+                Ok(())
+            },
+        ))?;
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        _ = self.port.Close();
+    }
+}
+
+pub struct WinRtOutput {
+    port: MidiOutPort,
+}
+
+impl MidiOutput for WinRtOutput {
+    fn send(&self, message: &MidiMessage) -> Result<()> {
+        let writer = DataWriter::new()?;
+        writer.WriteBytes(&message.to_bytes())?;
+        self.port.SendBuffer(&writer.DetachBuffer()?)?;
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        if let Err(error) = self.port.Close() {
+            println!("Error closing MIDI out handle: {}", error.message());
+        }
+    }
+}